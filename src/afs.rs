@@ -0,0 +1,6 @@
+mod archive;
+mod bytes;
+mod entry;
+
+pub use archive::AfsArchive;
+pub use entry::AfsEntry;
@@ -0,0 +1,619 @@
+use crate::afs::bytes;
+use crate::afs::bytes::{AfsAttrEntry, AfsAttrTablePtr, AfsHeader, AfsTocEntry};
+use crate::afs::entry::AfsEntry;
+use crate::archive::{Archive, ArchiveEntry, ReadSeek, WriteSeek};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+struct AttrTable {
+    offset: u64,
+    len: u64,
+    // where the pointer to the table is
+    ptr_offset: u64,
+}
+
+#[derive(Debug)]
+pub struct AfsArchive {
+    entries: IndexMap<u32, AfsEntry>,
+    names_to_ids: HashMap<String, u32>,
+    attr_table: Option<AttrTable>,
+}
+
+impl AfsArchive {
+    pub const AFS_SIG: &'static [u8] = b"AFS\0";
+
+    pub fn build<R: Read + Seek>(reader: &mut R) -> Self {
+        let header: AfsHeader = bytes::read_struct(reader);
+        assert_eq!(header.signature, Self::AFS_SIG, "invalid AFS signature");
+
+        let toc = (0..header.entry_count)
+            .map(|_| bytes::read_struct::<AfsTocEntry, _>(reader))
+            .collect::<Vec<_>>();
+
+        // the attribute table pointer normally directly follows the TOC, but some archives put
+        // it right before the first entry's data instead
+        let mut attr_ptr: AfsAttrTablePtr = bytes::read_struct(reader);
+        let mut attr_ptr_offset = Self::toc_end(u64::from(header.entry_count));
+        if attr_ptr.offset == 0 {
+            if let Some(first_offset) = toc
+                .iter()
+                .map(|toc_entry| toc_entry.offset)
+                .find(|&o| o != 0)
+            {
+                attr_ptr_offset = u64::from(first_offset) - 8;
+                reader.seek(SeekFrom::Start(attr_ptr_offset)).unwrap();
+                attr_ptr = bytes::read_struct(reader);
+            }
+        }
+
+        let attr_table = (attr_ptr.offset != 0).then(|| AttrTable {
+            offset: u64::from(attr_ptr.offset),
+            len: u64::from(attr_ptr.len),
+            ptr_offset: attr_ptr_offset,
+        });
+        let attrs = attr_table.as_ref().map(|attr_table| {
+            reader.seek(SeekFrom::Start(attr_table.offset)).unwrap();
+            (0..header.entry_count)
+                .map(|_| bytes::read_struct::<AfsAttrEntry, _>(reader))
+                .collect::<Vec<_>>()
+        });
+
+        let mut entries = IndexMap::with_capacity(toc.len());
+        let mut names_to_ids = HashMap::with_capacity(toc.len());
+        for (id, toc_entry) in (0..).zip(toc) {
+            let attr = attrs.as_ref().map(|attrs| &attrs[id as usize]);
+            let name = attr.map_or_else(
+                || format!("{id:05}.bin"),
+                |attr| bytes::entry_name_from_bytes(&attr.name),
+            );
+
+            let entry = AfsEntry::new(
+                id,
+                name,
+                u64::from(toc_entry.offset),
+                u64::from(toc_entry.len),
+                attr.map(|attr| attr.timestamp),
+            );
+            names_to_ids.insert(entry.name().to_string(), id);
+            entries.insert(id, entry);
+        }
+
+        Self {
+            entries,
+            names_to_ids,
+            attr_table,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AfsEntry> {
+        self.entries.values()
+    }
+
+    #[must_use]
+    pub fn get_entry_by_id(&self, id: u32) -> Option<&AfsEntry> {
+        self.entries.get(&id)
+    }
+
+    #[must_use]
+    pub fn get_entry_by_name(&self, name: &str) -> Option<&AfsEntry> {
+        self.names_to_ids
+            .get(name)
+            .and_then(|id| self.get_entry_by_id(*id))
+    }
+
+    fn entry_count(&self) -> u64 {
+        self.entries.len() as u64
+    }
+
+    const fn toc_end(entry_count: u64) -> u64 {
+        bytes::HEADER_SIZE + entry_count * bytes::TOC_ENTRY_SIZE
+    }
+
+    fn build_repack_map<P: AsRef<Path>>(rpk_paths: &[P]) -> HashMap<String, PathBuf> {
+        rpk_paths
+            .iter()
+            .map(|p| {
+                (
+                    p.as_ref()
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned(),
+                    p.as_ref().to_path_buf(),
+                )
+            })
+            .collect()
+    }
+
+    fn repack_entry<R: Read + Seek, W: Write + Seek>(
+        orig_reader: &mut R,
+        rpk_writer: &mut W,
+        rpk_paths: &HashMap<String, PathBuf>,
+        entry: &AfsEntry,
+    ) -> AfsEntry {
+        let rpk_path = rpk_paths.get(entry.name());
+
+        // unused slots are all 0s and have to stay that way
+        if entry.offset() == 0 && rpk_path.is_none() {
+            return entry.updated(0, 0);
+        }
+
+        let cur_pos = rpk_writer.stream_position().unwrap();
+        crate::bytes::write_padding(rpk_writer, cur_pos, bytes::ALIGNMENT);
+        let new_offset = rpk_writer.stream_position().unwrap();
+
+        let len = if let Some(rpk_path) = rpk_path {
+            let mut rpk_reader = BufReader::new(File::open(rpk_path).unwrap());
+            io::copy(&mut rpk_reader, rpk_writer).expect("failed to copy entry from reader")
+        } else {
+            orig_reader.seek(SeekFrom::Start(entry.offset())).unwrap();
+            io::copy(&mut orig_reader.take(entry.len()), rpk_writer)
+                .expect("failed to copy entry from reader")
+        };
+
+        entry.updated(new_offset, len)
+    }
+
+    // Writes out a whole new archive, laying out entries in ID order starting at `data_start`,
+    // followed by the attribute table if the original had one.
+    fn rebuild<R: Read + Seek, W: Write + Seek>(
+        &self,
+        orig_reader: &mut R,
+        rpk_writer: &mut W,
+        rpk_paths: &HashMap<String, PathBuf>,
+        data_start: u64,
+    ) -> Self {
+        let header = AfsHeader {
+            signature: Self::AFS_SIG.try_into().unwrap(),
+            entry_count: u32::try_from(self.entry_count()).unwrap(),
+        };
+        bytes::write_struct(rpk_writer, header);
+
+        rpk_writer.seek(SeekFrom::Start(data_start)).unwrap();
+        let rpk_entries = self
+            .iter()
+            .map(|entry| {
+                let new_entry = Self::repack_entry(orig_reader, rpk_writer, rpk_paths, entry);
+                (entry.id(), new_entry)
+            })
+            .collect::<IndexMap<_, _>>();
+
+        let attr_table = self.attr_table.as_ref().map(|_| {
+            let cur_pos = rpk_writer.stream_position().unwrap();
+            crate::bytes::write_padding(rpk_writer, cur_pos, bytes::ALIGNMENT);
+            let offset = rpk_writer.stream_position().unwrap();
+            for entry in rpk_entries.values() {
+                bytes::write_struct(rpk_writer, AfsAttrEntry::from(entry));
+            }
+
+            AttrTable {
+                offset,
+                len: self.entry_count() * bytes::ATTR_ENTRY_SIZE,
+                ptr_offset: Self::toc_end(self.entry_count()),
+            }
+        });
+
+        // go back and fill out the TOC
+        rpk_writer
+            .seek(SeekFrom::Start(bytes::HEADER_SIZE))
+            .unwrap();
+        for entry in rpk_entries.values() {
+            bytes::write_struct(rpk_writer, AfsTocEntry::from(entry));
+        }
+        let attr_ptr =
+            attr_table
+                .as_ref()
+                .map_or(AfsAttrTablePtr { offset: 0, len: 0 }, |attr_table| {
+                    AfsAttrTablePtr {
+                        offset: u32::try_from(attr_table.offset).unwrap(),
+                        len: u32::try_from(attr_table.len).unwrap(),
+                    }
+                });
+        bytes::write_struct(rpk_writer, attr_ptr);
+
+        rpk_writer.flush().unwrap();
+
+        Self {
+            entries: rpk_entries,
+            names_to_ids: self.names_to_ids.clone(),
+            attr_table,
+        }
+    }
+
+    #[allow(clippy::return_self_not_must_use)]
+    pub fn repack_entries<R, W, P>(
+        &self,
+        orig_reader: &mut R,
+        rpk_writer: &mut W,
+        rpk_paths: &[P],
+    ) -> Self
+    where
+        R: Read + Seek,
+        W: Write + Seek,
+        P: AsRef<Path>,
+    {
+        let rpk_paths = Self::build_repack_map(rpk_paths);
+
+        // keep the original header area as-is, some games expect the data where it was
+        let data_start = self
+            .iter()
+            .map(AfsEntry::offset)
+            .filter(|&offset| offset != 0)
+            .min()
+            .unwrap_or_else(|| self.header_table_len());
+        self.rebuild(orig_reader, rpk_writer, &rpk_paths, data_start)
+    }
+
+    // The space an entry is allowed to occupy without clobbering its neighbor or the attribute
+    // table. The last thing in the file can grow freely.
+    fn available_space(&self, entry: &AfsEntry) -> Option<u64> {
+        self.iter()
+            .map(AfsEntry::offset)
+            .chain(self.attr_table.as_ref().map(|attr_table| attr_table.offset))
+            .filter(|&offset| offset > entry.offset())
+            .min()
+            .map(|next_offset| next_offset - entry.offset())
+    }
+
+    fn patch_entry<F: Write + Seek>(&mut self, archive: &mut F, entry_id: u32, rpk_path: &Path) {
+        let entry = &self.entries[&entry_id];
+        let rpk_data = std::fs::read(rpk_path).unwrap();
+        let rpk_len = rpk_data.len() as u64;
+
+        let new_offset = if entry.offset() != 0
+            && self
+                .available_space(entry)
+                .is_none_or(|space| rpk_len <= space)
+        {
+            archive.seek(SeekFrom::Start(entry.offset())).unwrap()
+        } else {
+            let end = archive.seek(SeekFrom::End(0)).unwrap();
+            crate::bytes::write_padding(archive, end, bytes::ALIGNMENT);
+            archive.stream_position().unwrap()
+        };
+        archive.write_all(&rpk_data).unwrap();
+
+        let new_entry = entry.updated(new_offset, rpk_len);
+        if new_offset != entry.offset() {
+            self.move_attr_ptr_after_toc(archive);
+        }
+        archive
+            .seek(SeekFrom::Start(
+                bytes::HEADER_SIZE + u64::from(entry_id) * bytes::TOC_ENTRY_SIZE,
+            ))
+            .unwrap();
+        bytes::write_struct(archive, AfsTocEntry::from(&new_entry));
+        if let Some(attr_table) = &self.attr_table {
+            archive
+                .seek(SeekFrom::Start(
+                    attr_table.offset + u64::from(entry_id) * bytes::ATTR_ENTRY_SIZE,
+                ))
+                .unwrap();
+            bytes::write_struct(archive, AfsAttrEntry::from(&new_entry));
+        }
+
+        self.entries.insert(entry_id, new_entry);
+    }
+
+    // A pointer to the attribute table that sits before the first entry's data is only found
+    // as long as that entry stays put, so once entries start moving it goes after the TOC,
+    // which is free since the pointer is never before it.
+    fn move_attr_ptr_after_toc<F: Write + Seek>(&mut self, archive: &mut F) {
+        let toc_end = Self::toc_end(self.entry_count());
+        let Some(attr_table) = self.attr_table.as_mut() else {
+            return;
+        };
+        if attr_table.ptr_offset == toc_end {
+            return;
+        }
+
+        archive.seek(SeekFrom::Start(toc_end)).unwrap();
+        bytes::write_struct(
+            archive,
+            AfsAttrTablePtr {
+                offset: u32::try_from(attr_table.offset).unwrap(),
+                len: u32::try_from(attr_table.len).unwrap(),
+            },
+        );
+        attr_table.ptr_offset = toc_end;
+    }
+
+    /// Replaces entries directly inside `archive` instead of rebuilding it, the same way as
+    /// `MagesArchive::patch_entries()`.
+    pub fn patch_entries<F, P>(&mut self, archive: &mut F, rpk_paths: &[P]) -> Result<(), String>
+    where
+        F: Write + Seek,
+        P: AsRef<Path>,
+    {
+        let rpk_paths = Self::build_repack_map(rpk_paths);
+        if let Some(name) = rpk_paths
+            .keys()
+            .filter(|name| !self.names_to_ids.contains_key(*name))
+            .min()
+        {
+            return Err(format!("no entry named '{name}' in archive"));
+        }
+
+        let replacements: Vec<_> = self
+            .entries
+            .iter()
+            .filter_map(|(&entry_id, entry)| {
+                rpk_paths
+                    .get(entry.name())
+                    .map(|rpk_path| (entry_id, rpk_path))
+            })
+            .collect();
+        for (entry_id, rpk_path) in replacements {
+            self.patch_entry(archive, entry_id, rpk_path);
+        }
+
+        archive.flush().unwrap();
+        Ok(())
+    }
+
+    /// Rewrites the archive with no unused space, keeping only the alignment padding each
+    /// entry requires.
+    #[allow(clippy::return_self_not_must_use)]
+    pub fn compact<R, W>(&self, orig_reader: &mut R, rpk_writer: &mut W) -> Self
+    where
+        R: Read + Seek,
+        W: Write + Seek,
+    {
+        self.rebuild(
+            orig_reader,
+            rpk_writer,
+            &HashMap::new(),
+            self.header_table_len(),
+        )
+    }
+}
+
+impl Archive for AfsArchive {
+    fn description(&self) -> String {
+        let attrs = if self.attr_table.is_some() {
+            "with"
+        } else {
+            "without"
+        };
+        format!("AFS ({attrs} attribute table)")
+    }
+
+    fn entries(&self) -> Vec<&dyn ArchiveEntry> {
+        self.iter()
+            .map(|entry| entry as &dyn ArchiveEntry)
+            .collect()
+    }
+
+    fn open_entry<'r>(
+        &self,
+        reader: &'r mut dyn ReadSeek,
+        entry: &dyn ArchiveEntry,
+    ) -> Box<dyn Read + 'r> {
+        self.entries[&entry.id()].open(reader)
+    }
+
+    fn repack(
+        &self,
+        mut orig_reader: &mut dyn ReadSeek,
+        mut rpk_writer: &mut dyn WriteSeek,
+        rpk_paths: &[PathBuf],
+    ) {
+        self.repack_entries(&mut orig_reader, &mut rpk_writer, rpk_paths);
+    }
+
+    fn patch(
+        &mut self,
+        mut archive: &mut dyn WriteSeek,
+        rpk_paths: &[PathBuf],
+    ) -> Result<(), String> {
+        self.patch_entries(&mut archive, rpk_paths)
+    }
+
+    fn compact(&self, mut orig_reader: &mut dyn ReadSeek, mut writer: &mut dyn WriteSeek) {
+        Self::compact(self, &mut orig_reader, &mut writer);
+    }
+
+    fn sort_by_id(&mut self) {
+        // entries can only ever be in ID order, see sort_by_name()
+    }
+
+    fn sort_by_name(&mut self) {
+        panic!("AFS entries are referenced by their position, so they can't be reordered");
+    }
+
+    fn supports_sort_by_name(&self) -> bool {
+        false
+    }
+
+    fn header_table_len(&self) -> u64 {
+        // header + TOC + attribute table pointer
+        Self::toc_end(self.entry_count()) + 8
+    }
+
+    fn alignment(&self) -> u64 {
+        bytes::ALIGNMENT
+    }
+
+    fn reserved_spans(&self) -> Vec<(u64, u64)> {
+        self.attr_table
+            .iter()
+            .map(|attr_table| (attr_table.offset, attr_table.offset + attr_table.len))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Attrs {
+        None,
+        AfterToc,
+        BeforeData,
+    }
+
+    fn put_u32(afs: &mut [u8], pos: usize, value: usize) {
+        afs[pos..pos + 4].copy_from_slice(&u32::try_from(value).unwrap().to_le_bytes());
+    }
+
+    // an AFS holding `entries`, each a name and contents, where empty contents make an unused
+    // slot, with its attribute table pointed at from where `attrs` says
+    fn build_afs(entries: &[(&str, &[u8])], attrs: Attrs) -> Vec<u8> {
+        let mut afs = vec![0; 16 + entries.len() * 8];
+        afs[..4].copy_from_slice(b"AFS\0");
+        put_u32(&mut afs, 4, entries.len());
+
+        let mut first_offset = None;
+        for (i, (_, data)) in entries.iter().enumerate() {
+            if data.is_empty() {
+                continue;
+            }
+            let offset = afs.len().next_multiple_of(2048);
+            first_offset.get_or_insert(offset);
+            afs.resize(offset, 0);
+            afs.extend(*data);
+            put_u32(&mut afs, 8 + i * 8, offset);
+            put_u32(&mut afs, 12 + i * 8, data.len());
+        }
+
+        let attr_offset = afs.len().next_multiple_of(2048);
+        let attr_ptr = match attrs {
+            Attrs::None => return afs,
+            Attrs::AfterToc => 8 + entries.len() * 8,
+            Attrs::BeforeData => first_offset.unwrap() - 8,
+        };
+        put_u32(&mut afs, attr_ptr, attr_offset);
+        put_u32(&mut afs, attr_ptr + 4, entries.len() * 0x30);
+        afs.resize(attr_offset, 0);
+        for (name, data) in entries {
+            let mut attr = [0; 0x30];
+            attr[..name.len()].copy_from_slice(name.as_bytes());
+            attr[32..34].copy_from_slice(&2009_u16.to_le_bytes());
+            put_u32(&mut attr, 44, data.len());
+            afs.extend(attr);
+        }
+        afs
+    }
+
+    fn contents(afs: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut reader = Cursor::new(afs);
+        let archive = AfsArchive::build(&mut reader);
+        archive
+            .iter()
+            .map(|entry| {
+                let mut data = Vec::new();
+                entry.open(&mut reader).read_to_end(&mut data).unwrap();
+                (entry.name().to_string(), data)
+            })
+            .collect()
+    }
+
+    fn owned(entries: &[(&str, &[u8])]) -> Vec<(String, Vec<u8>)> {
+        entries
+            .iter()
+            .map(|(name, data)| ((*name).to_string(), data.to_vec()))
+            .collect()
+    }
+
+    const ENTRIES: &[(&str, &[u8])] = &[
+        ("v_001.ogg", b"voice one"),
+        ("unused", b""),
+        ("v_002.ogg", b"voice two"),
+    ];
+
+    fn replacement(dir: &tempfile::TempDir, name: &str, data: &[u8]) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_entries_wherever_the_attribute_table_is() {
+        for attrs in [Attrs::AfterToc, Attrs::BeforeData] {
+            let afs = build_afs(ENTRIES, attrs);
+            assert_eq!(contents(&afs), owned(ENTRIES));
+            let archive = AfsArchive::build(&mut Cursor::new(&afs));
+            let timestamp = archive.iter().next().unwrap().timestamp().unwrap();
+            assert_eq!(timestamp.year, 2009);
+        }
+
+        let afs = build_afs(ENTRIES, Attrs::None);
+        let names = contents(&afs)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["00000.bin", "00001.bin", "00002.bin"]);
+    }
+
+    #[test]
+    fn repack_patch_and_compact_keep_slots_and_attributes() {
+        let dir = tempfile::tempdir().unwrap();
+        let long_voice = vec![b'v'; 3000];
+        let rpk_paths = [
+            replacement(&dir, "v_001.ogg", &long_voice),
+            replacement(&dir, "v_002.ogg", b"VOICE TWO"),
+        ];
+        let mut expected = owned(ENTRIES);
+        expected[0].1.clone_from(&long_voice);
+        expected[2].1 = b"VOICE TWO".to_vec();
+
+        for attrs in [Attrs::AfterToc, Attrs::BeforeData, Attrs::None] {
+            let afs = build_afs(ENTRIES, attrs);
+            let names = |afs: &[u8]| {
+                contents(afs)
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>()
+            };
+            let mut expected = expected.clone();
+            for (entry, name) in expected.iter_mut().zip(names(&afs)) {
+                entry.0 = name;
+            }
+            let rpk_paths = if attrs == Attrs::None {
+                vec![
+                    replacement(&dir, "00000.bin", &long_voice),
+                    replacement(&dir, "00002.bin", b"VOICE TWO"),
+                ]
+            } else {
+                rpk_paths.to_vec()
+            };
+
+            let archive = AfsArchive::build(&mut Cursor::new(&afs));
+            let mut repacked = Cursor::new(Vec::new());
+            archive.repack_entries(&mut Cursor::new(&afs), &mut repacked, &rpk_paths);
+            let repacked = repacked.into_inner();
+            assert_eq!(contents(&repacked), expected);
+
+            let mut archive = AfsArchive::build(&mut Cursor::new(&afs));
+            let mut patched = Cursor::new(afs.clone());
+            archive.patch_entries(&mut patched, &rpk_paths).unwrap();
+            let patched = patched.into_inner();
+            assert_eq!(contents(&patched), expected);
+
+            let archive = AfsArchive::build(&mut Cursor::new(&patched));
+            let mut compacted = Cursor::new(Vec::new());
+            archive.compact(&mut Cursor::new(&patched), &mut compacted);
+            let compacted = compacted.into_inner();
+            assert_eq!(contents(&compacted), expected);
+
+            // the unused slot stays unused
+            for afs in [&repacked, &patched, &compacted] {
+                let archive = AfsArchive::build(&mut Cursor::new(afs));
+                assert_eq!(archive.get_entry_by_id(1).unwrap().offset(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn entries_cant_be_sorted_by_name() {
+        let archive = AfsArchive::build(&mut Cursor::new(build_afs(ENTRIES, Attrs::AfterToc)));
+        assert!(!archive.supports_sort_by_name());
+    }
+}
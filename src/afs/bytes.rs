@@ -0,0 +1,79 @@
+use crate::afs::AfsEntry;
+use bincode::{Decode, Encode};
+
+pub use crate::bytes::{read_struct, write_struct};
+
+#[derive(Debug, Decode, Encode)]
+pub(super) struct AfsHeader {
+    pub signature: [u8; 4],
+    pub entry_count: u32,
+}
+
+#[derive(Debug, Decode, Encode)]
+pub(super) struct AfsTocEntry {
+    pub offset: u32,
+    pub len: u32,
+}
+
+// follows the TOC (or occasionally sits just before the first entry's data) and points at the
+// optional attribute table
+#[derive(Debug, Decode, Encode)]
+pub(super) struct AfsAttrTablePtr {
+    pub offset: u32,
+    pub len: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, Decode, Encode)]
+pub struct AfsTimestamp {
+    pub year: u16,
+    pub month: u16,
+    pub day: u16,
+    pub hour: u16,
+    pub minute: u16,
+    pub second: u16,
+}
+
+#[derive(Debug, Decode, Encode)]
+pub(super) struct AfsAttrEntry {
+    pub name: [u8; 32],
+    pub timestamp: AfsTimestamp,
+    pub len: u32,
+}
+
+pub const HEADER_SIZE: u64 = 8;
+pub const TOC_ENTRY_SIZE: u64 = 8;
+pub const ATTR_ENTRY_SIZE: u64 = 0x30;
+pub const ALIGNMENT: u64 = 2048;
+
+pub fn entry_name_from_bytes(name: &[u8]) -> String {
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+impl From<&AfsEntry> for AfsTocEntry {
+    fn from(entry: &AfsEntry) -> Self {
+        Self {
+            offset: u32::try_from(entry.offset()).expect("AFS entry offset too large for u32"),
+            len: u32::try_from(entry.len()).expect("AFS entry size too large for u32"),
+        }
+    }
+}
+
+impl From<&AfsEntry> for AfsAttrEntry {
+    fn from(entry: &AfsEntry) -> Self {
+        let mut name = [0u8; 32];
+        let name_bytes = entry.name().as_bytes();
+        assert!(
+            name_bytes.len() <= name.len(),
+            "AFS entry name '{}' is longer than 32 bytes",
+            entry.name()
+        );
+        name[..name_bytes.len()].copy_from_slice(name_bytes);
+
+        Self {
+            name,
+            timestamp: entry.timestamp().unwrap_or_default(),
+            len: u32::try_from(entry.len()).expect("AFS entry size too large for u32"),
+        }
+    }
+}
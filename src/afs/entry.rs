@@ -0,0 +1,112 @@
+use crate::afs::bytes::AfsTimestamp;
+use crate::archive::ArchiveEntry;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+#[derive(Debug)]
+pub struct AfsEntry {
+    id: u32,
+    name: String,
+    offset: u64,
+    len: u64,
+    timestamp: Option<AfsTimestamp>,
+}
+
+impl AfsEntry {
+    pub(super) const fn new(
+        id: u32,
+        name: String,
+        offset: u64,
+        len: u64,
+        timestamp: Option<AfsTimestamp>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            offset,
+            len,
+            timestamp,
+        }
+    }
+
+    #[must_use]
+    pub const fn id(&self) -> u32 {
+        self.id
+    }
+
+    #[allow(clippy::missing_const_for_fn)] // compilation error if it's made const
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub const fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The modification time recorded in the attribute table, if the archive has one.
+    #[must_use]
+    pub const fn timestamp(&self) -> Option<AfsTimestamp> {
+        self.timestamp
+    }
+
+    /// Opens a reader over this entry's contents. AFS never compresses entries, so this is just
+    /// a window into the archive.
+    pub fn open<'r, R: Read + Seek + ?Sized>(&self, reader: &'r mut R) -> Box<dyn Read + 'r> {
+        reader.seek(SeekFrom::Start(self.offset)).unwrap();
+        Box::new(reader.take(self.len))
+    }
+
+    pub fn extract<R: Read, W: Write>(&self, reader: &mut R, writer: &mut W) {
+        let mut reader = reader.take(self.len);
+        io::copy(&mut reader, writer).expect("failed to copy entry from reader");
+    }
+
+    #[must_use]
+    pub fn updated(&self, offset: u64, len: u64) -> Self {
+        Self {
+            id: self.id,
+            name: self.name.clone(),
+            offset,
+            len,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+impl ArchiveEntry for AfsEntry {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn len_compressed(&self) -> u64 {
+        self.len
+    }
+
+    fn len_deflated(&self) -> u64 {
+        self.len
+    }
+
+    fn is_compressed(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,457 @@
+use crate::afs::AfsArchive;
+use crate::cpk::CpkArchive;
+use crate::mpk::{Codecs, EntryLayout, MagesArchive, NameEncoding};
+use crate::npa::NpaArchive;
+use crate::select::{EntrySelector, Selection};
+use crate::sniff::FileType;
+use bytesize::ByteSize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+pub trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+pub trait WriteSeek: Write + Seek {}
+impl<T: Write + Seek> WriteSeek for T {}
+
+/// The common view of an entry that every archive format provides.
+pub trait ArchiveEntry: fmt::Debug {
+    fn id(&self) -> u32;
+    fn name(&self) -> &str;
+    /// Where the entry's (possibly compressed) data starts in the archive.
+    fn offset(&self) -> u64;
+    /// How many bytes the entry takes up in the archive.
+    fn len_compressed(&self) -> u64;
+    /// How many bytes the entry takes up once extracted.
+    fn len_deflated(&self) -> u64;
+    fn is_compressed(&self) -> bool;
+}
+
+/// An archive format that ungelify can list, extract, and repack.
+///
+/// Everything takes `&mut dyn ReadSeek`/`&mut dyn WriteSeek` rather than generics so that
+/// backends can be picked at runtime by [`open_archive()`].
+pub trait Archive {
+    /// A short human-readable description of the format and version, e.g. `MPK v2.0`.
+    fn description(&self) -> String;
+
+    /// All entries in the order they appear in the archive's entry table.
+    fn entries(&self) -> Vec<&dyn ArchiveEntry>;
+
+    /// Opens a reader over the extracted contents of `entry`, handling any seeking and
+    /// decompression.
+    fn open_entry<'r>(
+        &self,
+        reader: &'r mut dyn ReadSeek,
+        entry: &dyn ArchiveEntry,
+    ) -> Box<dyn Read + 'r>;
+
+    /// Writes a new archive to `rpk_writer`, replacing the entries `rpk_paths` match (see
+    /// [`match_replacements()`]) with those files' contents.
+    fn repack(
+        &self,
+        orig_reader: &mut dyn ReadSeek,
+        rpk_writer: &mut dyn WriteSeek,
+        rpk_paths: &[PathBuf],
+    );
+
+    /// Replaces entries directly inside an existing archive rather than writing a new one.
+    ///
+    /// # Errors
+    ///
+    /// Fails without touching `archive` if a file in `rpk_paths` doesn't match any entry.
+    fn patch(&mut self, archive: &mut dyn WriteSeek, rpk_paths: &[PathBuf]) -> Result<(), String>;
+
+    /// Writes a new archive to `writer` without any unused space between entries.
+    fn compact(&self, orig_reader: &mut dyn ReadSeek, writer: &mut dyn WriteSeek);
+
+    /// Reorders the entries by ID. Takes effect when the archive is next written out.
+    fn sort_by_id(&mut self);
+
+    /// Reorders the entries by name. Takes effect when the archive is next written out.
+    ///
+    /// Only call this if [`supports_sort_by_name`](Self::supports_sort_by_name) says so.
+    fn sort_by_name(&mut self);
+
+    /// Whether the format allows entries to be reordered by name.
+    fn supports_sort_by_name(&self) -> bool {
+        true
+    }
+
+    /// How many bytes at the start of the archive are taken up by its header and entry table.
+    fn header_table_len(&self) -> u64;
+
+    /// The boundary that entry data is aligned on.
+    fn alignment(&self) -> u64;
+
+    /// Spans of the archive (as `(start, end)` offsets) that hold something other than the header
+    /// table or entry data, e.g. a trailing name table.
+    fn reserved_spans(&self) -> Vec<(u64, u64)> {
+        Vec::new()
+    }
+
+    /// The entry count the archive claims to have, which doesn't always match reality.
+    fn reported_entry_count(&self) -> u64 {
+        self.entries().len() as u64
+    }
+
+    fn extract_entry(
+        &self,
+        reader: &mut dyn ReadSeek,
+        entry: &dyn ArchiveEntry,
+        writer: &mut dyn Write,
+    ) {
+        let mut entry_reader = self.open_entry(reader, entry);
+        io::copy(&mut entry_reader, writer).expect("failed to copy entry from reader");
+    }
+
+    /// Detects what kind of file `entry` holds by looking at the start of its contents.
+    fn sniff_entry(&self, reader: &mut dyn ReadSeek, entry: &dyn ArchiveEntry) -> FileType {
+        let mut head = Vec::with_capacity(FileType::SNIFF_LEN);
+        self.open_entry(reader, entry)
+            .take(FileType::SNIFF_LEN as u64)
+            .read_to_end(&mut head)
+            .expect("failed to read entry from reader");
+
+        FileType::sniff(&head, entry.len_deflated())
+    }
+
+    /// Runs `selector` over this archive's entries. `reader` is only used if the selector needs
+    /// to sniff entry contents.
+    fn select<'a>(&'a self, reader: &mut dyn ReadSeek, selector: &EntrySelector) -> Selection<'a> {
+        selector.select(self.entries(), |entry| self.sniff_entry(reader, entry))
+    }
+
+    #[allow(clippy::print_literal)] // readability >>>
+    fn list_selection(&self, reader: &mut dyn ReadSeek, selection: &Selection<'_>) {
+        println!(
+            "{:<5} {:<20} {:<12} {:<10} {}",
+            "ID", "Name", "Size", "Offset", "Type"
+        );
+        println!("============================================================");
+
+        let mut mismatches = Vec::new();
+        for &entry in &selection.entries {
+            let cpr_suffix = if entry.is_compressed() { "*" } else { "" };
+            let file_type = self.sniff_entry(reader, entry);
+            let mismatch_suffix = if file_type.agrees_with(entry.name()) {
+                ""
+            } else {
+                mismatches.push((entry, file_type));
+                "!"
+            };
+            println!(
+                "{:<5} {:<20} {:<12} {:<10} {file_type}{mismatch_suffix}",
+                entry.id(),
+                entry.name(),
+                format!("{}{cpr_suffix}", ByteSize::b(entry.len_deflated())),
+                format!("0x{:x}", entry.offset()),
+            );
+        }
+
+        for (entry, file_type) in mismatches {
+            eprintln!(
+                "warning: entry {} '{}' has contents of type '{file_type}'",
+                entry.id(),
+                entry.name()
+            );
+        }
+    }
+
+    fn list_entries(&self, reader: &mut dyn ReadSeek) {
+        let selection = self.select(reader, &EntrySelector::all());
+        self.list_selection(reader, &selection);
+    }
+
+    fn extract_selection(
+        &self,
+        reader: &mut dyn ReadSeek,
+        output_dir: &Path,
+        selection: &Selection<'_>,
+    ) {
+        for &entry in &selection.entries {
+            // some formats keep entries in subdirectories
+            let extract_path = output_dir.join(entry.name());
+            if let Some(parent) = extract_path.parent() {
+                std::fs::create_dir_all(parent).unwrap();
+            }
+            let mut writer = BufWriter::new(File::create(&extract_path).unwrap());
+            self.extract_entry(reader, entry, &mut writer);
+        }
+    }
+
+    /// Every span of the archive that holds entry data or reserved data, sorted by offset.
+    fn occupied_spans(&self) -> Vec<(u64, u64)> {
+        let mut spans = self
+            .entries()
+            .into_iter()
+            .map(|entry| (entry.offset(), entry.offset() + entry.len_compressed()))
+            .chain(self.reserved_spans())
+            .collect::<Vec<_>>();
+        spans.sort_unstable();
+        spans
+    }
+
+    /// Computes how many bytes of `archive_len` aren't needed to hold the archive's entries.
+    ///
+    /// This counts any gaps between the header table and the first entry, gaps between entries
+    /// beyond the alignment each entry needs, and anything trailing the last entry.
+    fn unused_space(&self, archive_len: u64) -> u64 {
+        let spans = self.occupied_spans();
+
+        let mut unused = 0;
+        let mut prev_end = self.header_table_len();
+        for (offset, end) in spans {
+            unused += offset.saturating_sub(prev_end.next_multiple_of(self.alignment()));
+            prev_end = prev_end.max(end);
+        }
+
+        unused + archive_len.saturating_sub(prev_end)
+    }
+
+    /// The counterpart of `unused_space()`, counting only the gaps that alignment requires.
+    fn padding(&self) -> u64 {
+        let spans = self.occupied_spans();
+
+        let mut padding = 0;
+        let mut prev_end = self.header_table_len();
+        for (offset, end) in spans {
+            padding += offset
+                .min(prev_end.next_multiple_of(self.alignment()))
+                .saturating_sub(prev_end);
+            prev_end = prev_end.max(end);
+        }
+
+        padding
+    }
+}
+
+/// Settings that some formats need in order to open an archive.
+#[derive(Debug, Default)]
+pub struct OpenOptions {
+    /// The key for encrypted NPA archives: a built-in key's name or a key file, see
+    /// [`NpaKey::for_game()`](crate::npa::NpaKey::for_game).
+    pub npa_key: Option<String>,
+    /// Overrides the entry table layout of MPK archives, for versions ungelify doesn't know.
+    pub mpk_layout: Option<EntryLayout>,
+    /// How MPK entry names are encoded.
+    pub mpk_name_encoding: NameEncoding,
+    /// The codecs MPK entry data is decoded and encoded with.
+    pub codecs: Codecs,
+}
+
+/// An archive format known to ungelify, along with how to recognize and open it.
+pub struct ArchiveFormat {
+    pub name: &'static str,
+    /// Checks whether the first `MAGIC_LEN` bytes of a file look like this format.
+    pub is_match: fn(&[u8]) -> bool,
+    pub open: fn(&mut dyn ReadSeek, &OpenOptions) -> Box<dyn Archive>,
+}
+
+/// How many bytes from the start of a file are handed to `ArchiveFormat::is_match`.
+pub const MAGIC_LEN: usize = 16;
+
+pub static FORMATS: &[ArchiveFormat] = &[
+    ArchiveFormat {
+        name: "mpk",
+        is_match: |head| head.starts_with(MagesArchive::MPK_SIG),
+        open: |mut reader, options| {
+            let mut archive = MagesArchive::build_with(
+                &mut reader,
+                options.mpk_layout.as_ref(),
+                options.mpk_name_encoding,
+            );
+            archive.set_codecs(&options.codecs);
+            Box::new(archive)
+        },
+    },
+    ArchiveFormat {
+        name: "afs",
+        is_match: |head| head.starts_with(AfsArchive::AFS_SIG),
+        open: |mut reader, _| Box::new(AfsArchive::build(&mut reader)),
+    },
+    ArchiveFormat {
+        name: "cpk",
+        is_match: |head| head.starts_with(CpkArchive::CPK_SIG),
+        open: |mut reader, _| Box::new(CpkArchive::build(&mut reader)),
+    },
+    ArchiveFormat {
+        name: "npa",
+        is_match: |head| head.starts_with(NpaArchive::NPA_SIG),
+        open: |mut reader, options| {
+            Box::new(NpaArchive::build_for_key(
+                &mut reader,
+                options.npa_key.as_deref(),
+            ))
+        },
+    },
+];
+
+/// Figures out which format the archive in `reader` is by its magic bytes. The reader is left
+/// at the start of the archive.
+pub fn detect_format(reader: &mut dyn ReadSeek) -> Option<&'static ArchiveFormat> {
+    let mut head = Vec::with_capacity(MAGIC_LEN);
+    reader.seek(SeekFrom::Start(0)).unwrap();
+    reader
+        .take(MAGIC_LEN as u64)
+        .read_to_end(&mut head)
+        .expect("failed to read archive header");
+    reader.seek(SeekFrom::Start(0)).unwrap();
+
+    FORMATS.iter().find(|format| (format.is_match)(&head))
+}
+
+/// Opens the archive in `reader` with whichever backend recognizes it.
+pub fn open_archive(reader: &mut dyn ReadSeek) -> Box<dyn Archive> {
+    open_archive_with(reader, &OpenOptions::default())
+}
+
+/// Like [`open_archive()`], but with settings for formats that need them.
+pub fn open_archive_with(reader: &mut dyn ReadSeek, options: &OpenOptions) -> Box<dyn Archive> {
+    let format = detect_format(reader).expect("unrecognized archive format");
+    (format.open)(reader, options)
+}
+
+/// Which entry each of `rpk_paths` replaces, if any.
+///
+/// An entry's whole name has to match the end of the file's path, so `out/script/main.scx`
+/// replaces `script/main.scx` but not `system/main.scx`, and relative paths count the
+/// directories they're in. If several entries match, the one with the longest name wins.
+#[must_use]
+pub fn match_replacements<'n, P: AsRef<Path>>(
+    names: impl IntoIterator<Item = &'n str>,
+    rpk_paths: &[P],
+) -> Vec<Option<&'n str>> {
+    let mut names_by_file_name = HashMap::<_, Vec<_>>::new();
+    for name in names {
+        let file_name = name.rsplit('/').next().unwrap_or(name);
+        names_by_file_name.entry(file_name).or_default().push(name);
+    }
+
+    rpk_paths
+        .iter()
+        .map(|path| {
+            let path = std::path::absolute(path.as_ref()).unwrap();
+            let mut components = Vec::new();
+            for component in path.components() {
+                match component {
+                    Component::Normal(component) => components.push(component.to_string_lossy()),
+                    Component::ParentDir => {
+                        components.pop();
+                    }
+                    _ => {}
+                }
+            }
+
+            names_by_file_name
+                .get(&*components.pop()?)?
+                .iter()
+                .copied()
+                .filter(|name| {
+                    // the file names already match, so compare the directories above them
+                    let mut path_dirs = components.iter().rev();
+                    name.split('/')
+                        .filter(|part| !part.is_empty())
+                        .rev()
+                        .skip(1)
+                        .all(|dir| path_dirs.next().is_some_and(|path_dir| path_dir == dir))
+                })
+                .max_by_key(|name| name.len())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpk::{build_mpk, Endian};
+    use std::io::Cursor;
+
+    #[test]
+    fn replacements_match_whole_names() {
+        let names = [
+            "main.scx",
+            "script/main.scx",
+            "system/main.scx",
+            "bg/001.png",
+        ];
+        let rpk_paths = [
+            "/out/script/main.scx",
+            "/out/main.scx",
+            "/out/system/../bg/001.png",
+            "/out/chara/001.png",
+            "/out/other.scx",
+        ];
+        assert_eq!(
+            match_replacements(names, &rpk_paths),
+            [
+                Some("script/main.scx"),
+                Some("main.scx"),
+                Some("bg/001.png"),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_replacement_paths_include_the_current_directory() {
+        let cwd = std::env::current_dir().unwrap();
+        let dir_name = cwd.file_name().unwrap().to_string_lossy();
+        let name = format!("{dir_name}/entry.bin");
+        assert_eq!(
+            match_replacements([name.as_str()], &["entry.bin"]),
+            [Some(name.as_str())]
+        );
+    }
+
+    const ENTRIES: &[(&str, &[u8], u32)] =
+        &[("a.txt", b"alpha", 0), ("b.png", b"\x89PNG\r\n\x1a\n", 0)];
+
+    #[test]
+    fn formats_are_detected_by_magic() {
+        let mut reader = Cursor::new(build_mpk((2, 0), Endian::Little, ENTRIES, 0));
+        reader.set_position(0x100);
+        assert_eq!(detect_format(&mut reader).unwrap().name, "mpk");
+        assert_eq!(reader.position(), 0);
+
+        let mut reader = Cursor::new(b"PK\x03\x04 not an archive we know".to_vec());
+        assert!(detect_format(&mut reader).is_none());
+    }
+
+    #[test]
+    fn archives_are_read_through_the_trait() {
+        let mpk = build_mpk((2, 0), Endian::Little, ENTRIES, 0);
+        let mut reader = Cursor::new(&mpk);
+        let archive = open_archive(&mut reader);
+        assert_eq!(archive.description(), "MPK v2.0");
+        assert_eq!(archive.reported_entry_count(), 2);
+        assert_eq!(archive.header_table_len(), 0x240);
+
+        let contents = archive
+            .entries()
+            .into_iter()
+            .map(|entry| {
+                let mut data = Vec::new();
+                archive.extract_entry(&mut reader, entry, &mut data);
+                (entry.name(), data, archive.sniff_entry(&mut reader, entry))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            contents,
+            [
+                ("a.txt", b"alpha".to_vec(), FileType::Text),
+                ("b.png", b"\x89PNG\r\n\x1a\n".to_vec(), FileType::Png),
+            ]
+        );
+
+        // the entries start on 2048-byte boundaries right after each other
+        assert_eq!(archive.padding(), (0x800 - 0x240) + (0x1000 - 0x805));
+        assert_eq!(archive.unused_space(mpk.len() as u64), 0);
+        assert_eq!(archive.unused_space(mpk.len() as u64 + 10), 10);
+    }
+}
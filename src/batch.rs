@@ -0,0 +1,180 @@
+// Working on a whole game install at once. Every archive ungelify recognizes under the install is
+// extracted to (or patched from) a tree mirroring it, where the archive `data/script.mpk` goes
+// with the directory `data/script/`, named like a single extraction would be.
+
+use crate::archive::detect_format;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Every archive under `dir`, in path order, relative to it. Backups (`.orig`) are skipped, and
+/// so is everything under `skip`, e.g. an output directory inside the install.
+#[must_use]
+pub fn find_archives(dir: &Path, skip: Option<&Path>) -> Vec<PathBuf> {
+    let mut archives = Vec::new();
+    find_archives_in(dir, dir, skip, &mut archives);
+    archives.sort();
+    archives
+}
+
+fn find_archives_in(root: &Path, dir: &Path, skip: Option<&Path>, archives: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if skip.is_some_and(|skip| path == skip) {
+            continue;
+        }
+        if path.is_dir() {
+            find_archives_in(root, &path, skip, archives);
+        } else if path.extension().is_none_or(|ext| ext != "orig") && is_archive(&path) {
+            archives.push(path.strip_prefix(root).unwrap().to_path_buf());
+        }
+    }
+}
+
+fn is_archive(path: &Path) -> bool {
+    File::open(path).is_ok_and(|file| detect_format(&mut BufReader::new(file)).is_some())
+}
+
+/// Every file under `dir`, in path order.
+#[must_use]
+pub fn files_in(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(files_in(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files
+}
+
+/// What happened to each archive of a batch.
+#[derive(Debug)]
+pub struct Report {
+    /// What was done to the entries, e.g. "extracted".
+    pub action: &'static str,
+    /// Each archive, with how many entries the action was applied to or why it failed.
+    pub archives: Vec<(PathBuf, Result<usize, String>)>,
+}
+
+impl Report {
+    #[must_use]
+    pub const fn new(action: &'static str) -> Self {
+        Self {
+            action,
+            archives: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn failures(&self) -> usize {
+        self.archives
+            .iter()
+            .filter(|(_, result)| result.is_err())
+            .count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .archives
+            .iter()
+            .map(|(path, _)| path.display().to_string().len())
+            .max()
+            .unwrap_or(0);
+        let mut entries = 0;
+        for (path, result) in &self.archives {
+            let path = path.display().to_string();
+            match result {
+                Ok(count) => {
+                    entries += count;
+                    writeln!(f, "{path:<width$}  {} {count} entries", self.action)?;
+                }
+                Err(e) => writeln!(f, "{path:<width$}  failed: {e}")?,
+            }
+        }
+        write!(
+            f,
+            "{} archives, {} failed, {} {entries} entries",
+            self.archives.len(),
+            self.failures(),
+            self.action
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, data: &[u8]) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn finds_archives_by_their_contents() {
+        let install = tempfile::tempdir().unwrap();
+        let root = install.path();
+        write(root, "script.mpk", b"MPK\0");
+        write(root, "data/sound.afs", b"AFS\0");
+        write(root, "data/movie.bin", b"CPK ");
+        write(root, "data/readme.mpk", b"not an archive");
+        write(root, "script.mpk.orig", b"MPK\0");
+        write(root, "out/script/a.mpk", b"MPK\0");
+
+        assert_eq!(
+            find_archives(root, None),
+            [
+                "data/movie.bin",
+                "data/sound.afs",
+                "out/script/a.mpk",
+                "script.mpk"
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(
+            find_archives(root, Some(&root.join("out"))),
+            ["data/movie.bin", "data/sound.afs", "script.mpk"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn lists_every_file_in_path_order() {
+        let dir = tempfile::tempdir().unwrap();
+        for path in ["b.txt", "a/z.txt", "a/b/c.txt"] {
+            write(dir.path(), path, b"");
+        }
+        assert_eq!(
+            files_in(dir.path()),
+            ["a/b/c.txt", "a/z.txt", "b.txt"].map(|path| dir.path().join(path))
+        );
+    }
+
+    #[test]
+    fn reports_count_entries_and_failures() {
+        let mut report = Report::new("extracted");
+        report.archives.push((PathBuf::from("script.mpk"), Ok(3)));
+        report
+            .archives
+            .push((PathBuf::from("data/bg.mpk"), Err("bad header".to_string())));
+        report.archives.push((PathBuf::from("chara.mpk"), Ok(4)));
+
+        assert_eq!(report.failures(), 1);
+        assert_eq!(
+            report.to_string(),
+            "\
+script.mpk   extracted 3 entries
+data/bg.mpk  failed: bad header
+chara.mpk    extracted 4 entries
+3 archives, 1 failed, extracted 7 entries"
+        );
+    }
+}
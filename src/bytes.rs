@@ -0,0 +1,84 @@
+use bincode::config::{BigEndian, Configuration as BincodeConfig, Fixint, LittleEndian};
+use bincode::{Decode, Encode};
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+// every format we support so far uses fixed-size integers, and nearly all of them are
+// little-endian
+type Config = BincodeConfig<LittleEndian, Fixint>;
+type BigConfig = BincodeConfig<BigEndian, Fixint>;
+
+const BINCODE_CONFIG: Config = bincode::config::standard()
+    .with_little_endian()
+    .with_fixed_int_encoding();
+
+const BIG_BINCODE_CONFIG: BigConfig = bincode::config::standard()
+    .with_big_endian()
+    .with_fixed_int_encoding();
+
+/// The byte order of an archive's headers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Little,
+    /// Used by some console ports.
+    Big,
+}
+
+impl fmt::Display for Endian {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Little => "little",
+            Self::Big => "big",
+        })
+    }
+}
+
+impl FromStr for Endian {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "little" | "le" => Ok(Self::Little),
+            "big" | "be" => Ok(Self::Big),
+            _ => Err(format!(
+                "unknown byte order '{s}', expected 'little' or 'big'"
+            )),
+        }
+    }
+}
+
+pub fn read_struct<D: Decode<()>, R: Read>(reader: &mut R) -> D {
+    bincode::decode_from_std_read::<D, Config, R>(reader, BINCODE_CONFIG).expect("failed to decode")
+}
+
+pub fn write_struct<E: Encode, W: Write>(writer: &mut W, val: E) {
+    bincode::encode_into_std_write::<E, Config, W>(val, writer, BINCODE_CONFIG)
+        .expect("failed to encode");
+}
+
+pub fn read_struct_as<D: Decode<()>, R: Read>(reader: &mut R, endian: Endian) -> D {
+    match endian {
+        Endian::Little => read_struct(reader),
+        Endian::Big => bincode::decode_from_std_read::<D, BigConfig, R>(reader, BIG_BINCODE_CONFIG)
+            .expect("failed to decode"),
+    }
+}
+
+pub fn write_struct_as<E: Encode, W: Write>(writer: &mut W, val: E, endian: Endian) {
+    match endian {
+        Endian::Little => write_struct(writer, val),
+        Endian::Big => {
+            bincode::encode_into_std_write::<E, BigConfig, W>(val, writer, BIG_BINCODE_CONFIG)
+                .expect("failed to encode");
+        }
+    }
+}
+
+// pads with zeros from `pos` up to the next multiple of `alignment`
+pub fn write_padding<W: Write>(writer: &mut W, pos: u64, alignment: u64) {
+    let padding_len = pos.next_multiple_of(alignment) - pos;
+    io::copy(&mut io::repeat(0).take(padding_len), writer).unwrap();
+}
@@ -1,10 +1,36 @@
-use clap::{Parser, Subcommand};
+use bytesize::ByteSize;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::path::PathBuf;
-use ungelify::mpk::MagesArchive;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use ungelify::archive::{
+    detect_format, match_replacements, open_archive_with, Archive, OpenOptions as ArchiveOptions,
+};
+use ungelify::batch;
+use ungelify::batch::Report;
+use ungelify::config::Config;
+use ungelify::convert;
+use ungelify::convert::{Converters, Manifest};
+use ungelify::font::Font;
+use ungelify::game::GameProfile;
+use ungelify::image::Image;
+use ungelify::lay::Layout;
+use ungelify::mpk::codec;
+use ungelify::mpk::{Endian, EntryLayout, MagesArchive, NameEncoding};
+use ungelify::sc3::strings::{StringFormat, StringLine};
+use ungelify::sc3::text::EncodeError;
+use ungelify::sc3::{text, Charset, InstructionSet, Sc3Script};
+use ungelify::select;
+use ungelify::select::{EntrySelector, Selection};
+use ungelify::sniff::FileType;
+use ungelify::stats::ArchiveStats;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -15,6 +41,60 @@ use ungelify::mpk::MagesArchive;
 pub struct Cli {
     #[command(subcommand)]
     pub command: Cmd,
+    #[arg(
+        long,
+        global = true,
+        value_name = "GAME",
+        help = "The game the archive is from: a game profile's ID or file, setting the defaults for reading its archives,\nor the path to an NPA key file.\nIdentified from the current directory if left out."
+    )]
+    pub game: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "LAYOUT",
+        value_parser = parse_mpk_layout,
+        help = "The entry table layout of an MPK archive, for versions ungelify doesn't know.\nEither v1, v2, or e.g. entry_size=0x100,id=4:4,offset=8:8,len_compressed=16:8,len_deflated=24:8,name=32:224"
+    )]
+    pub mpk_layout: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "ENCODING",
+        value_parser = parse_mpk_name_encoding,
+        help = "How MPK entry names are encoded, utf-8 (the default) or shift-jis."
+    )]
+    pub mpk_name_encoding: Option<String>,
+    #[arg(
+        long = "codec",
+        global = true,
+        value_name = "[INDICATOR=]CODEC",
+        value_parser = parse_codec_assignment,
+        help = "The codec for MPK entries with the given compression indicator (1 if left out).\nEither zlib or xor:KEY. Can be given multiple times."
+    )]
+    pub codecs: Vec<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "The config file to use instead of the closest ungelify.toml in the current directory or above."
+    )]
+    pub config: Option<PathBuf>,
+}
+
+// the global flags are kept as given so `config show` can print them, but checked up front
+fn parse_mpk_layout(s: &str) -> Result<String, String> {
+    s.parse::<EntryLayout>()?;
+    Ok(s.to_string())
+}
+
+fn parse_mpk_name_encoding(s: &str) -> Result<String, String> {
+    s.parse::<NameEncoding>()?;
+    Ok(s.to_string())
+}
+
+fn parse_codec_assignment(s: &str) -> Result<String, String> {
+    codec::parse_assignment(s)?;
+    Ok(s.to_string())
 }
 
 #[derive(Debug, Subcommand)]
@@ -26,6 +106,14 @@ pub enum Cmd {
     List {
         #[arg(value_name = "ARCHIVE", help = "The path to the archive.")]
         archive_path: PathBuf,
+        #[arg(
+            value_name = "ENTRIES",
+            value_parser = select::parse_pattern,
+            help = "Choose specific entry names/globs/regexes/IDs/ID ranges to list."
+        )]
+        entries: Vec<String>,
+        #[command(flatten)]
+        selection: SelectionArgs,
     },
     #[command(
         about = "Extract file(s) from an archive",
@@ -36,7 +124,8 @@ pub enum Cmd {
         archive_path: PathBuf,
         #[arg(
             value_name = "ENTRIES",
-            help = "Choose specific entry names/globs/IDs to extract."
+            value_parser = select::parse_pattern,
+            help = "Choose specific entry names/globs/regexes/IDs/ID ranges to extract."
         )]
         entries: Vec<String>,
         #[arg(
@@ -45,6 +134,50 @@ pub enum Cmd {
             help = "The output directory for extracted files.\nWill be created if it does not exist."
         )]
         output_dir: Option<PathBuf>,
+        #[arg(
+            long,
+            help = "Convert entries that have a converter (.lay to JSON, .SCX to text) and record it in a manifest,\nso repack converts them back."
+        )]
+        convert: bool,
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+    #[command(
+        about = "Extract every archive of a game install",
+        arg_required_else_help = true
+    )]
+    ExtractAll {
+        #[arg(value_name = "GAME_DIR", help = "The game's install directory.")]
+        game_dir: PathBuf,
+        #[arg(
+            short,
+            long,
+            help = "The directory to extract to, mirroring the install with a directory per archive.\nDefaults to extract_dir in ungelify.toml."
+        )]
+        output_dir: Option<PathBuf>,
+        #[arg(
+            long,
+            help = "Convert entries that have a converter, like extract --convert."
+        )]
+        convert: bool,
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+    #[command(
+        about = "Write the contents of entries to stdout",
+        arg_required_else_help = true
+    )]
+    Cat {
+        #[arg(value_name = "ARCHIVE", help = "The path to the archive.")]
+        archive_path: PathBuf,
+        #[arg(
+            value_name = "ENTRIES",
+            value_parser = select::parse_pattern,
+            help = "Choose specific entry names/globs/regexes/IDs/ID ranges to print."
+        )]
+        entries: Vec<String>,
+        #[command(flatten)]
+        selection: SelectionArgs,
     },
     #[command(
         about = "Repack files to a new archive",
@@ -64,9 +197,911 @@ pub enum Cmd {
             help = "Do not save a backup copy of the original archive."
         )]
         no_save: bool,
+        #[arg(
+            short,
+            long,
+            help = "Patch the archive in place instead of rebuilding it.\nReplacements that don't fit in their original slot are appended to the end.\nNo backup is kept unless --backup is given."
+        )]
+        in_place: bool,
+        #[arg(
+            short,
+            long,
+            requires = "in_place",
+            help = "Keep a backup copy of the original archive when patching in place."
+        )]
+        backup: bool,
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+    #[command(
+        about = "Repack every archive of a game install that a patch tree has files for",
+        arg_required_else_help = true
+    )]
+    RepackAll {
+        #[arg(value_name = "GAME_DIR", help = "The game's install directory.")]
+        game_dir: PathBuf,
+        #[arg(
+            value_name = "PATCH_DIR",
+            help = "The replacement files, laid out like extract-all's output: ARCHIVE_STEM/ENTRY_NAME."
+        )]
+        patch_dir: PathBuf,
+        #[arg(
+            short,
+            long,
+            help = "Do not save backup copies of the original archives."
+        )]
+        no_save: bool,
+        #[arg(
+            short,
+            long,
+            help = "Patch the archives in place instead of rebuilding them, like repack --in-place."
+        )]
+        in_place: bool,
+        #[arg(
+            short,
+            long,
+            requires = "in_place",
+            help = "Keep backup copies of the original archives when patching in place."
+        )]
+        backup: bool,
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+    #[command(
+        about = "Rewrite an archive without any unused space between entries",
+        arg_required_else_help = true,
+        aliases = ["c", "defrag"])]
+    Compact {
+        #[arg(value_name = "ARCHIVE", help = "The path to the archive.")]
+        archive_path: PathBuf,
+        #[arg(
+            short,
+            long,
+            help = "Only report how much space would be saved, without rewriting anything."
+        )]
+        dry_run: bool,
+        #[arg(
+            short,
+            long,
+            value_enum,
+            help = "Reorder the entries while compacting."
+        )]
+        sort: Option<SortOrder>,
+        #[arg(
+            short,
+            long,
+            help = "Do not save a backup copy of the original archive."
+        )]
+        no_save: bool,
+    },
+    #[command(
+        about = "Summarize how space is used in an archive",
+        arg_required_else_help = true,
+        aliases = ["s", "info"])]
+    Stats {
+        #[arg(value_name = "ARCHIVE", help = "The path to the archive.")]
+        archive_path: PathBuf,
+        #[arg(short, long, help = "Print the statistics as JSON.")]
+        json: bool,
+    },
+    #[command(
+        about = "Convert an MPK archive between the PC (little-endian) and console (big-endian) layouts",
+        arg_required_else_help = true
+    )]
+    Convert {
+        #[arg(value_name = "ARCHIVE", help = "The path to the archive.")]
+        archive_path: PathBuf,
+        #[arg(
+            short,
+            long,
+            value_name = "ORDER",
+            help = "The byte order to convert the archive's headers to (little, big)."
+        )]
+        endian: Endian,
+        #[arg(
+            short,
+            long,
+            help = "Do not save a backup copy of the original archive."
+        )]
+        no_save: bool,
+    },
+    #[command(about = "Work with SC3 scripts (.SCX)", arg_required_else_help = true)]
+    Script {
+        #[command(subcommand)]
+        command: ScriptCmd,
+    },
+    #[command(
+        about = "Export and import the strings of SC3 scripts for translation",
+        arg_required_else_help = true
+    )]
+    Strings {
+        #[command(subcommand)]
+        command: StringsCmd,
+    },
+    #[command(
+        about = "Work with character sprites (an atlas .png and its _.lay layout)",
+        arg_required_else_help = true
+    )]
+    Sprite {
+        #[command(subcommand)]
+        command: SpriteCmd,
+    },
+    #[command(
+        about = "Work with font atlases and their glyph width tables",
+        arg_required_else_help = true
+    )]
+    Font {
+        #[command(subcommand)]
+        command: FontCmd,
+    },
+    #[command(about = "Work with game profiles", arg_required_else_help = true)]
+    Game {
+        #[command(subcommand)]
+        command: GameCmd,
+    },
+    #[command(about = "Work with ungelify.toml", arg_required_else_help = true)]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCmd,
     },
 }
 
+#[derive(Debug, Subcommand)]
+pub enum GameCmd {
+    #[command(about = "List the built-in game profiles")]
+    List,
+    #[command(about = "Identify the game installed in a directory and check its archives")]
+    Identify {
+        #[arg(
+            value_name = "DIR",
+            default_value = ".",
+            help = "The game's install directory."
+        )]
+        dir: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCmd {
+    #[command(about = "Print the configuration in effect, with command line flags applied")]
+    Show,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ScriptCmd {
+    #[command(
+        about = "Disassemble a script into text",
+        arg_required_else_help = true
+    )]
+    Disasm {
+        #[arg(
+            value_name = "SCRIPT",
+            help = "The path to an .SCX file, or an entry in an archive as ARCHIVE:NAME."
+        )]
+        script: String,
+        #[arg(
+            short,
+            long,
+            value_name = "SET",
+            help = "The instruction set to decode the code with, as the path to a definition file (no sets are built in yet).\nWithout one, all code is printed as raw bytes."
+        )]
+        instructions: Option<String>,
+        #[arg(
+            short,
+            long,
+            value_name = "CHARSET",
+            help = "The charset file of the game's font.\nWith one, each string's text is added as a comment."
+        )]
+        charset: Option<String>,
+        #[arg(
+            short,
+            long,
+            help = "Write the disassembly to this file instead of stdout."
+        )]
+        output: Option<PathBuf>,
+    },
+    #[command(
+        about = "Assemble a disassembled script back into an .SCX file",
+        arg_required_else_help = true
+    )]
+    Asm {
+        #[arg(value_name = "TEXT", help = "The path to the disassembly.")]
+        input: PathBuf,
+        #[arg(
+            short,
+            long,
+            value_name = "SET",
+            help = "The instruction set the disassembly was made with, as the path to a definition file."
+        )]
+        instructions: Option<String>,
+        #[arg(
+            short,
+            long,
+            help = "Where to write the script.\nDefaults to the input path with an .SCX extension."
+        )]
+        output: Option<PathBuf>,
+        #[arg(
+            long,
+            value_name = "ARCHIVE",
+            help = "Also replace the entry named after the output file in this archive."
+        )]
+        repack: Option<PathBuf>,
+        #[arg(
+            short,
+            long,
+            requires = "repack",
+            help = "Do not save a backup copy of the original archive."
+        )]
+        no_save: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum StringsCmd {
+    #[command(
+        about = "Export the strings of every script in an archive",
+        arg_required_else_help = true
+    )]
+    Export {
+        #[arg(value_name = "ARCHIVE", help = "The path to the archive.")]
+        archive_path: PathBuf,
+        #[arg(
+            short,
+            long,
+            default_value = "po",
+            help = "The format of the translation file (po, csv, json)."
+        )]
+        format: StringFormat,
+        #[arg(
+            short,
+            long,
+            value_name = "CHARSET",
+            help = "The charset file of the game's font.\nWithout one, every glyph is written as its index."
+        )]
+        charset: Option<String>,
+        #[arg(
+            short,
+            long,
+            help = "Write the strings to this file instead of stdout."
+        )]
+        output: Option<PathBuf>,
+    },
+    #[command(
+        about = "Import translated strings, rebuild the scripts and repack the archive",
+        arg_required_else_help = true
+    )]
+    Import {
+        #[arg(value_name = "ARCHIVE", help = "The path to the archive.")]
+        archive_path: PathBuf,
+        #[arg(value_name = "TRANSLATIONS", help = "The translation file.")]
+        translations: PathBuf,
+        #[arg(
+            short,
+            long,
+            help = "The format of the translation file (po, csv, json).\nDefaults to the one its extension says."
+        )]
+        format: Option<StringFormat>,
+        #[arg(
+            short,
+            long,
+            value_name = "CHARSET",
+            help = "The charset file of the game's font.\nWithout one, translations can only use glyph indices."
+        )]
+        charset: Option<String>,
+        #[arg(
+            long,
+            value_name = "GLYPHS",
+            help = "Warn about translated lines with more glyphs than this."
+        )]
+        max_line_len: Option<usize>,
+        #[arg(
+            short,
+            long,
+            help = "Do not save a backup copy of the original archive."
+        )]
+        no_save: bool,
+    },
+    #[command(
+        about = "Check that translated strings can be encoded, without touching any archive",
+        arg_required_else_help = true
+    )]
+    Check {
+        #[arg(value_name = "TRANSLATIONS", help = "The translation file.")]
+        translations: PathBuf,
+        #[arg(
+            short,
+            long,
+            help = "The format of the translation file (po, csv, json).\nDefaults to the one its extension says."
+        )]
+        format: Option<StringFormat>,
+        #[arg(
+            short,
+            long,
+            value_name = "CHARSET",
+            help = "The charset file of the game's font.\nWithout one, translations can only use glyph indices."
+        )]
+        charset: Option<String>,
+        #[arg(
+            long,
+            value_name = "GLYPHS",
+            help = "Warn about translated lines with more glyphs than this."
+        )]
+        max_line_len: Option<usize>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SpriteCmd {
+    #[command(about = "List the states of a sprite", arg_required_else_help = true)]
+    Info {
+        #[arg(value_name = "ARCHIVE", help = "The path to the archive.")]
+        archive_path: PathBuf,
+        #[arg(
+            value_name = "SPRITE",
+            help = "The sprite's name, e.g. ARI_ALA for ARI_ALA.png and ARI_ALA_.lay."
+        )]
+        sprite: String,
+    },
+    #[command(
+        about = "Composite states of a sprite into an image",
+        arg_required_else_help = true
+    )]
+    Render {
+        #[arg(value_name = "ARCHIVE", help = "The path to the archive.")]
+        archive_path: PathBuf,
+        #[arg(
+            value_name = "SPRITE",
+            help = "The sprite's name, e.g. ARI_ALA for ARI_ALA.png and ARI_ALA_.lay."
+        )]
+        sprite: String,
+        #[arg(
+            short,
+            long = "state",
+            value_name = "N",
+            required = true,
+            help = "The state to draw. Can be given multiple times to draw states over each other, e.g. a face over a pose."
+        )]
+        states: Vec<usize>,
+        #[arg(
+            short,
+            long,
+            help = "Where to write the image.\nDefaults to the sprite name and states, e.g. ARI_ALA_0+5.png."
+        )]
+        output: Option<PathBuf>,
+    },
+    #[command(
+        about = "Rebuild a sprite's atlas and layout from edited renders of its states and repack them",
+        arg_required_else_help = true
+    )]
+    Rebuild {
+        #[arg(value_name = "ARCHIVE", help = "The path to the archive.")]
+        archive_path: PathBuf,
+        #[arg(
+            value_name = "SPRITE",
+            help = "The sprite's name, e.g. ARI_ALA for ARI_ALA.png and ARI_ALA_.lay."
+        )]
+        sprite: String,
+        #[arg(
+            value_name = "[STATE=]IMAGE",
+            required = true,
+            value_parser = parse_state_image,
+            help = "An edited render of a single state, the same size as `sprite render` drew it.\nThe state can be left out if the file is named like a render, e.g. ARI_ALA_0.png."
+        )]
+        images: Vec<(usize, PathBuf)>,
+        #[arg(
+            short,
+            long,
+            help = "Do not save a backup copy of the original archive."
+        )]
+        no_save: bool,
+    },
+}
+
+// Parses `STATE=IMAGE`, or takes the state from an image named like `sprite render` names them.
+fn parse_state_image(s: &str) -> Result<(usize, PathBuf), String> {
+    if let Some((state, path)) = s.split_once('=') {
+        if let Ok(state) = state.parse() {
+            return Ok((state, PathBuf::from(path)));
+        }
+    }
+
+    let path = PathBuf::from(s);
+    path.file_stem()
+        .and_then(|stem| stem.to_str()?.rsplit_once('_')?.1.parse().ok())
+        .map(|state| (state, path.clone()))
+        .ok_or_else(|| format!("can't tell which state '{s}' is; pass it as STATE={s}"))
+}
+
+#[derive(Debug, Subcommand)]
+pub enum FontCmd {
+    #[command(
+        about = "Show how a font atlas is laid out",
+        arg_required_else_help = true
+    )]
+    Info {
+        #[command(flatten)]
+        font: FontArgs,
+    },
+    #[command(
+        about = "Render a TTF/OTF font into a font atlas and width table and repack them",
+        arg_required_else_help = true
+    )]
+    Build {
+        #[command(flatten)]
+        font: FontArgs,
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "The TTF/OTF font to draw glyphs with."
+        )]
+        ttf: PathBuf,
+        #[arg(
+            short,
+            long,
+            value_name = "CHARSET",
+            help = "The charset file saying which character each glyph is.\nDefaults to the charset in ungelify.toml."
+        )]
+        charset: Option<String>,
+        #[arg(
+            long,
+            value_name = "PX",
+            help = "The size to draw glyphs at, in pixels.\nDefaults to the cell size."
+        )]
+        size: Option<f32>,
+        #[arg(
+            long,
+            value_name = "START-END",
+            value_parser = parse_glyph_range,
+            help = "Only draw these glyphs, e.g. 0x1f00-0x1fff for characters added for a translation.\nDefaults to every glyph in the charset."
+        )]
+        glyphs: Option<RangeInclusive<u16>>,
+        #[arg(
+            short,
+            long,
+            help = "Do not save a backup copy of the original archive."
+        )]
+        no_save: bool,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct FontArgs {
+    #[arg(value_name = "ARCHIVE", help = "The path to the archive.")]
+    archive_path: PathBuf,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "The entry holding the font atlas, as a PNG."
+    )]
+    atlas: String,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "The entry holding the glyph width table."
+    )]
+    widths: String,
+    #[arg(
+        long,
+        value_name = "PX",
+        help = "The width and height of a glyph's cell in the atlas."
+    )]
+    cell_size: u32,
+}
+
+fn parse_glyph_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let range = select::parse_offset_range(s)?;
+    let glyph =
+        |glyph: u64| u16::try_from(glyph).map_err(|_| format!("glyph {glyph:#x} too large"));
+    Ok(glyph(*range.start())?..=glyph(*range.end())?)
+}
+
+#[derive(Debug, Args)]
+pub struct SelectionArgs {
+    #[arg(short = 'I', long, help = "Match entry names case-insensitively.")]
+    ignore_case: bool,
+    #[arg(
+        short,
+        long,
+        value_name = "PATTERN",
+        value_parser = select::parse_pattern,
+        help = "Skip entries matching this name/glob/regex/ID/ID range.\nCan be given multiple times."
+    )]
+    exclude: Vec<String>,
+    #[arg(long, help = "Only select compressed entries.")]
+    compressed: bool,
+    #[arg(
+        long,
+        conflicts_with = "compressed",
+        help = "Only select uncompressed entries."
+    )]
+    stored: bool,
+    #[arg(
+        long,
+        value_name = "SIZE",
+        help = "Only select entries at least this large when uncompressed, e.g. 1M or 512KiB."
+    )]
+    min_size: Option<ByteSize>,
+    #[arg(
+        long,
+        value_name = "SIZE",
+        help = "Only select entries at most this large when uncompressed."
+    )]
+    max_size: Option<ByteSize>,
+    #[arg(
+        long,
+        value_name = "START-END",
+        value_parser = select::parse_offset_range,
+        help = "Only select entries whose data starts within this range of offsets, e.g. 0x1000-0x8000."
+    )]
+    offset_range: Option<RangeInclusive<u64>>,
+    #[arg(
+        short = 't',
+        long = "type",
+        value_name = "TYPE",
+        help = "Only select entries whose contents are of this type (png, ogg, wav, dds, zlib, sc3, lay, text, unknown).\nCan be given multiple times."
+    )]
+    types: Vec<FileType>,
+}
+
+impl SelectionArgs {
+    fn selector(&self, entries: &[String], config: &Config) -> EntrySelector {
+        let compressed = match (self.compressed, self.stored) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        };
+
+        EntrySelector::builder()
+            .includes(entries)
+            .excludes(if self.exclude.is_empty() {
+                &config.exclude
+            } else {
+                &self.exclude
+            })
+            .case_insensitive(self.ignore_case)
+            .compressed(compressed)
+            .min_size(self.min_size.map(|size| size.as_u64()))
+            .max_size(self.max_size.map(|size| size.as_u64()))
+            .offset_range(self.offset_range.clone())
+            .types(&self.types)
+            .build()
+            // patterns are checked when the arguments and the config are parsed
+            .expect("invalid entry pattern")
+    }
+}
+
+fn warn_unmatched(selection: &Selection) {
+    for pattern in &selection.unmatched_includes {
+        eprintln!("warning: pattern '{pattern}' did not match any entries");
+    }
+    for pattern in &selection.unmatched_excludes {
+        eprintln!("warning: exclude pattern '{pattern}' did not match any entries");
+    }
+}
+
+// Only keeps the replacement files whose entries were picked out by the selection, warning about
+// every other one. Fails if that leaves nothing to repack, e.g. when the config excludes every
+// file that was given.
+fn filter_repack_files(
+    rpk_files: Vec<PathBuf>,
+    archive: &dyn Archive,
+    selection: &Selection,
+) -> Vec<PathBuf> {
+    // manifests come along with converted files, they aren't replacements themselves
+    let rpk_files = rpk_files
+        .into_iter()
+        .filter(|path| {
+            path.file_name()
+                .is_none_or(|name| name != Manifest::FILE_NAME)
+        })
+        .collect::<Vec<_>>();
+    let selected_names = selection
+        .entries
+        .iter()
+        .map(|entry| entry.name())
+        .collect::<HashSet<_>>();
+    let entries = archive.entries();
+    let names = match_replacements(entries.iter().map(|entry| entry.name()), &rpk_files);
+
+    let mut kept = Vec::new();
+    for (name, path) in names.into_iter().zip(&rpk_files) {
+        match name {
+            Some(name) if selected_names.contains(name) => kept.push(path.clone()),
+            Some(name) => eprintln!(
+                "warning: skipping {}, its entry '{name}' isn't selected",
+                path.display()
+            ),
+            None => eprintln!(
+                "warning: skipping {}, it doesn't match any entry",
+                path.display()
+            ),
+        }
+    }
+    assert!(
+        rpk_files.is_empty() || !kept.is_empty(),
+        "none of the given files replace a selected entry"
+    );
+    kept
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum SortOrder {
+    Id,
+    Name,
+}
+
+// Reads either a file on disk or, given `ARCHIVE:NAME`, the entry NAME from an archive.
+fn read_file_or_entry(path: &str, archive_options: &ArchiveOptions) -> Vec<u8> {
+    if Path::new(path).is_file() {
+        return fs::read(path).unwrap();
+    }
+
+    let (archive_path, entry_name) = path
+        .rsplit_once(':')
+        .unwrap_or_else(|| panic!("no such file '{path}'"));
+    let mut reader = BufReader::new(File::open(archive_path).unwrap());
+    let archive = open_archive_with(&mut reader, archive_options);
+    let entry = archive
+        .entries()
+        .into_iter()
+        .find(|entry| entry.name() == entry_name)
+        .unwrap_or_else(|| panic!("no entry named '{entry_name}' in {archive_path}"));
+
+    let mut data = Vec::new();
+    archive
+        .open_entry(&mut reader, entry)
+        .read_to_end(&mut data)
+        .expect("failed to read entry from archive");
+    data
+}
+
+// Replaces entries of the archive at `archive_path` with `rpk_files`, either by rebuilding it or
+// by patching it in place. A rebuilt archive's original is kept with a ".orig" suffix unless
+// `no_save` is set; patching only keeps one if `backup` is set, since copying a multi-gigabyte
+// archive would defeat the point of patching it.
+fn repack_archive(
+    archive_path: &Path,
+    rpk_files: Vec<PathBuf>,
+    no_save: bool,
+    in_place: bool,
+    backup: bool,
+    selector: &EntrySelector,
+    archive_options: &ArchiveOptions,
+) -> usize {
+    assert!(archive_path.is_file());
+    // settle which files to use before touching the archive, so a bad selection leaves it be
+    let rpk_files = {
+        let mut reader = BufReader::new(File::open(archive_path).unwrap());
+        let archive = open_archive_with(&mut reader, archive_options);
+        let selection = archive.select(&mut reader, selector);
+        warn_unmatched(&selection);
+        filter_repack_files(rpk_files, archive.as_ref(), &selection)
+    };
+
+    if in_place {
+        if backup {
+            fs::copy(archive_path, append_to_path(archive_path, ".orig")).unwrap();
+        }
+
+        let mut archive = BufReader::new(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(archive_path)
+                .unwrap(),
+        );
+        let mut rpk_archive = open_archive_with(&mut archive, archive_options);
+        let mut archive = BufWriter::new(archive.into_inner());
+        rpk_archive
+            .patch(&mut archive, &rpk_files)
+            .unwrap_or_else(|e| panic!("failed to patch {}: {e}", archive_path.display()));
+        return rpk_files.len();
+    }
+
+    let orig_path = append_to_path(archive_path, ".orig");
+    fs::rename(archive_path, &orig_path).unwrap();
+
+    let mut orig_reader = BufReader::new(File::open(&orig_path).unwrap());
+    let archive = open_archive_with(&mut orig_reader, archive_options);
+    let mut rpk_writer = BufWriter::new(File::create(archive_path).unwrap());
+
+    archive.repack(&mut orig_reader, &mut rpk_writer, &rpk_files);
+
+    if no_save {
+        fs::remove_file(&orig_path).unwrap();
+    }
+    rpk_files.len()
+}
+
+// Runs `f`, turning a panic into its message, so one bad archive doesn't stop a whole batch.
+fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|message| (*message).to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".to_string())
+    })
+}
+
+// Warns about the archives `profile` expects that the install in `dir` doesn't have.
+fn warn_missing_archives(profile: &GameProfile, dir: &Path) {
+    let found = profile.archives_in(dir);
+    for archive in profile
+        .archives
+        .iter()
+        .filter(|a| !found.contains(&a.as_str()))
+    {
+        eprintln!(
+            "warning: {} has no {archive}, which {} installs have",
+            dir.display(),
+            profile.title
+        );
+    }
+}
+
+// Reads every script in the archive at `archive_path`, along with its entry name.
+fn read_scripts(archive_path: &Path, archive_options: &ArchiveOptions) -> Vec<(String, Sc3Script)> {
+    let mut reader = BufReader::new(File::open(archive_path).unwrap());
+    let archive = open_archive_with(&mut reader, archive_options);
+    let selector = EntrySelector::builder()
+        .includes(&["*.scx".to_string()])
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+    let selection = archive.select(&mut reader, &selector);
+
+    selection
+        .entries
+        .iter()
+        .map(|entry| {
+            let mut data = Vec::new();
+            archive
+                .open_entry(&mut reader, *entry)
+                .read_to_end(&mut data)
+                .expect("failed to read entry from archive");
+            (entry.name().to_string(), Sc3Script::parse(&data))
+        })
+        .collect()
+}
+
+// Reads the entries named `names` (ignoring case) from an archive, in the same order, along with
+// their names as the archive spells them.
+fn read_entries(
+    archive_path: &Path,
+    names: &[&str],
+    archive_options: &ArchiveOptions,
+) -> Vec<(String, Vec<u8>)> {
+    let mut reader = BufReader::new(File::open(archive_path).unwrap());
+    let archive = open_archive_with(&mut reader, archive_options);
+    names
+        .iter()
+        .map(|name| {
+            let entry = archive
+                .entries()
+                .into_iter()
+                .find(|entry| entry.name().eq_ignore_ascii_case(name))
+                .unwrap_or_else(|| panic!("no entry named '{name}' in {}", archive_path.display()));
+            let mut data = Vec::new();
+            archive
+                .open_entry(&mut reader, entry)
+                .read_to_end(&mut data)
+                .expect("failed to read entry from archive");
+            (entry.name().to_string(), data)
+        })
+        .collect()
+}
+
+// Writes each file to where extracting its entry into `dir` would put it, so it replaces that
+// entry and no other, and returns the paths.
+fn stage_replacements(dir: &Path, files: &[(&str, &[u8])]) -> Vec<PathBuf> {
+    files
+        .iter()
+        .map(|(name, data)| {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, data).unwrap();
+            path
+        })
+        .collect()
+}
+
+// Reads the layout and atlas of the sprite `name` from an archive, along with the names of
+// their entries.
+fn read_sprite(
+    archive_path: &Path,
+    name: &str,
+    archive_options: &ArchiveOptions,
+) -> (Layout, Image, [String; 2]) {
+    let [(layout_name, layout), (atlas_name, atlas)] = read_entries(
+        archive_path,
+        &[&format!("{name}_.lay"), &format!("{name}.png")],
+        archive_options,
+    )
+    .try_into()
+    .unwrap();
+    (
+        Layout::parse(&layout),
+        Image::from_png(&atlas),
+        [layout_name, atlas_name],
+    )
+}
+
+impl FontArgs {
+    // Reads the font, along with the names of its atlas and width table entries.
+    fn read(&self, archive_options: &ArchiveOptions) -> (Font, [String; 2]) {
+        let [(atlas_name, atlas), (widths_name, widths)] = read_entries(
+            &self.archive_path,
+            &[&self.atlas, &self.widths],
+            archive_options,
+        )
+        .try_into()
+        .unwrap();
+        (
+            Font::new(Image::from_png(&atlas), widths, self.cell_size),
+            [atlas_name, widths_name],
+        )
+    }
+}
+
+fn string_id(entry_name: &str, index: usize) -> String {
+    format!("{entry_name}:{index}")
+}
+
+fn read_translations(path: &Path, format: Option<StringFormat>) -> Vec<StringLine> {
+    let format = format
+        .or_else(|| StringFormat::from_extension(path))
+        .unwrap_or_else(|| panic!("can't tell the format of {}; pass --format", path.display()));
+    let text = fs::read_to_string(path).unwrap();
+    ungelify::sc3::strings::read_lines(&text, format)
+        .unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()))
+}
+
+// Encodes the translation of the string `id`, warning about lines longer than `max_line_len`.
+// Characters missing from the charset are added to `missing_chars`.
+fn encode_translation(
+    id: &str,
+    translation: &str,
+    charset: &Charset,
+    max_line_len: Option<usize>,
+    missing_chars: &mut Vec<char>,
+) -> Result<Vec<u8>, String> {
+    let encoded = text::encode(translation, charset).map_err(|e| {
+        if let EncodeError::MissingChars(chars) = &e {
+            for &c in chars {
+                if !missing_chars.contains(&c) {
+                    missing_chars.push(c);
+                }
+            }
+        }
+        format!("{id}: {e}")
+    })?;
+
+    if let Some(max) = max_line_len {
+        for (line, len) in text::line_lengths(&encoded).into_iter().enumerate() {
+            if len > max {
+                eprintln!(
+                    "warning: {id} line {} is {len} glyphs long (max {max})",
+                    line + 1
+                );
+            }
+        }
+    }
+    Ok(encoded)
+}
+
+fn report_encode_errors(errors: &[String], missing_chars: &[char]) {
+    for e in errors {
+        eprintln!("error: {e}");
+    }
+    if !missing_chars.is_empty() {
+        eprintln!(
+            "characters missing from the font: {}",
+            missing_chars.iter().collect::<String>()
+        );
+    }
+}
+
 fn append_to_path(p: impl Into<OsString>, s: impl AsRef<OsStr>) -> PathBuf {
     let mut p = p.into();
     p.push(s);
@@ -74,51 +1109,787 @@ fn append_to_path(p: impl Into<OsString>, s: impl AsRef<OsStr>) -> PathBuf {
 }
 
 pub fn run(cli: Cli) {
+    let current_dir = std::env::current_dir().unwrap();
+    let config_path = cli.config.or_else(|| Config::discover(&current_dir));
+    let mut config = config_path.map(Config::from_file).unwrap_or_default();
+    // flags override the config, codecs are registered after the config's so they win too
+    config.game = cli.game.or(config.game);
+    config.mpk_layout = cli.mpk_layout.or(config.mpk_layout);
+    config.mpk_name_encoding = cli.mpk_name_encoding.or(config.mpk_name_encoding);
+    config.codecs.extend(cli.codecs);
+    let game_given = config.game.is_some();
+    let install_dir = match &cli.command {
+        Cmd::ExtractAll { game_dir, .. } | Cmd::RepackAll { game_dir, .. } => game_dir,
+        _ => &current_dir,
+    };
+    let profile = match &config.game {
+        Some(game) => GameProfile::for_game(game),
+        // working on (or from inside) an install picks its game
+        None => GameProfile::identify(install_dir),
+    };
+    if let Some(profile) = profile {
+        config.apply_profile(profile);
+    }
+    let archive_options = config.open_options();
     match cli.command {
-        Cmd::List { archive_path } => {
+        Cmd::List {
+            archive_path,
+            entries,
+            selection,
+        } => {
             assert!(archive_path.is_file());
             let mut reader = BufReader::new(File::open(&archive_path).unwrap());
-            let mpk = MagesArchive::build(&mut reader);
-            mpk.list_entries();
+            let archive = open_archive_with(&mut reader, &archive_options);
+            let selection = archive.select(&mut reader, &selection.selector(&entries, &config));
+            warn_unmatched(&selection);
+            archive.list_selection(&mut reader, &selection);
         }
         Cmd::Extract {
             archive_path,
             entries,
             output_dir,
+            convert,
+            selection,
         } => {
             assert!(archive_path.is_file());
             let parent_dir = archive_path.parent().unwrap();
-            let output_dir = output_dir
-                .unwrap_or_else(|| parent_dir.join(ungelify::archive_output_dir(&archive_path)));
+            let output_dir = output_dir.unwrap_or_else(|| {
+                config
+                    .extract_dir
+                    .as_deref()
+                    .unwrap_or(parent_dir)
+                    .join(ungelify::archive_output_dir(&archive_path))
+            });
             fs::create_dir_all(&output_dir).unwrap();
 
             let mut reader = BufReader::new(File::open(&archive_path).unwrap());
-            let mpk = MagesArchive::build(&mut reader);
+            let archive = open_archive_with(&mut reader, &archive_options);
+            let selection = archive.select(&mut reader, &selection.selector(&entries, &config));
+            warn_unmatched(&selection);
+            // hooks always run, the built-in converters only when asked for
+            let converters = config.register_hooks(if convert {
+                config.converters()
+            } else {
+                Converters::none()
+            });
+            if converters.is_empty() {
+                archive.extract_selection(&mut reader, &output_dir, &selection);
+            } else {
+                convert::extract_converted(
+                    archive.as_ref(),
+                    &mut reader,
+                    &output_dir,
+                    &selection,
+                    &converters,
+                );
+            }
+        }
+        Cmd::ExtractAll {
+            game_dir,
+            output_dir,
+            convert,
+            selection,
+        } => {
+            assert!(
+                game_dir.is_dir(),
+                "{} is not a directory",
+                game_dir.display()
+            );
+            let output_dir = output_dir.or_else(|| config.extract_dir.clone()).expect(
+                "no output directory given, pass --output-dir or set extract_dir in ungelify.toml",
+            );
+            fs::create_dir_all(&output_dir).unwrap();
+            if let Some(profile) = &config.profile {
+                warn_missing_archives(profile, &game_dir);
+            }
 
-            if entries.is_empty() {
-                mpk.extract(&mut reader, &output_dir);
+            let converters = config.register_hooks(if convert {
+                config.converters()
             } else {
-                mpk.extract_entries(&mut reader, &output_dir, &entries);
+                Converters::none()
+            });
+            let selector = selection.selector(&[], &config);
+            // the output directory may well be inside the install
+            let archives = batch::find_archives(
+                &game_dir.canonicalize().unwrap(),
+                Some(&output_dir.canonicalize().unwrap()),
+            );
+
+            let mut report = Report::new("extracted");
+            for archive_name in archives {
+                let archive_path = game_dir.join(&archive_name);
+                let archive_dir = output_dir
+                    .join(archive_name.parent().unwrap())
+                    .join(ungelify::archive_output_dir(&archive_name));
+                let result = catch_panic(|| {
+                    fs::create_dir_all(&archive_dir).unwrap();
+                    let mut reader = BufReader::new(File::open(&archive_path).unwrap());
+                    let archive = open_archive_with(&mut reader, &archive_options);
+                    let selection = archive.select(&mut reader, &selector);
+                    if converters.is_empty() {
+                        archive.extract_selection(&mut reader, &archive_dir, &selection);
+                    } else {
+                        convert::extract_converted(
+                            archive.as_ref(),
+                            &mut reader,
+                            &archive_dir,
+                            &selection,
+                            &converters,
+                        );
+                    }
+                    selection.entries.len()
+                });
+                report.archives.push((archive_name, result));
+            }
+
+            println!("{report}");
+            assert_eq!(report.failures(), 0, "some archives failed to extract");
+        }
+        Cmd::RepackAll {
+            game_dir,
+            patch_dir,
+            no_save,
+            in_place,
+            backup,
+            selection,
+        } => {
+            assert!(
+                game_dir.is_dir(),
+                "{} is not a directory",
+                game_dir.display()
+            );
+            assert!(
+                patch_dir.is_dir(),
+                "{} is not a directory",
+                patch_dir.display()
+            );
+            if let Some(profile) = &config.profile {
+                warn_missing_archives(profile, &game_dir);
+            }
+
+            let converters = config.register_hooks(config.converters());
+            let selector = selection.selector(&[], &config);
+            let archives = batch::find_archives(
+                &game_dir.canonicalize().unwrap(),
+                Some(&patch_dir.canonicalize().unwrap()),
+            );
+
+            let mut report = Report::new("replaced");
+            for archive_name in archives {
+                let archive_patch_dir = patch_dir
+                    .join(archive_name.parent().unwrap())
+                    .join(ungelify::archive_output_dir(&archive_name));
+                if !archive_patch_dir.is_dir() {
+                    continue;
+                }
+                let result = catch_panic(|| {
+                    let files = batch::files_in(&archive_patch_dir)
+                        .into_iter()
+                        .filter(|path| {
+                            path.file_name()
+                                .is_some_and(|name| name != Manifest::FILE_NAME)
+                        })
+                        .collect::<Vec<_>>();
+                    let file_count = files.len();
+                    // files extracted with --convert or hooks go back in their original form
+                    let temp_dir = tempfile::tempdir().unwrap();
+                    let files = convert::revert_converted(files, temp_dir.path(), &converters);
+                    let replaced = repack_archive(
+                        &game_dir.join(&archive_name),
+                        files,
+                        no_save,
+                        in_place,
+                        backup,
+                        &selector,
+                        &archive_options,
+                    );
+                    if replaced < file_count {
+                        eprintln!(
+                            "warning: {} files in {} don't match any entry",
+                            file_count - replaced,
+                            archive_patch_dir.display()
+                        );
+                    }
+                    replaced
+                });
+                report.archives.push((archive_name, result));
+            }
+
+            if report.archives.is_empty() {
+                println!(
+                    "{} has nothing for the archives in {}",
+                    patch_dir.display(),
+                    game_dir.display()
+                );
+                return;
+            }
+            println!("{report}");
+            assert_eq!(report.failures(), 0, "some archives failed to repack");
+        }
+        Cmd::Cat {
+            archive_path,
+            entries,
+            selection,
+        } => {
+            assert!(archive_path.is_file());
+            let mut reader = BufReader::new(File::open(&archive_path).unwrap());
+            let archive = open_archive_with(&mut reader, &archive_options);
+            let selection = archive.select(&mut reader, &selection.selector(&entries, &config));
+            warn_unmatched(&selection);
+
+            let mut stdout = BufWriter::new(io::stdout().lock());
+            for &entry in &selection.entries {
+                archive.extract_entry(&mut reader, entry, &mut stdout);
             }
         }
         Cmd::Repack {
             archive_path,
             rpk_files,
             no_save,
+            in_place,
+            backup,
+            selection,
+        } => {
+            // files extracted with --convert or hooks go back in their original form
+            let temp_dir = tempfile::tempdir().unwrap();
+            let converters = config.register_hooks(config.converters());
+            let rpk_files = convert::revert_converted(rpk_files, temp_dir.path(), &converters);
+            repack_archive(
+                &archive_path,
+                rpk_files,
+                no_save,
+                in_place,
+                backup,
+                &selection.selector(&[], &config),
+                &archive_options,
+            );
+        }
+        Cmd::Compact {
+            archive_path,
+            dry_run,
+            sort,
+            no_save,
         } => {
             assert!(archive_path.is_file());
+            let archive_len = archive_path.metadata().unwrap().len();
+            let mut reader = BufReader::new(File::open(&archive_path).unwrap());
+            let mut archive = open_archive_with(&mut reader, &archive_options);
+            // refuse before anything is written, not halfway through
+            assert!(
+                !matches!(sort, Some(SortOrder::Name)) || archive.supports_sort_by_name(),
+                "{} entries are referenced by their position, so they can't be sorted by name",
+                detect_format(&mut reader).unwrap().name.to_uppercase()
+            );
+
+            let unused = archive.unused_space(archive_len);
+            println!(
+                "{} of {} unused ({:.1}%)",
+                ByteSize::b(unused),
+                ByteSize::b(archive_len),
+                100.0 * unused as f64 / archive_len as f64
+            );
+            if dry_run || (unused == 0 && sort.is_none()) {
+                return;
+            }
+
+            match sort {
+                Some(SortOrder::Id) => archive.sort_by_id(),
+                Some(SortOrder::Name) => archive.sort_by_name(),
+                None => {}
+            }
+
+            drop(reader);
             let orig_path = append_to_path(&archive_path, ".orig");
             fs::rename(&archive_path, &orig_path).unwrap();
 
             let mut orig_reader = BufReader::new(File::open(&orig_path).unwrap());
-            let mpk = MagesArchive::build(&mut orig_reader);
-            let mut rpk_writer = BufWriter::new(File::create(&archive_path).unwrap());
-
-            mpk.repack_entries(&mut orig_reader, &mut rpk_writer, &rpk_files);
+            let mut compact_writer = BufWriter::new(File::create(&archive_path).unwrap());
+            archive.compact(&mut orig_reader, &mut compact_writer);
 
             if no_save {
                 fs::remove_file(&orig_path).unwrap();
             }
         }
+        Cmd::Stats { archive_path, json } => {
+            assert!(archive_path.is_file());
+            let archive_len = archive_path.metadata().unwrap().len();
+            let mut reader = BufReader::new(File::open(&archive_path).unwrap());
+            let archive = open_archive_with(&mut reader, &archive_options);
+            let mut stats = ArchiveStats::new(archive.as_ref(), archive_len);
+            stats.padded_entry_table = config
+                .profile
+                .as_ref()
+                .is_some_and(|profile| profile.has_padded_entry_table(&archive_path));
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+            } else {
+                print!("{stats}");
+            }
+        }
+        Cmd::Convert {
+            archive_path,
+            endian,
+            no_save,
+        } => {
+            assert!(archive_path.is_file());
+            let mut archive = BufReader::new(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&archive_path)
+                    .unwrap(),
+            );
+            let format = detect_format(&mut archive).expect("unrecognized archive format");
+            assert_eq!(format.name, "mpk", "only MPK archives can be converted");
+
+            let mut mpk_archive = MagesArchive::build_with(
+                &mut archive,
+                archive_options.mpk_layout.as_ref(),
+                archive_options.mpk_name_encoding,
+            );
+            if mpk_archive.endian() == endian {
+                println!("archive is already {endian}-endian");
+                return;
+            }
+
+            if !no_save {
+                fs::copy(&archive_path, append_to_path(&archive_path, ".orig")).unwrap();
+            }
+            let mut archive = BufWriter::new(archive.into_inner());
+            mpk_archive.convert_endian(&mut archive, endian);
+        }
+        Cmd::Script { command } => match command {
+            ScriptCmd::Disasm {
+                script,
+                instructions,
+                charset,
+                output,
+            } => {
+                let data = read_file_or_entry(&script, &archive_options);
+                let instructions = instructions
+                    .as_deref()
+                    .or(config.instructions.as_deref())
+                    .map(InstructionSet::for_game)
+                    .unwrap_or_default();
+                let charset = charset
+                    .as_deref()
+                    .or(config.charset.as_deref())
+                    .map(Charset::for_game);
+                let text = ungelify::sc3::disassemble(
+                    &Sc3Script::parse(&data),
+                    &instructions,
+                    charset.as_ref(),
+                );
+
+                match output {
+                    Some(path) => fs::write(path, text).unwrap(),
+                    None => io::stdout().lock().write_all(text.as_bytes()).unwrap(),
+                }
+            }
+            ScriptCmd::Asm {
+                input,
+                instructions,
+                output,
+                repack,
+                no_save,
+            } => {
+                let text = fs::read_to_string(&input).unwrap();
+                let instructions = instructions
+                    .as_deref()
+                    .or(config.instructions.as_deref())
+                    .map(InstructionSet::for_game)
+                    .unwrap_or_default();
+                let script = ungelify::sc3::assemble(&text, &instructions)
+                    .unwrap_or_else(|e| panic!("failed to assemble {}: {e}", input.display()));
+
+                let output = output.unwrap_or_else(|| input.with_extension("SCX"));
+                fs::write(&output, script.to_bytes()).unwrap();
+
+                if let Some(archive_path) = repack {
+                    repack_archive(
+                        &archive_path,
+                        vec![output],
+                        no_save,
+                        false,
+                        false,
+                        &EntrySelector::all(),
+                        &archive_options,
+                    );
+                }
+            }
+        },
+        Cmd::Strings { command } => match command {
+            StringsCmd::Export {
+                archive_path,
+                format,
+                charset,
+                output,
+            } => {
+                assert!(archive_path.is_file());
+                let charset = charset
+                    .as_deref()
+                    .or(config.charset.as_deref())
+                    .map(Charset::for_game)
+                    .unwrap_or_default();
+                let lines = read_scripts(&archive_path, &archive_options)
+                    .into_iter()
+                    .flat_map(|(name, script)| {
+                        script
+                            .strings
+                            .iter()
+                            .enumerate()
+                            .map(|(i, string)| StringLine {
+                                id: string_id(&name, i),
+                                source: text::decode(string, &charset),
+                                translation: String::new(),
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>();
+
+                match output {
+                    Some(path) => {
+                        let mut writer = BufWriter::new(File::create(path).unwrap());
+                        ungelify::sc3::strings::write_lines(&mut writer, &lines, format);
+                        writer.flush().unwrap();
+                    }
+                    None => ungelify::sc3::strings::write_lines(
+                        &mut io::stdout().lock(),
+                        &lines,
+                        format,
+                    ),
+                }
+            }
+            StringsCmd::Import {
+                archive_path,
+                translations,
+                format,
+                charset,
+                max_line_len,
+                no_save,
+            } => {
+                assert!(archive_path.is_file());
+                let charset = charset
+                    .as_deref()
+                    .or(config.charset.as_deref())
+                    .map(Charset::for_game)
+                    .unwrap_or_default();
+                let max_line_len = max_line_len.or(config.max_line_len);
+                let mut translated = read_translations(&translations, format)
+                    .into_iter()
+                    .filter(|line| !line.translation.is_empty())
+                    .map(|line| (line.id, line.translation))
+                    .collect::<HashMap<_, _>>();
+
+                let mut scripts = read_scripts(&archive_path, &archive_options);
+                let mut errors = Vec::new();
+                let mut missing_chars = Vec::new();
+                let mut missing = 0;
+                let mut changed_scripts = Vec::new();
+                for (name, script) in &mut scripts {
+                    let mut changed = false;
+                    for (i, string) in script.strings.iter_mut().enumerate() {
+                        let id = string_id(name, i);
+                        let Some(translation) = translated.remove(&id) else {
+                            // nothing to translate in empty strings
+                            if text::tokenize(string).is_empty() {
+                                continue;
+                            }
+                            missing += 1;
+                            eprintln!("warning: {id} has no translation");
+                            continue;
+                        };
+                        let encoded = match encode_translation(
+                            &id,
+                            &translation,
+                            &charset,
+                            max_line_len,
+                            &mut missing_chars,
+                        ) {
+                            Ok(encoded) => encoded,
+                            Err(e) => {
+                                errors.push(e);
+                                continue;
+                            }
+                        };
+                        if encoded != *string {
+                            *string = encoded;
+                            changed = true;
+                        }
+                    }
+                    if changed {
+                        changed_scripts.push(name.clone());
+                    }
+                }
+
+                let mut unknown = translated.into_keys().collect::<Vec<_>>();
+                unknown.sort();
+                for id in &unknown {
+                    eprintln!("warning: {id} doesn't match any string in the archive");
+                }
+                report_encode_errors(&errors, &missing_chars);
+                assert!(
+                    errors.is_empty(),
+                    "failed to encode {} strings, nothing was repacked",
+                    errors.len()
+                );
+
+                if !changed_scripts.is_empty() {
+                    let temp_dir = tempfile::tempdir().unwrap();
+                    let rpk_files = scripts
+                        .iter()
+                        .filter(|(name, _)| changed_scripts.contains(name))
+                        .map(|(name, script)| {
+                            // keep the entry's directories so same-named scripts don't collide
+                            let path = temp_dir.path().join(name);
+                            fs::create_dir_all(path.parent().unwrap()).unwrap();
+                            fs::write(&path, script.to_bytes()).unwrap();
+                            path
+                        })
+                        .collect();
+                    repack_archive(
+                        &archive_path,
+                        rpk_files,
+                        no_save,
+                        false,
+                        false,
+                        &EntrySelector::all(),
+                        &archive_options,
+                    );
+                }
+                println!(
+                    "rebuilt {} scripts; {missing} strings untranslated, {} unknown IDs",
+                    changed_scripts.len(),
+                    unknown.len()
+                );
+            }
+            StringsCmd::Check {
+                translations,
+                format,
+                charset,
+                max_line_len,
+            } => {
+                let charset = charset
+                    .as_deref()
+                    .or(config.charset.as_deref())
+                    .map(Charset::for_game)
+                    .unwrap_or_default();
+                let max_line_len = max_line_len.or(config.max_line_len);
+                let lines = read_translations(&translations, format);
+                let mut errors = Vec::new();
+                let mut missing_chars = Vec::new();
+                let mut untranslated = 0;
+                for line in &lines {
+                    if line.translation.is_empty() {
+                        untranslated += 1;
+                        continue;
+                    }
+                    if let Err(e) = encode_translation(
+                        &line.id,
+                        &line.translation,
+                        &charset,
+                        max_line_len,
+                        &mut missing_chars,
+                    ) {
+                        errors.push(e);
+                    }
+                }
+
+                report_encode_errors(&errors, &missing_chars);
+                println!("{} strings, {untranslated} untranslated", lines.len());
+                assert!(
+                    errors.is_empty(),
+                    "failed to encode {} strings",
+                    errors.len()
+                );
+            }
+        },
+        Cmd::Sprite { command } => match command {
+            SpriteCmd::Info {
+                archive_path,
+                sprite,
+            } => {
+                let (layout, atlas, _) = read_sprite(&archive_path, &sprite, &archive_options);
+                println!(
+                    "{sprite}: {} states, {} chunks, {}x{} atlas\n",
+                    layout.states.len(),
+                    layout.chunks.len(),
+                    atlas.width,
+                    atlas.height
+                );
+                print!("{layout}");
+            }
+            SpriteCmd::Render {
+                archive_path,
+                sprite,
+                states,
+                output,
+            } => {
+                let (layout, atlas, _) = read_sprite(&archive_path, &sprite, &archive_options);
+                let image = layout.render(&atlas, &states);
+                let output = output.unwrap_or_else(|| {
+                    let states = states.iter().map(ToString::to_string).collect::<Vec<_>>();
+                    PathBuf::from(format!("{sprite}_{}.png", states.join("+")))
+                });
+                image.write_png(BufWriter::new(File::create(&output).unwrap()));
+                println!(
+                    "wrote {}x{} image to {}",
+                    image.width,
+                    image.height,
+                    output.display()
+                );
+            }
+            SpriteCmd::Rebuild {
+                archive_path,
+                sprite,
+                images,
+                no_save,
+            } => {
+                let (layout, atlas, [layout_name, atlas_name]) =
+                    read_sprite(&archive_path, &sprite, &archive_options);
+                let edits = images
+                    .iter()
+                    .map(|(state, path)| (*state, Image::from_png(&fs::read(path).unwrap())))
+                    .collect::<Vec<_>>();
+                let (layout, atlas) = layout.rebuild(&atlas, &edits);
+
+                let temp_dir = tempfile::tempdir().unwrap();
+                let rpk_files = stage_replacements(
+                    temp_dir.path(),
+                    &[
+                        (&atlas_name, &atlas.to_png()),
+                        (&layout_name, &layout.to_bytes()),
+                    ],
+                );
+                repack_archive(
+                    &archive_path,
+                    rpk_files,
+                    no_save,
+                    false,
+                    false,
+                    &EntrySelector::all(),
+                    &archive_options,
+                );
+                println!(
+                    "rebuilt {sprite} from {} states into a {}x{} atlas",
+                    edits.len(),
+                    atlas.width,
+                    atlas.height
+                );
+            }
+        },
+        Cmd::Game { command } => match command {
+            GameCmd::List => {
+                let profiles = GameProfile::known();
+                if profiles.is_empty() {
+                    println!(
+                        "No built-in game profiles yet, pass a profile file to --game instead."
+                    );
+                }
+                for profile in profiles {
+                    println!(
+                        "{:<12}{} ({} archives)",
+                        profile.id,
+                        profile.title,
+                        profile.archives.len()
+                    );
+                }
+            }
+            GameCmd::Identify { dir } => {
+                assert!(dir.is_dir(), "{} is not a directory", dir.display());
+                // a profile given with --game is checked rather than guessed
+                let profile = config
+                    .profile
+                    .clone()
+                    .filter(|_| game_given)
+                    .or_else(|| GameProfile::identify(&dir))
+                    .unwrap_or_else(|| panic!("no known game is installed in {}", dir.display()));
+
+                println!("{} ({})", profile.title, profile.id);
+                let found = profile.archives_in(&dir);
+                for archive in &profile.archives {
+                    let status = if found.contains(&archive.as_str()) {
+                        "found"
+                    } else {
+                        "missing"
+                    };
+                    println!("  {status:<9}{archive}");
+                }
+            }
+        },
+        Cmd::Config { command } => match command {
+            ConfigCmd::Show => {
+                match &config.path {
+                    Some(path) => println!("# {}", path.display()),
+                    None => println!("# no {} found", Config::FILE_NAME),
+                }
+                if let Some(profile) = &config.profile {
+                    println!("# with the game profile of {}", profile.title);
+                }
+                print!("{}", config.to_toml());
+            }
+        },
+        Cmd::Font { command } => match command {
+            FontCmd::Info { font: font_args } => {
+                let (font, _) = font_args.read(&archive_options);
+                println!(
+                    "Atlas:      {}x{}, {} columns of {}px cells",
+                    font.atlas.width,
+                    font.atlas.height,
+                    font.columns(),
+                    font.cell_size
+                );
+                println!("Capacity:   {} glyphs", font.capacity());
+                println!("Widths:     {} glyphs", font.widths.len());
+            }
+            FontCmd::Build {
+                font: font_args,
+                ttf,
+                charset,
+                size,
+                glyphs,
+                no_save,
+            } => {
+                let (mut font, [atlas_name, widths_name]) = font_args.read(&archive_options);
+                let charset =
+                    Charset::for_game(charset.as_deref().or(config.charset.as_deref()).expect(
+                        "no charset given, pass --charset or set charset in ungelify.toml",
+                    ));
+                let size = size.unwrap_or(font_args.cell_size as f32);
+                let report = font
+                    .render_ttf(&fs::read(&ttf).unwrap(), size, &charset, glyphs.as_ref())
+                    .unwrap_or_else(|e| panic!("invalid font {}: {e}", ttf.display()));
+                if !report.missing.is_empty() {
+                    eprintln!(
+                        "warning: {} has no glyphs for {}, left as they were",
+                        ttf.display(),
+                        report.missing.iter().collect::<String>()
+                    );
+                }
+
+                let temp_dir = tempfile::tempdir().unwrap();
+                let rpk_files = stage_replacements(
+                    temp_dir.path(),
+                    &[
+                        (&atlas_name, &font.atlas.to_png()),
+                        (&widths_name, &font.widths),
+                    ],
+                );
+                repack_archive(
+                    &font_args.archive_path,
+                    rpk_files,
+                    no_save,
+                    false,
+                    false,
+                    &EntrySelector::all(),
+                    &archive_options,
+                );
+                println!(
+                    "drew {} glyphs into a {}x{} atlas",
+                    report.rendered, font.atlas.width, font.atlas.height
+                );
+            }
+        },
     }
 }
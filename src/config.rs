@@ -0,0 +1,385 @@
+// ungelify's TOML config, `ungelify.toml`. It's looked for in the current directory and the
+// ones above it, so a whole mod project can share one, and holds defaults for flags that would
+// otherwise be repeated on every invocation:
+//
+//   game = "cc"
+//   mpk_layout = "v2"
+//   mpk_name_encoding = "shift-jis"
+//   codecs = ["2=xor:0x5a"]
+//   charset = "charset.txt"
+//   instructions = "cc.ins"
+//   npa_key = "game.key"
+//   max_line_len = 40
+//   extract_dir = "extracted"
+//   exclude = ["*.ogg"]
+//
+// Flags given on the command line override the config, and the config overrides the game's
+// profile (see `crate::game`). Relative paths are relative to the config file.
+//
+// The config can also hook external tools to entries:
+//
+//   [[hook]]
+//   glob = "*.dds"
+//   extension = "png"
+//   on_extract = "mytool decode {in} {out}"
+//   on_repack = "mytool encode {in} {out}"
+//
+// Extracting writes matching entries through `on_extract` as `NAME.png` and records them in the
+// manifest like any converted file, and repacking runs `on_repack` to turn them back. Globs match
+// the whole entry name, ignoring case, and hooks listed earlier win over later ones and over the
+// built-in converters.
+
+use crate::archive::OpenOptions;
+use crate::convert::{Converters, ExternalCommand, Match};
+use crate::game::GameProfile;
+use crate::mpk::{codec, Codecs, EntryLayout, NameEncoding};
+use crate::sc3::{Charset, InstructionSet};
+use crate::select;
+use globset::GlobBuilder;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The file the config was read from, if any.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    /// The profile of `game`, if it has one.
+    #[serde(skip)]
+    pub profile: Option<GameProfile>,
+
+    /// `--game`, either a game profile or just an NPA key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub game: Option<String>,
+    /// `--mpk-layout`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mpk_layout: Option<String>,
+    /// `--mpk-name-encoding`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mpk_name_encoding: Option<String>,
+    /// `--codec`, registered before the ones given on the command line.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub codecs: Vec<String>,
+    /// The charset for the `strings` and `font` commands.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+    /// The instruction set for the `script` commands.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    /// The key for encrypted NPA archives, a built-in key's name or a key file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub npa_key: Option<String>,
+    /// `strings --max-line-len`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_line_len: Option<usize>,
+    /// Where `extract` puts each archive's directory, instead of next to the archive.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extract_dir: Option<PathBuf>,
+    /// Entries left out of every selection, unless `--exclude` is given.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+
+    #[serde(default, rename = "hook", skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hook {
+    pub glob: String,
+    /// What the hook is recorded as in manifests. Defaults to the glob.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The extension appended to the names of converted files.
+    pub extension: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_extract: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_repack: Option<String>,
+}
+
+impl Config {
+    pub const FILE_NAME: &'static str = "ungelify.toml";
+
+    /// Finds the config file for `dir`: the closest `ungelify.toml` in it or a directory above.
+    #[must_use]
+    pub fn discover(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|dir| dir.join(Self::FILE_NAME))
+            .find(|path| path.is_file())
+    }
+
+    #[must_use]
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("failed to read config {}: {e}", path.display()));
+        let mut config =
+            Self::parse(&text).unwrap_or_else(|e| panic!("invalid config {}: {e}", path.display()));
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        config.extract_dir = config.extract_dir.map(|extract_dir| dir.join(extract_dir));
+        // charsets, instruction sets and NPA keys are either built-in names or files
+        for value in [
+            &mut config.charset,
+            &mut config.instructions,
+            &mut config.npa_key,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            let file = dir.join(&*value);
+            if file.is_file() {
+                *value = file.display().to_string();
+            }
+        }
+        config.path = Some(path.to_path_buf());
+        config
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        // catch bad settings now rather than halfway through an extraction
+        if let Some(layout) = &config.mpk_layout {
+            layout.parse::<EntryLayout>()?;
+        }
+        if let Some(encoding) = &config.mpk_name_encoding {
+            encoding.parse::<NameEncoding>()?;
+        }
+        for assignment in &config.codecs {
+            codec::parse_assignment(assignment)?;
+        }
+        for pattern in &config.exclude {
+            select::parse_pattern(pattern)?;
+        }
+        config.hook_converters()?;
+        Ok(config)
+    }
+
+    /// Fills in the settings the config leaves out from `profile`, whose codecs go first so the
+    /// config's own win.
+    pub fn apply_profile(&mut self, profile: GameProfile) {
+        self.game = Some(profile.id.clone());
+        self.mpk_layout = self
+            .mpk_layout
+            .take()
+            .or_else(|| profile.mpk_layout.clone());
+        self.mpk_name_encoding = self
+            .mpk_name_encoding
+            .take()
+            .or_else(|| profile.mpk_name_encoding.clone());
+        self.codecs = profile
+            .codecs
+            .iter()
+            .cloned()
+            .chain(self.codecs.drain(..))
+            .collect();
+        self.charset = self.charset.take().or_else(|| profile.charset.clone());
+        self.instructions = self
+            .instructions
+            .take()
+            .or_else(|| profile.instructions.clone());
+        self.npa_key = self.npa_key.take().or_else(|| profile.npa_key.clone());
+        self.profile = Some(profile);
+    }
+
+    /// The settings for opening archives.
+    #[must_use]
+    pub fn open_options(&self) -> OpenOptions {
+        let mut codecs = Codecs::default();
+        for assignment in &self.codecs {
+            let (indicator, codec) = codec::parse_assignment(assignment).unwrap();
+            codecs.register(indicator, codec);
+        }
+        OpenOptions {
+            // without a profile, `game` can just name the key
+            npa_key: self
+                .npa_key
+                .clone()
+                .or_else(|| self.profile.is_none().then(|| self.game.clone()).flatten()),
+            mpk_layout: self
+                .mpk_layout
+                .as_ref()
+                .map(|layout| layout.parse().unwrap()),
+            mpk_name_encoding: self
+                .mpk_name_encoding
+                .as_ref()
+                .map(|encoding| encoding.parse().unwrap())
+                .unwrap_or_default(),
+            codecs,
+        }
+    }
+
+    /// The config as TOML, as `config show` prints it.
+    #[must_use]
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("failed to serialize config")
+    }
+
+    /// The built-in converters, set up with the config's instruction set and charset.
+    #[must_use]
+    pub fn converters(&self) -> Converters {
+        Converters::new(
+            self.instructions
+                .as_deref()
+                .map(InstructionSet::for_game)
+                .unwrap_or_default(),
+            self.charset.as_deref().map(Charset::for_game),
+        )
+    }
+
+    /// Registers the hooks on top of `converters`.
+    #[must_use]
+    pub fn register_hooks(&self, mut converters: Converters) -> Converters {
+        let hooks = self.hook_converters().unwrap();
+        // registering puts converters first, so go backwards to keep the config's order
+        for (rule, converter) in hooks.into_iter().rev() {
+            converters.register(rule, converter);
+        }
+        converters
+    }
+
+    fn hook_converters(&self) -> Result<Vec<(Match, Arc<ExternalCommand>)>, String> {
+        self.hooks
+            .iter()
+            .map(|hook| {
+                let name = hook.name.as_deref().unwrap_or(&hook.glob);
+                let glob = GlobBuilder::new(&hook.glob)
+                    .case_insensitive(true)
+                    .literal_separator(false)
+                    .build()
+                    .map_err(|e| format!("hook '{name}': {e}"))?
+                    .compile_matcher();
+                let command = ExternalCommand::new(
+                    name,
+                    &hook.extension,
+                    hook.on_extract.as_deref(),
+                    hook.on_repack.as_deref(),
+                )
+                .map_err(|e| format!("hook '{name}': {e}"))?;
+                Ok((Match::Glob(glob), Arc::new(command)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const CONFIG: &str = r#"
+        game = "sg"
+        mpk_layout = "v1"
+        codecs = ["2=xor:0x5a"]
+        charset = "charset.txt"
+        npa_key = "some-key"
+        max_line_len = 40
+        extract_dir = "extracted"
+        exclude = ["*.ogg"]
+
+        [[hook]]
+        glob = "*.dds"
+        extension = "png"
+        on_extract = "mytool decode {in} {out}"
+    "#;
+
+    #[test]
+    fn reads_back_what_it_shows() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.game.as_deref(), Some("sg"));
+        assert_eq!(config.max_line_len, Some(40));
+        assert_eq!(config.hooks.len(), 1);
+
+        let shown = config.to_toml();
+        assert_eq!(Config::parse(&shown).unwrap().to_toml(), shown);
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        for text in [
+            "gmae = \"sg\"",
+            "mpk_layout = \"v9\"",
+            "mpk_name_encoding = \"latin-1\"",
+            "codecs = [\"2=rot13\"]",
+            "exclude = [\"re:SG0[12\"]",
+            "[[hook]]\nglob = \"[\"\nextension = \"png\"",
+            "[[hook]]\nglob = \"*.dds\"\nextension = \"png\"\non_repack = \"mytool {in}\"",
+        ] {
+            assert!(Config::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("mod/scripts");
+        fs::create_dir_all(&nested).unwrap();
+        let path = dir.path().join(Config::FILE_NAME);
+        fs::write(&path, CONFIG).unwrap();
+        fs::write(dir.path().join("charset.txt"), "abc").unwrap();
+
+        assert_eq!(Config::discover(&nested), Some(path.clone()));
+        let config = Config::from_file(&path);
+        assert_eq!(config.path, Some(path));
+        assert_eq!(config.extract_dir, Some(dir.path().join("extracted")));
+        assert_eq!(
+            config.charset,
+            Some(dir.path().join("charset.txt").display().to_string())
+        );
+        // no file by that name, so it's a built-in key
+        assert_eq!(config.npa_key.as_deref(), Some("some-key"));
+    }
+
+    #[test]
+    fn open_options_come_from_the_settings() {
+        let options = Config::parse(CONFIG).unwrap().open_options();
+        assert_eq!(options.npa_key.as_deref(), Some("some-key"));
+        assert_eq!(options.mpk_layout, Some(EntryLayout::V1));
+        assert_eq!(options.codecs.get(1).unwrap().name(), "zlib");
+        assert_eq!(options.codecs.get(2).unwrap().name(), "xor:0x5a");
+    }
+
+    #[test]
+    fn the_config_wins_over_the_profile() {
+        let mut config = Config::parse(CONFIG).unwrap();
+        let profile = GameProfile::parse(
+            r#"
+            id = "test"
+            title = "Test;Game"
+            mpk_layout = "v2"
+            mpk_name_encoding = "shift-jis"
+            codecs = ["1=zlib"]
+            charset = "profile-charset.txt"
+            instructions = "test.ins"
+            npa_key = "test.key"
+            "#,
+        )
+        .unwrap();
+        config.apply_profile(profile);
+
+        assert_eq!(config.game.as_deref(), Some("test"));
+        assert_eq!(config.mpk_layout.as_deref(), Some("v1"));
+        assert_eq!(config.codecs, ["1=zlib", "2=xor:0x5a"]);
+        assert_eq!(config.charset.as_deref(), Some("charset.txt"));
+        assert_eq!(config.instructions.as_deref(), Some("test.ins"));
+        assert_eq!(config.mpk_name_encoding.as_deref(), Some("shift-jis"));
+
+        let options = config.open_options();
+        assert_eq!(options.npa_key.as_deref(), Some("some-key"));
+        assert_eq!(options.mpk_name_encoding, NameEncoding::ShiftJis);
+    }
+
+    #[test]
+    fn without_a_profile_game_names_the_npa_key() {
+        let config = Config::parse("game = \"some-key\"").unwrap();
+        assert_eq!(config.open_options().npa_key.as_deref(), Some("some-key"));
+        assert_eq!(config.open_options().mpk_name_encoding, NameEncoding::Utf8);
+
+        let mut config = config;
+        config.apply_profile(GameProfile::parse("id = \"test\"\ntitle = \"Test;Game\"").unwrap());
+        assert_eq!(config.open_options().npa_key, None);
+    }
+}
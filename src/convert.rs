@@ -0,0 +1,516 @@
+// Entry converters. Some entries are much easier to work with in another form, e.g. sprite
+// layouts as JSON or scripts as text, so extraction can convert them on the way out and repacking
+// turns them back. Converters are picked by entry extension first and sniffed type second.
+//
+// Extracting with converters writes a manifest next to the extracted files recording which
+// converter made each file from which entry, so repacking knows how to undo it without being told.
+//
+// Besides the built-in converters, a config file can hook external tools to entries by glob (see
+// `crate::config`).
+
+mod external;
+
+pub use external::ExternalCommand;
+
+use crate::archive::{Archive, ArchiveEntry, ReadSeek};
+use crate::lay::Layout;
+use crate::sc3::{Charset, InstructionSet, Sc3Script};
+use crate::select::Selection;
+use crate::sniff::FileType;
+use globset::GlobMatcher;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Converts extracted entries into a friendlier form and back again.
+pub trait Converter: fmt::Debug + Send + Sync {
+    /// The name the converter is recorded under in manifests.
+    fn name(&self) -> String;
+
+    /// The extension appended to the entry name for the converted file.
+    fn extension(&self) -> String;
+
+    /// Converts the contents of the entry `name`.
+    fn convert(&self, name: &str, data: &[u8]) -> Result<Vec<u8>, String>;
+
+    /// Turns a converted file back into the original contents of the entry `name`.
+    fn revert(&self, name: &str, data: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+/// Sprite layouts as pretty-printed JSON.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LayJson;
+
+impl Converter for LayJson {
+    fn name(&self) -> String {
+        "lay-json".to_string()
+    }
+
+    fn extension(&self) -> String {
+        "json".to_string()
+    }
+
+    fn convert(&self, _name: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        serde_json::to_vec_pretty(&Layout::parse(data)).map_err(|e| e.to_string())
+    }
+
+    fn revert(&self, _name: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let layout: Layout = serde_json::from_slice(data).map_err(|e| e.to_string())?;
+        Ok(layout.to_bytes())
+    }
+}
+
+/// SC3 scripts as disassembly, with strings annotated with their text if there's a charset.
+/// Reverting reads the instruction set back from the header `disassemble()` writes.
+#[derive(Debug, Clone, Default)]
+pub struct ScxText {
+    pub instructions: InstructionSet,
+    pub charset: Option<Charset>,
+}
+
+impl ScxText {
+    const INSTRUCTION_SET_HEADER: &'static str = "; instruction set: ";
+}
+
+impl Converter for ScxText {
+    fn name(&self) -> String {
+        "scx-text".to_string()
+    }
+
+    fn extension(&self) -> String {
+        "txt".to_string()
+    }
+
+    fn convert(&self, _name: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        if !data.starts_with(Sc3Script::SC3_SIG) {
+            return Err("not an SC3 script".to_string());
+        }
+        let script = Sc3Script::parse(data);
+        Ok(
+            crate::sc3::disassemble(&script, &self.instructions, self.charset.as_ref())
+                .into_bytes(),
+        )
+    }
+
+    fn revert(&self, _name: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let text = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+        let instructions = match text
+            .lines()
+            .find_map(|line| line.strip_prefix(Self::INSTRUCTION_SET_HEADER))
+        {
+            Some(name) if name == self.instructions.name => self.instructions.clone(),
+            Some(name) if name != InstructionSet::default().name => InstructionSet::for_game(name),
+            _ => InstructionSet::default(),
+        };
+        Ok(crate::sc3::assemble(text, &instructions)?.to_bytes())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Match {
+    /// Entry names ending in `.EXTENSION`, ignoring case.
+    Extension(String),
+    /// Entry names matching a glob.
+    Glob(GlobMatcher),
+    Type(FileType),
+}
+
+impl Match {
+    fn matches_name(&self, name: &str) -> bool {
+        match self {
+            Self::Extension(ext) => Path::new(name)
+                .extension()
+                .is_some_and(|name_ext| name_ext.eq_ignore_ascii_case(ext)),
+            Self::Glob(glob) => glob.is_match(name),
+            Self::Type(_) => false,
+        }
+    }
+}
+
+/// Which converter handles which entries.
+#[derive(Debug, Clone)]
+pub struct Converters {
+    rules: Vec<(Match, Arc<dyn Converter>)>,
+}
+
+impl Default for Converters {
+    fn default() -> Self {
+        Self::new(InstructionSet::default(), None)
+    }
+}
+
+impl Converters {
+    /// The built-in converters, disassembling scripts with `instructions` and `charset`.
+    #[must_use]
+    pub fn new(instructions: InstructionSet, charset: Option<Charset>) -> Self {
+        let mut converters = Self::none();
+        let lay: Arc<dyn Converter> = Arc::new(LayJson);
+        let scx: Arc<dyn Converter> = Arc::new(ScxText {
+            instructions,
+            charset,
+        });
+        converters.register(Match::Extension("lay".to_string()), lay.clone());
+        converters.register(Match::Type(FileType::Lay), lay);
+        converters.register(Match::Extension("scx".to_string()), scx.clone());
+        converters.register(Match::Type(FileType::Sc3), scx);
+        converters
+    }
+
+    /// No converters at all, not even the built-in ones.
+    #[must_use]
+    pub const fn none() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Makes `converter` handle the entries `rule` matches, taking priority over any converter
+    /// registered for them before.
+    pub fn register(&mut self, rule: Match, converter: Arc<dyn Converter>) {
+        self.rules.insert(0, (rule, converter));
+    }
+
+    /// Looks a converter up by the name it's recorded under in manifests.
+    #[must_use]
+    pub fn by_name(&self, name: &str) -> Option<&Arc<dyn Converter>> {
+        self.rules
+            .iter()
+            .map(|(_, converter)| converter)
+            .find(|converter| converter.name() == name)
+    }
+
+    /// The converter for an entry named `name`. `sniff` is only called if no converter is
+    /// registered for the entry's name.
+    pub fn find<F: FnOnce() -> FileType>(
+        &self,
+        name: &str,
+        sniff: F,
+    ) -> Option<&Arc<dyn Converter>> {
+        if let Some((_, converter)) = self.rules.iter().find(|(rule, _)| rule.matches_name(name)) {
+            return Some(converter);
+        }
+
+        let file_type = sniff();
+        self.rules
+            .iter()
+            .find(|(rule, _)| matches!(rule, Match::Type(t) if *t == file_type))
+            .map(|(_, converter)| converter)
+    }
+}
+
+/// A file made by a converter from an entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub entry: String,
+    /// The converted file, relative to the manifest.
+    pub file: String,
+    pub converter: String,
+}
+
+/// The record of which extracted files were converted, kept in the directory they were
+/// extracted to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub const FILE_NAME: &'static str = "ungelify-manifest.json";
+
+    /// Reads the manifest in `dir`, if there is one.
+    #[must_use]
+    pub fn read(dir: &Path) -> Option<Self> {
+        let path = dir.join(Self::FILE_NAME);
+        let data = fs::read(&path).ok()?;
+        Some(
+            serde_json::from_slice(&data)
+                .unwrap_or_else(|e| panic!("invalid manifest {}: {e}", path.display())),
+        )
+    }
+
+    pub fn write(&self, dir: &Path) {
+        let data = serde_json::to_vec_pretty(self).unwrap();
+        fs::write(dir.join(Self::FILE_NAME), data).unwrap();
+    }
+
+    /// Records `entry`, replacing any earlier record of the same entry.
+    pub fn insert(&mut self, entry: ManifestEntry) {
+        self.entries.retain(|e| e.entry != entry.entry);
+        self.entries.push(entry);
+    }
+}
+
+/// Extracts `selection` into `output_dir` like `Archive::extract_selection()`, converting entries
+/// a converter handles.
+///
+/// Converted files are recorded in the directory's manifest. Entries that fail to convert are
+/// extracted as-is with a warning.
+pub fn extract_converted(
+    archive: &dyn Archive,
+    reader: &mut dyn ReadSeek,
+    output_dir: &Path,
+    selection: &Selection<'_>,
+    converters: &Converters,
+) {
+    // entries are read in batches so converters (which may be slow external tools) run in
+    // parallel without the whole archive having to sit in memory
+    const BATCH_LEN: usize = 64;
+
+    let mut manifest = Manifest::read(output_dir).unwrap_or_default();
+    for batch in selection.entries.chunks(BATCH_LEN) {
+        let batch = batch
+            .iter()
+            .map(|&entry| (entry.name(), read_entry(archive, reader, entry)))
+            .collect::<Vec<_>>();
+        let results = batch
+            .par_iter()
+            .map(|(name, data)| {
+                let converter = converters.find(name, || {
+                    FileType::sniff(
+                        &data[..data.len().min(FileType::SNIFF_LEN)],
+                        data.len() as u64,
+                    )
+                })?;
+                Some((converter, converter.convert(name, data)))
+            })
+            .collect::<Vec<_>>();
+
+        for ((name, data), result) in batch.into_iter().zip(results) {
+            let extract_path = output_dir.join(name);
+            if let Some(parent) = extract_path.parent() {
+                fs::create_dir_all(parent).unwrap();
+            }
+            match result {
+                Some((converter, Ok(output))) => {
+                    let file = format!("{name}.{}", converter.extension());
+                    fs::write(output_dir.join(&file), output).unwrap();
+                    manifest.insert(ManifestEntry {
+                        entry: name.to_string(),
+                        file,
+                        converter: converter.name(),
+                    });
+                }
+                Some((converter, Err(e))) => {
+                    eprintln!(
+                        "warning: failed to convert '{name}' with {}, extracting it as-is: {e}",
+                        converter.name()
+                    );
+                    fs::write(&extract_path, data).unwrap();
+                }
+                None => fs::write(&extract_path, data).unwrap(),
+            }
+        }
+    }
+
+    if !manifest.entries.is_empty() {
+        manifest.write(output_dir);
+    }
+}
+
+fn read_entry(
+    archive: &dyn Archive,
+    reader: &mut dyn ReadSeek,
+    entry: &dyn ArchiveEntry,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    archive
+        .open_entry(reader, entry)
+        .read_to_end(&mut data)
+        .expect("failed to read entry from archive");
+    data
+}
+
+/// Turns any converted files in `rpk_files` back into their entries.
+///
+/// Converted files are found through the manifests in the directories above them. Reverted files
+/// are written to `temp_dir` under their entry's path; everything else is passed through as-is.
+#[must_use]
+pub fn revert_converted(
+    rpk_files: Vec<PathBuf>,
+    temp_dir: &Path,
+    converters: &Converters,
+) -> Vec<PathBuf> {
+    rpk_files
+        .into_par_iter()
+        .map(|path| {
+            // the manifest sits at the top of the extraction, the file can be further down
+            let record = path.ancestors().skip(1).find_map(|dir| {
+                let manifest = Manifest::read(dir)?;
+                manifest
+                    .entries
+                    .into_iter()
+                    .find(|record| dir.join(&record.file) == path)
+            });
+            let Some(record) = record else {
+                return path;
+            };
+
+            let converter = converters
+                .by_name(&record.converter)
+                .unwrap_or_else(|| panic!("unknown converter '{}'", record.converter));
+            let data = converter
+                .revert(&record.entry, &fs::read(&path).unwrap())
+                .unwrap_or_else(|e| panic!("failed to convert {} back: {e}", path.display()));
+            // keep the entry's directories so same-named entries don't collide
+            let reverted = temp_dir.join(&record.entry);
+            fs::create_dir_all(reverted.parent().unwrap()).unwrap();
+            fs::write(&reverted, data).unwrap();
+            reverted
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sc3::ReturnAddress;
+
+    // stands in for a real converter, its converted form is the data backwards
+    #[derive(Debug)]
+    struct Reverse;
+
+    impl Converter for Reverse {
+        fn name(&self) -> String {
+            "reverse".to_string()
+        }
+
+        fn extension(&self) -> String {
+            "rev".to_string()
+        }
+
+        fn convert(&self, _name: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+            Ok(data.iter().rev().copied().collect())
+        }
+
+        fn revert(&self, name: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+            self.convert(name, data)
+        }
+    }
+
+    fn found(converters: &Converters, name: &str, file_type: FileType) -> Option<String> {
+        converters
+            .find(name, || file_type)
+            .map(|converter| converter.name())
+    }
+
+    #[test]
+    fn converters_are_found_by_extension_before_type() {
+        let mut converters = Converters::default();
+        assert_eq!(
+            found(&converters, "ui/menu.LAY", FileType::Unknown).as_deref(),
+            Some("lay-json")
+        );
+        assert_eq!(
+            found(&converters, "menu.bin", FileType::Sc3).as_deref(),
+            Some("scx-text")
+        );
+        assert_eq!(found(&converters, "menu.bin", FileType::Unknown), None);
+
+        // later registrations win
+        converters.register(Match::Extension("lay".to_string()), Arc::new(Reverse));
+        assert_eq!(
+            found(&converters, "menu.lay", FileType::Lay).as_deref(),
+            Some("reverse")
+        );
+        assert_eq!(
+            found(&converters, "menu.bin", FileType::Lay).as_deref(),
+            Some("lay-json")
+        );
+    }
+
+    #[test]
+    fn converted_files_are_reverted_through_the_manifest() {
+        let extracted = tempfile::tempdir().unwrap();
+        fs::create_dir_all(extracted.path().join("sub")).unwrap();
+        let converted = extracted.path().join("sub/x.bin.rev");
+        let plain = extracted.path().join("y.bin");
+        fs::write(&converted, b"1x").unwrap();
+        fs::write(&plain, b"y").unwrap();
+        let mut manifest = Manifest::default();
+        manifest.insert(ManifestEntry {
+            entry: "sub/x.bin".to_string(),
+            file: "sub/x.bin.rev".to_string(),
+            converter: "reverse".to_string(),
+        });
+        manifest.write(extracted.path());
+        assert_eq!(Manifest::read(extracted.path()), Some(manifest));
+
+        let mut converters = Converters::default();
+        converters.register(Match::Extension("bin".to_string()), Arc::new(Reverse));
+        let temp_dir = tempfile::tempdir().unwrap();
+        let reverted =
+            revert_converted(vec![converted, plain.clone()], temp_dir.path(), &converters);
+
+        assert_eq!(reverted[1], plain);
+        assert!(reverted[0].starts_with(temp_dir.path()));
+        assert_eq!(fs::read(&reverted[0]).unwrap(), b"x1");
+    }
+
+    #[test]
+    fn reverted_files_keep_their_entry_paths() {
+        let extracted = tempfile::tempdir().unwrap();
+        let mut manifest = Manifest::default();
+        let mut rpk_files = Vec::new();
+        for (dir, data) in [("a", b"1x"), ("b", b"2x")] {
+            let file = format!("{dir}/x.bin.rev");
+            fs::create_dir_all(extracted.path().join(dir)).unwrap();
+            fs::write(extracted.path().join(&file), data).unwrap();
+            rpk_files.push(extracted.path().join(&file));
+            manifest.entries.push(ManifestEntry {
+                entry: format!("{dir}/x.bin"),
+                file,
+                converter: "reverse".to_string(),
+            });
+        }
+        manifest.write(extracted.path());
+
+        let mut converters = Converters::none();
+        converters.register(Match::Extension("bin".to_string()), Arc::new(Reverse));
+        let temp_dir = tempfile::tempdir().unwrap();
+        let reverted = revert_converted(rpk_files, temp_dir.path(), &converters);
+
+        assert_eq!(
+            reverted,
+            [
+                temp_dir.path().join("a/x.bin"),
+                temp_dir.path().join("b/x.bin")
+            ]
+        );
+        assert_eq!(fs::read(&reverted[0]).unwrap(), b"x1");
+        assert_eq!(fs::read(&reverted[1]).unwrap(), b"x2");
+    }
+
+    #[test]
+    fn scripts_use_the_given_instructions_and_charset() {
+        let instructions = InstructionSet::parse("test", "0210 Msg string").unwrap();
+        let charset = Charset::parse("test", "AB").unwrap();
+        let converters = Converters::new(instructions, Some(charset));
+        let script = Sc3Script {
+            labels: vec![0],
+            code: vec![0x02, 0x10, 0x00, 0x00],
+            strings: vec![vec![0x80, 0x01, 0x80, 0x00, 0xff]],
+            return_addresses: vec![ReturnAddress::Raw(0)],
+        };
+        let data = script.to_bytes();
+
+        let converter = converters
+            .find("SG01_01.SCX", || FileType::Unknown)
+            .unwrap();
+        let text = converter.convert("SG01_01.SCX", &data).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("; instruction set: test"), "{text}");
+        assert!(text.contains("    Msg string_0"), "{text}");
+        assert!(text.contains(".string 0 80 01 80 00 ff ; BA"), "{text}");
+        assert_eq!(
+            converter.revert("SG01_01.SCX", text.as_bytes()).unwrap(),
+            data
+        );
+    }
+}
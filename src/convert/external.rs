@@ -0,0 +1,241 @@
+// Converters that hand entries to an external tool. A command is a template like
+// `mytool decode {in} {out}`: ungelify writes the entry to a temporary `{in}` file, runs the
+// tool, and reads the result back from `{out}`. Commands are split on whitespace, with single or
+// double quotes around arguments that contain spaces, and run directly rather than through a
+// shell.
+
+use super::Converter;
+use std::fs;
+use std::process::Command;
+
+#[derive(Debug, Clone)]
+pub struct ExternalCommand {
+    name: String,
+    extension: String,
+    on_extract: Option<Vec<String>>,
+    on_repack: Option<Vec<String>>,
+}
+
+impl ExternalCommand {
+    /// A converter running `on_extract` to convert entries and `on_repack` to revert them. Either
+    /// can be left out if the tool only works one way.
+    pub fn new(
+        name: &str,
+        extension: &str,
+        on_extract: Option<&str>,
+        on_repack: Option<&str>,
+    ) -> Result<Self, String> {
+        let parse = |template: Option<&str>| template.map(split_command).transpose();
+        Ok(Self {
+            name: name.to_string(),
+            extension: extension.to_string(),
+            on_extract: parse(on_extract)?,
+            on_repack: parse(on_repack)?,
+        })
+    }
+
+    // runs `command` on `data`, naming the temporary files so tools that look at extensions
+    // see the ones they expect
+    fn run(
+        &self,
+        command: &[String],
+        data: &[u8],
+        in_name: &str,
+        out_name: &str,
+    ) -> Result<Vec<u8>, String> {
+        let dir = tempfile::tempdir().map_err(|e| e.to_string())?;
+        let in_path = dir.path().join(in_name);
+        let out_path = dir.path().join(out_name);
+        fs::write(&in_path, data).map_err(|e| e.to_string())?;
+
+        let args = command
+            .iter()
+            .map(|arg| {
+                arg.replace("{in}", &in_path.to_string_lossy())
+                    .replace("{out}", &out_path.to_string_lossy())
+            })
+            .collect::<Vec<_>>();
+        let output = Command::new(&args[0])
+            .args(&args[1..])
+            .output()
+            .map_err(|e| format!("failed to run '{}': {e}", args[0]))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!(
+                "'{}' failed ({}): {}",
+                self.name,
+                output.status,
+                stderr.trim()
+            ));
+        }
+
+        fs::read(&out_path).map_err(|e| format!("'{}' didn't write {{out}}: {e}", self.name))
+    }
+}
+
+impl Converter for ExternalCommand {
+    fn name(&self) -> String {
+        format!("hook:{}", self.name)
+    }
+
+    fn extension(&self) -> String {
+        self.extension.clone()
+    }
+
+    fn convert(&self, name: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let command = self
+            .on_extract
+            .as_ref()
+            .ok_or_else(|| format!("'{}' has no on_extract command", self.name))?;
+        let entry_name = base_name(name);
+        self.run(
+            command,
+            data,
+            entry_name,
+            &format!("{entry_name}.{}", self.extension),
+        )
+    }
+
+    fn revert(&self, name: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let command = self
+            .on_repack
+            .as_ref()
+            .ok_or_else(|| format!("'{}' has no on_repack command", self.name))?;
+        let entry_name = base_name(name);
+        self.run(
+            command,
+            data,
+            &format!("{entry_name}.{}", self.extension),
+            entry_name,
+        )
+    }
+}
+
+fn base_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap()
+}
+
+fn split_command(template: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut arg = None::<String>;
+    let mut quote = None;
+    for c in template.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => args.extend(arg.take()),
+            (_, c) => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(format!("unterminated quote in '{template}'"));
+    }
+    args.extend(arg);
+
+    if args.is_empty() {
+        return Err("empty command".to_string());
+    }
+    for placeholder in ["{in}", "{out}"] {
+        if !args.iter().any(|arg| arg.contains(placeholder)) {
+            return Err(format!("'{template}' doesn't use {placeholder}"));
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(on_extract: &str, on_repack: &str) -> ExternalCommand {
+        ExternalCommand::new("test", "out", Some(on_extract), Some(on_repack)).unwrap()
+    }
+
+    #[test]
+    fn splits_commands_on_whitespace_outside_quotes() {
+        assert_eq!(
+            split_command("tool  -x \"two words\" 'it''s' a\"b c\"d {in} {out}").unwrap(),
+            ["tool", "-x", "two words", "its", "ab cd", "{in}", "{out}"]
+        );
+        assert_eq!(
+            split_command("tool '' {in} {out}").unwrap(),
+            ["tool", "", "{in}", "{out}"]
+        );
+        assert_eq!(
+            split_command("tool --in={in} \"{out}\"").unwrap(),
+            ["tool", "--in={in}", "{out}"]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        for (template, error) in [
+            (
+                "tool 'x {in} {out}",
+                "unterminated quote in 'tool 'x {in} {out}'",
+            ),
+            ("  ", "empty command"),
+            ("tool {in}", "'tool {in}' doesn't use {out}"),
+            ("tool {out}", "'tool {out}' doesn't use {in}"),
+        ] {
+            assert_eq!(split_command(template).unwrap_err(), error, "{template}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_the_tool_on_temp_files() {
+        let upper = hook(
+            "sh -c 'tr a-z A-Z < \"$0\" > \"$1\"' {in} {out}",
+            "sh -c 'tr A-Z a-z < \"$0\" > \"$1\"' {in} {out}",
+        );
+        assert_eq!(upper.convert("a.txt", b"mages").unwrap(), b"MAGES");
+        assert_eq!(upper.revert("a.txt", b"MAGES").unwrap(), b"mages");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn temp_files_are_named_after_the_entry() {
+        let names = hook(
+            "sh -c 'basename \"$0\" > \"$1\"; basename \"$1\" >> \"$1\"' {in} {out}",
+            "sh -c 'basename \"$0\" > \"$1\"; basename \"$1\" >> \"$1\"' {in} {out}",
+        );
+        assert_eq!(
+            names.convert("script/main.scx", b"").unwrap(),
+            b"main.scx\nmain.scx.out\n"
+        );
+        assert_eq!(
+            names.revert("script/main.scx", b"").unwrap(),
+            b"main.scx.out\nmain.scx\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_tools_are_errors() {
+        let failing = hook(
+            "sh -c 'echo oops >&2; exit 3' {in} {out}",
+            "true {in} {out}",
+        );
+        assert_eq!(
+            failing.convert("a.txt", b"").unwrap_err(),
+            "'test' failed (exit status: 3): oops"
+        );
+        let e = failing.revert("a.txt", b"").unwrap_err();
+        assert!(e.starts_with("'test' didn't write {out}: "), "{e}");
+    }
+
+    #[test]
+    fn one_way_hooks_refuse_the_other_way() {
+        let extract_only =
+            ExternalCommand::new("test", "out", Some("tool {in} {out}"), None).unwrap();
+        assert_eq!(
+            extract_only.revert("a.txt", b"").unwrap_err(),
+            "'test' has no on_repack command"
+        );
+        assert_eq!(extract_only.name(), "hook:test");
+    }
+}
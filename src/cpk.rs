@@ -0,0 +1,8 @@
+mod archive;
+mod bytes;
+pub mod crilayla;
+mod entry;
+pub mod utf;
+
+pub use archive::CpkArchive;
+pub use entry::CpkEntry;
@@ -0,0 +1,877 @@
+use crate::archive::{match_replacements, Archive, ArchiveEntry, ReadSeek, WriteSeek};
+use crate::cpk::bytes;
+use crate::cpk::bytes::ChunkHeader;
+use crate::cpk::entry::CpkEntry;
+use crate::cpk::utf::UtfTable;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// used when the header doesn't say otherwise
+const DEFAULT_ALIGNMENT: u64 = 2048;
+
+/// The tables a CPK header can point to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum TableKind {
+    // file names, offsets and sizes
+    Toc,
+    // ID-only file listing, used on its own by archives without names
+    Itoc,
+    // extra per-file info such as timestamps
+    Etoc,
+    // file groups
+    Gtoc,
+}
+
+impl TableKind {
+    const ALL: [Self; 4] = [Self::Toc, Self::Itoc, Self::Etoc, Self::Gtoc];
+
+    const fn signature(self) -> &'static [u8] {
+        match self {
+            Self::Toc => b"TOC ",
+            Self::Itoc => b"ITOC",
+            Self::Etoc => b"ETOC",
+            Self::Gtoc => b"GTOC",
+        }
+    }
+
+    // the CPK header column holding the table's offset
+    const fn offset_column(self) -> &'static str {
+        match self {
+            Self::Toc => "TocOffset",
+            Self::Itoc => "ItocOffset",
+            Self::Etoc => "EtocOffset",
+            Self::Gtoc => "GtocOffset",
+        }
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Toc => "TOC",
+            Self::Itoc => "ITOC",
+            Self::Etoc => "ETOC",
+            Self::Gtoc => "GTOC",
+        }
+    }
+}
+
+// a table along with the chunk header wrapping it
+#[derive(Debug, Clone)]
+struct Chunk {
+    offset: u64,
+    header: ChunkHeader,
+    table: UtfTable,
+}
+
+impl Chunk {
+    fn read<R: Read + Seek>(reader: &mut R, offset: u64, signature: &[u8]) -> Self {
+        reader.seek(SeekFrom::Start(offset)).unwrap();
+        let header: ChunkHeader = bytes::read_struct(reader);
+        assert_eq!(
+            header.signature,
+            signature,
+            "invalid '{}' signature at 0x{offset:x}",
+            String::from_utf8_lossy(signature)
+        );
+
+        let mut table = vec![0; usize::try_from(header.len).unwrap()];
+        reader
+            .read_exact(&mut table)
+            .expect("failed to read CPK table");
+
+        Self {
+            offset,
+            header,
+            table: UtfTable::parse(&table),
+        }
+    }
+
+    const fn end(&self) -> u64 {
+        self.offset + bytes::CHUNK_HEADER_SIZE + self.header.len
+    }
+
+    fn write<W: Write + Seek>(&self, writer: &mut W) {
+        writer.seek(SeekFrom::Start(self.offset)).unwrap();
+        bytes::write_struct(writer, self.header);
+        writer.write_all(&self.table.to_bytes()).unwrap();
+    }
+}
+
+#[derive(Debug)]
+pub struct CpkArchive {
+    entries: IndexMap<u32, CpkEntry>,
+    names_to_ids: HashMap<String, u32>,
+    header: Chunk,
+    tables: IndexMap<TableKind, Chunk>,
+    content_offset: u64,
+    alignment: u64,
+}
+
+impl CpkArchive {
+    pub const CPK_SIG: &'static [u8] = b"CPK ";
+
+    pub fn build<R: Read + Seek>(reader: &mut R) -> Self {
+        let header = Chunk::read(reader, 0, Self::CPK_SIG);
+        let content_offset = header
+            .table
+            .get_u64(0, "ContentOffset")
+            .expect("CPK header has no ContentOffset");
+        let alignment = header
+            .table
+            .get_u64(0, "Align")
+            .filter(|&align| align != 0)
+            .unwrap_or(DEFAULT_ALIGNMENT);
+
+        let mut tables = IndexMap::new();
+        for kind in TableKind::ALL {
+            if let Some(offset) = header
+                .table
+                .get_u64(0, kind.offset_column())
+                .filter(|&offset| offset != 0)
+            {
+                tables.insert(kind, Chunk::read(reader, offset, kind.signature()));
+            }
+        }
+
+        // the TOC has everything we need if it's there; name-less archives only have an ITOC
+        let entries = match (tables.get(&TableKind::Toc), tables.get(&TableKind::Itoc)) {
+            (Some(toc), _) => Self::toc_entries(toc, content_offset),
+            (None, Some(itoc)) => Self::itoc_entries(itoc, content_offset, alignment),
+            (None, None) => Vec::new(),
+        };
+
+        let names_to_ids = entries
+            .iter()
+            .map(|entry| (entry.name().to_string(), entry.id()))
+            .collect();
+        let entries = entries
+            .into_iter()
+            .map(|entry| (entry.id(), entry))
+            .collect();
+
+        Self {
+            entries,
+            names_to_ids,
+            header,
+            tables,
+            content_offset,
+            alignment,
+        }
+    }
+
+    // TOC file offsets are relative to whichever comes first of the content and the TOC itself
+    const fn toc_base_offset(toc: &Chunk, content_offset: u64) -> u64 {
+        if toc.offset < content_offset {
+            toc.offset
+        } else {
+            content_offset
+        }
+    }
+
+    fn toc_entries(toc: &Chunk, content_offset: u64) -> Vec<CpkEntry> {
+        let table = &toc.table;
+        let base_offset = Self::toc_base_offset(toc, content_offset);
+
+        (0..table.row_count())
+            .map(|row| {
+                let id = table.get_u64(row, "ID").map_or_else(
+                    || u32::try_from(row).unwrap(),
+                    |id| u32::try_from(id).expect("CPK file ID too large for u32"),
+                );
+                let file_name = table
+                    .get_str(row, "FileName")
+                    .map_or_else(|| format!("{id:05}.bin"), str::to_string);
+                let name = match table.get_str(row, "DirName") {
+                    Some(dir) if !dir.is_empty() => format!("{dir}/{file_name}"),
+                    _ => file_name,
+                };
+
+                let offset = table
+                    .get_u64(row, "FileOffset")
+                    .expect("CPK TOC has no FileOffset");
+                let len_compressed = table
+                    .get_u64(row, "FileSize")
+                    .expect("CPK TOC has no FileSize");
+                let len_deflated = table.get_u64(row, "ExtractSize").unwrap_or(len_compressed);
+
+                CpkEntry::new(
+                    id,
+                    name,
+                    base_offset + offset,
+                    len_compressed,
+                    len_deflated,
+                    Some(row),
+                )
+            })
+            .collect()
+    }
+
+    // The ITOC splits files into small (DataL) and large (DataH) ones, each its own nested
+    // table. Files don't have offsets; they're laid out back to back in ID order.
+    fn itoc_entries(itoc: &Chunk, content_offset: u64, alignment: u64) -> Vec<CpkEntry> {
+        let mut files = Vec::new();
+        for column in ["DataL", "DataH"] {
+            let Some(data) = itoc.table.get_data(0, column).filter(|d| !d.is_empty()) else {
+                continue;
+            };
+
+            let table = UtfTable::parse(data);
+            for row in 0..table.row_count() {
+                let id = table.get_u64(row, "ID").expect("CPK ITOC has no ID");
+                let len_compressed = table
+                    .get_u64(row, "FileSize")
+                    .expect("CPK ITOC has no FileSize");
+                let len_deflated = table.get_u64(row, "ExtractSize").unwrap_or(len_compressed);
+                files.push((
+                    u32::try_from(id).expect("CPK file ID too large for u32"),
+                    len_compressed,
+                    len_deflated,
+                ));
+            }
+        }
+        files.sort_unstable_by_key(|&(id, _, _)| id);
+
+        let mut offset = content_offset;
+        files
+            .into_iter()
+            .map(|(id, len_compressed, len_deflated)| {
+                let entry = CpkEntry::new(
+                    id,
+                    format!("{id:05}.bin"),
+                    offset,
+                    len_compressed,
+                    len_deflated,
+                    None,
+                );
+                offset = (offset + len_compressed).next_multiple_of(alignment);
+                entry
+            })
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CpkEntry> {
+        self.entries.values()
+    }
+
+    #[must_use]
+    pub fn get_entry_by_id(&self, id: u32) -> Option<&CpkEntry> {
+        self.entries.get(&id)
+    }
+
+    #[must_use]
+    pub fn get_entry_by_name(&self, name: &str) -> Option<&CpkEntry> {
+        self.names_to_ids
+            .get(name)
+            .and_then(|id| self.get_entry_by_id(*id))
+    }
+
+    fn toc(&self) -> &Chunk {
+        self.tables
+            .get(&TableKind::Toc)
+            .expect("only CPKs with a TOC can be repacked")
+    }
+
+    // Entries move around when repacking, so the TOC has to be able to store each one's offset.
+    fn assert_repackable(&self) {
+        assert!(
+            self.tables.contains_key(&TableKind::Toc),
+            "only CPKs with a TOC can be repacked"
+        );
+        let toc = &self.toc().table;
+        for entry in self.iter() {
+            let row = entry.toc_row.expect("CPK entry isn't in the TOC");
+            assert!(
+                toc.is_stored(row, "FileOffset"),
+                "this CPK's TOC doesn't store FileOffset per entry, so its entries can't be moved"
+            );
+        }
+    }
+
+    // Replacements are stored uncompressed, so both sizes of their TOC rows become the file's
+    // length. Checked before anything is written, so a TOC keeping a size as a constant
+    // column fails cleanly instead of halfway through. A zero or missing ExtractSize is fine,
+    // since it's read as FileSize.
+    fn assert_toc_can_hold(&self, entry: &CpkEntry, rpk_path: &Path) {
+        let len = std::fs::metadata(rpk_path).unwrap().len();
+        let toc = &self.toc().table;
+        let row = entry.toc_row.expect("CPK entry isn't in the TOC");
+        let extract_size_ok =
+            toc.get_u64(row, "ExtractSize").is_none() || toc.can_set_u64(row, "ExtractSize", len);
+        for (column, ok) in [
+            ("FileSize", toc.can_set_u64(row, "FileSize", len)),
+            ("ExtractSize", extract_size_ok),
+        ] {
+            assert!(
+                ok,
+                "can't replace '{}': this CPK's TOC stores {column} as a constant",
+                entry.name()
+            );
+        }
+    }
+
+    // map of entry name => replacement file
+    fn build_repack_map<P: AsRef<Path>>(&self, rpk_paths: &[P]) -> HashMap<String, PathBuf> {
+        match_replacements(self.iter().map(CpkEntry::name), rpk_paths)
+            .into_iter()
+            .zip(rpk_paths)
+            .filter_map(|(name, path)| Some((name?.to_string(), path.as_ref().to_path_buf())))
+            .collect()
+    }
+
+    // Replacement files are always stored uncompressed, which the games read just fine.
+    fn repack_entry<R: Read + Seek, W: Write + Seek>(
+        &self,
+        orig_reader: &mut R,
+        rpk_writer: &mut W,
+        rpk_paths: &HashMap<String, PathBuf>,
+        entry: &CpkEntry,
+    ) -> CpkEntry {
+        let cur_pos = rpk_writer.stream_position().unwrap();
+        crate::bytes::write_padding(rpk_writer, cur_pos, self.alignment);
+        let new_offset = rpk_writer.stream_position().unwrap();
+
+        if let Some(rpk_path) = rpk_paths.get(entry.name()) {
+            let mut rpk_reader = BufReader::new(File::open(rpk_path).unwrap());
+            let len =
+                io::copy(&mut rpk_reader, rpk_writer).expect("failed to copy entry from reader");
+            entry.updated(new_offset, len, len)
+        } else {
+            orig_reader.seek(SeekFrom::Start(entry.offset())).unwrap();
+            io::copy(&mut orig_reader.take(entry.len_compressed()), rpk_writer)
+                .expect("failed to copy entry from reader");
+            entry.updated(new_offset, entry.len_compressed(), entry.len_deflated())
+        }
+    }
+
+    fn update_toc_row(&self, toc: &mut UtfTable, entry: &CpkEntry) {
+        let row = entry.toc_row.expect("CPK entry isn't in the TOC");
+        let base_offset = Self::toc_base_offset(self.toc(), self.content_offset);
+        toc.set_u64(row, "FileOffset", entry.offset() - base_offset);
+        toc.set_u64(row, "FileSize", entry.len_compressed());
+        if toc.get_u64(row, "ExtractSize").is_some() {
+            toc.set_u64(row, "ExtractSize", entry.len_deflated());
+        }
+    }
+
+    fn updated_header<'a, I>(&self, entries: I, tables: &IndexMap<TableKind, Chunk>) -> Chunk
+    where
+        I: IntoIterator<Item = &'a CpkEntry>,
+    {
+        let mut header = self.header.clone();
+        let mut content_end = self.content_offset;
+        let mut packed_size = 0;
+        let mut data_size = 0;
+        for entry in entries {
+            content_end = content_end.max(entry.offset() + entry.len_compressed());
+            packed_size += entry.len_compressed();
+            data_size += entry.len_deflated();
+        }
+
+        let table = &mut header.table;
+        table.set_u64_if_stored(0, "ContentSize", content_end - self.content_offset);
+        table.set_u64_if_stored(0, "EnabledPackedSize", packed_size);
+        table.set_u64_if_stored(0, "EnabledDataSize", data_size);
+        for (kind, chunk) in tables {
+            table.set_u64(0, kind.offset_column(), chunk.offset);
+        }
+
+        header
+    }
+
+    // Writes out a whole new archive. Everything before the content (the header, TOC, and
+    // whatever else the original put there) stays where it was, entries are laid out in order
+    // from the content offset, and any tables that followed the content are moved after it.
+    fn rebuild<R: Read + Seek, W: Write + Seek>(
+        &self,
+        orig_reader: &mut R,
+        rpk_writer: &mut W,
+        rpk_paths: &HashMap<String, PathBuf>,
+    ) -> Self {
+        self.assert_repackable();
+        for entry in self.iter() {
+            if let Some(rpk_path) = rpk_paths.get(entry.name()) {
+                self.assert_toc_can_hold(entry, rpk_path);
+            }
+        }
+
+        orig_reader.seek(SeekFrom::Start(0)).unwrap();
+        rpk_writer.seek(SeekFrom::Start(0)).unwrap();
+        io::copy(&mut orig_reader.take(self.content_offset), rpk_writer)
+            .expect("failed to copy CPK header area");
+
+        let rpk_entries = self
+            .iter()
+            .map(|entry| {
+                let new_entry = self.repack_entry(orig_reader, rpk_writer, rpk_paths, entry);
+                (entry.id(), new_entry)
+            })
+            .collect::<IndexMap<_, _>>();
+
+        let mut tables = self.tables.clone();
+        let toc = &mut tables[&TableKind::Toc].table;
+        for entry in rpk_entries.values() {
+            self.update_toc_row(toc, entry);
+        }
+
+        let mut next_offset = rpk_writer.stream_position().unwrap();
+        for chunk in tables.values_mut() {
+            if chunk.offset >= self.content_offset {
+                chunk.offset = next_offset.next_multiple_of(self.alignment);
+                next_offset = chunk.end();
+            }
+        }
+
+        let header = self.updated_header(rpk_entries.values(), &tables);
+        header.write(rpk_writer);
+        for chunk in tables.values() {
+            chunk.write(rpk_writer);
+        }
+        rpk_writer.flush().unwrap();
+
+        Self {
+            entries: rpk_entries,
+            names_to_ids: self.names_to_ids.clone(),
+            header,
+            tables,
+            content_offset: self.content_offset,
+            alignment: self.alignment,
+        }
+    }
+
+    #[allow(clippy::return_self_not_must_use)]
+    pub fn repack_entries<R, W, P>(
+        &self,
+        orig_reader: &mut R,
+        rpk_writer: &mut W,
+        rpk_paths: &[P],
+    ) -> Self
+    where
+        R: Read + Seek,
+        W: Write + Seek,
+        P: AsRef<Path>,
+    {
+        let rpk_paths = self.build_repack_map(rpk_paths);
+        self.rebuild(orig_reader, rpk_writer, &rpk_paths)
+    }
+
+    // The space an entry is allowed to occupy without clobbering its neighbor or a table. The
+    // last thing in the file can grow freely.
+    fn available_space(&self, entry: &CpkEntry) -> Option<u64> {
+        self.iter()
+            .map(CpkEntry::offset)
+            .chain(self.tables.values().map(|chunk| chunk.offset))
+            .filter(|&offset| offset > entry.offset())
+            .min()
+            .map(|next_offset| next_offset - entry.offset())
+    }
+
+    fn patch_entry<F: Write + Seek>(&mut self, archive: &mut F, entry_id: u32, rpk_path: &Path) {
+        let entry = &self.entries[&entry_id];
+        let rpk_data = std::fs::read(rpk_path).unwrap();
+        let rpk_len = rpk_data.len() as u64;
+
+        let new_offset = if self
+            .available_space(entry)
+            .is_none_or(|space| rpk_len <= space)
+        {
+            archive.seek(SeekFrom::Start(entry.offset())).unwrap()
+        } else {
+            let end = archive.seek(SeekFrom::End(0)).unwrap();
+            crate::bytes::write_padding(archive, end, self.alignment);
+            archive.stream_position().unwrap()
+        };
+        archive.write_all(&rpk_data).unwrap();
+
+        let new_entry = entry.updated(new_offset, rpk_len, rpk_len);
+        let mut toc = self.toc().table.clone();
+        self.update_toc_row(&mut toc, &new_entry);
+        self.tables[&TableKind::Toc].table = toc;
+        self.entries.insert(entry_id, new_entry);
+    }
+
+    /// Replaces entries directly inside `archive` instead of rebuilding it, the same way as
+    /// `MagesArchive::patch_entries()`. Replacement files are matched on their path inside the
+    /// archive, see [`match_replacements()`].
+    pub fn patch_entries<F, P>(&mut self, archive: &mut F, rpk_paths: &[P]) -> Result<(), String>
+    where
+        F: Write + Seek,
+        P: AsRef<Path>,
+    {
+        self.assert_repackable();
+        let names = match_replacements(self.iter().map(CpkEntry::name), rpk_paths);
+
+        let mut replacements = Vec::new();
+        for (name, rpk_path) in names.into_iter().zip(rpk_paths) {
+            let rpk_path = rpk_path.as_ref();
+            let name = name.ok_or_else(|| {
+                format!("no entry in the archive matches '{}'", rpk_path.display())
+            })?;
+            for entry in self.iter().filter(|entry| entry.name() == name) {
+                self.assert_toc_can_hold(entry, rpk_path);
+                replacements.push((entry.id(), rpk_path));
+            }
+        }
+        for (entry_id, rpk_path) in replacements {
+            self.patch_entry(archive, entry_id, rpk_path);
+        }
+
+        self.header = self.updated_header(self.entries.values(), &self.tables);
+        self.header.write(archive);
+        self.tables[&TableKind::Toc].write(archive);
+        archive.flush().unwrap();
+        Ok(())
+    }
+
+    /// Rewrites the archive with no unused space, keeping only the alignment padding each
+    /// entry requires.
+    #[allow(clippy::return_self_not_must_use)]
+    pub fn compact<R, W>(&self, orig_reader: &mut R, rpk_writer: &mut W) -> Self
+    where
+        R: Read + Seek,
+        W: Write + Seek,
+    {
+        self.rebuild(orig_reader, rpk_writer, &HashMap::new())
+    }
+}
+
+impl Archive for CpkArchive {
+    fn description(&self) -> String {
+        let tables = self
+            .tables
+            .keys()
+            .map(|kind| kind.name())
+            .collect::<Vec<_>>();
+        format!("CPK ({})", tables.join("+"))
+    }
+
+    fn entries(&self) -> Vec<&dyn ArchiveEntry> {
+        self.iter()
+            .map(|entry| entry as &dyn ArchiveEntry)
+            .collect()
+    }
+
+    fn open_entry<'r>(
+        &self,
+        reader: &'r mut dyn ReadSeek,
+        entry: &dyn ArchiveEntry,
+    ) -> Box<dyn Read + 'r> {
+        self.entries[&entry.id()].open(reader)
+    }
+
+    fn repack(
+        &self,
+        mut orig_reader: &mut dyn ReadSeek,
+        mut rpk_writer: &mut dyn WriteSeek,
+        rpk_paths: &[PathBuf],
+    ) {
+        self.repack_entries(&mut orig_reader, &mut rpk_writer, rpk_paths);
+    }
+
+    fn patch(
+        &mut self,
+        mut archive: &mut dyn WriteSeek,
+        rpk_paths: &[PathBuf],
+    ) -> Result<(), String> {
+        self.patch_entries(&mut archive, rpk_paths)
+    }
+
+    fn compact(&self, mut orig_reader: &mut dyn ReadSeek, mut writer: &mut dyn WriteSeek) {
+        Self::compact(self, &mut orig_reader, &mut writer);
+    }
+
+    fn sort_by_id(&mut self) {
+        self.entries.sort_keys();
+    }
+
+    fn sort_by_name(&mut self) {
+        self.entries
+            .sort_by(|_, e1, _, e2| e1.name().cmp(e2.name()));
+    }
+
+    fn header_table_len(&self) -> u64 {
+        self.header.end()
+    }
+
+    fn alignment(&self) -> u64 {
+        self.alignment
+    }
+
+    fn reserved_spans(&self) -> Vec<(u64, u64)> {
+        self.tables
+            .values()
+            .map(|chunk| (chunk.offset, chunk.end()))
+            .collect()
+    }
+
+    fn reported_entry_count(&self) -> u64 {
+        self.header
+            .table
+            .get_u64(0, "Files")
+            .unwrap_or(self.entries.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpk::utf::tests::{build_table, TestColumn};
+    use crate::cpk::utf::UtfValue;
+    use std::io::Cursor;
+    use std::panic;
+
+    const TOC_OFFSET: u64 = 0x100;
+    const CONTENT_OFFSET: u64 = 0x400;
+    const ALIGN: usize = 0x20;
+
+    fn chunk(signature: &[u8], table: &[u8]) -> Vec<u8> {
+        let mut chunk = signature.to_vec();
+        chunk.extend(0xff_u32.to_le_bytes());
+        chunk.extend(u64::try_from(table.len()).unwrap().to_le_bytes());
+        chunk.extend(table);
+        chunk
+    }
+
+    fn u32_value(n: usize) -> UtfValue {
+        UtfValue::U32(u32::try_from(n).unwrap())
+    }
+
+    // a CPK holding `files`, each a directory, file name and contents, with its TOC's
+    // ExtractSize column stored as `extract_size`
+    fn build_cpk(files: &[(&str, &str, &[u8])], extract_size: TestColumn) -> Vec<u8> {
+        let mut content = Vec::new();
+        let mut offsets = Vec::new();
+        for (_, _, data) in files {
+            let offset = CONTENT_OFFSET - TOC_OFFSET + u64::try_from(content.len()).unwrap();
+            offsets.push(UtfValue::U64(offset));
+            content.extend(*data);
+            content.resize(content.len().next_multiple_of(ALIGN), 0);
+        }
+
+        let string = |s: &str| UtfValue::String(s.to_string());
+        let mut columns = vec![
+            TestColumn::PerRow("DirName", files.iter().map(|file| string(file.0)).collect()),
+            TestColumn::PerRow(
+                "FileName",
+                files.iter().map(|file| string(file.1)).collect(),
+            ),
+            TestColumn::PerRow("FileOffset", offsets),
+            TestColumn::PerRow(
+                "FileSize",
+                files.iter().map(|file| u32_value(file.2.len())).collect(),
+            ),
+            TestColumn::PerRow("ID", (0..files.len()).map(u32_value).collect()),
+        ];
+        columns.push(extract_size);
+        let toc = build_table("CpkTocInfo", files.len(), &columns);
+        let header = build_table(
+            "CpkHeader",
+            1,
+            &[
+                TestColumn::PerRow("ContentOffset", vec![UtfValue::U64(CONTENT_OFFSET)]),
+                TestColumn::PerRow("TocOffset", vec![UtfValue::U64(TOC_OFFSET)]),
+                TestColumn::PerRow("Align", vec![UtfValue::U16(u16::try_from(ALIGN).unwrap())]),
+                TestColumn::PerRow("Files", vec![u32_value(files.len())]),
+            ],
+        );
+
+        let mut cpk = chunk(CpkArchive::CPK_SIG, &header);
+        cpk.resize(usize::try_from(TOC_OFFSET).unwrap(), 0);
+        cpk.extend(chunk(b"TOC ", &toc));
+        cpk.resize(usize::try_from(CONTENT_OFFSET).unwrap(), 0);
+        cpk.extend(content);
+        cpk
+    }
+
+    fn per_row_extract_size(files: &[(&str, &str, &[u8])]) -> TestColumn {
+        TestColumn::PerRow(
+            "ExtractSize",
+            files.iter().map(|file| u32_value(file.2.len())).collect(),
+        )
+    }
+
+    fn contents(cpk: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut reader = Cursor::new(cpk);
+        let archive = CpkArchive::build(&mut reader);
+        archive
+            .iter()
+            .map(|entry| {
+                let mut data = Vec::new();
+                entry.open(&mut reader).read_to_end(&mut data).unwrap();
+                (entry.name().to_string(), data)
+            })
+            .collect()
+    }
+
+    fn owned(files: &[(&str, &str, &[u8])]) -> Vec<(String, Vec<u8>)> {
+        files
+            .iter()
+            .map(|(dir, name, data)| (format!("{dir}/{name}"), data.to_vec()))
+            .collect()
+    }
+
+    const FILES: &[(&str, &str, &[u8])] = &[
+        ("script", "a.txt", b"alpha"),
+        ("script", "b.txt", b"bravo"),
+        ("system", "c.txt", b"charlie"),
+    ];
+    const LONG_BRAVO: &[u8] = b"bravo, now long enough to spill past the entry's alignment";
+
+    // writes a replacement for the entry `name` where extracting to `dir` would put it
+    fn replacement(dir: &tempfile::TempDir, name: &str, data: &[u8]) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn repack(cpk: &[u8], rpk_paths: &[PathBuf]) -> Vec<u8> {
+        let archive = CpkArchive::build(&mut Cursor::new(cpk));
+        let mut rpk = Cursor::new(Vec::new());
+        archive.repack_entries(&mut Cursor::new(cpk), &mut rpk, rpk_paths);
+        rpk.into_inner()
+    }
+
+    fn patch(cpk: &[u8], rpk_paths: &[PathBuf]) -> Vec<u8> {
+        let mut archive = CpkArchive::build(&mut Cursor::new(cpk));
+        let mut patched = Cursor::new(cpk.to_vec());
+        archive.patch_entries(&mut patched, rpk_paths).unwrap();
+        patched.into_inner()
+    }
+
+    fn expected_after_replacing_bravo() -> Vec<(String, Vec<u8>)> {
+        let mut expected = owned(FILES);
+        expected[1].1 = LONG_BRAVO.to_vec();
+        expected
+    }
+
+    #[test]
+    fn reads_entries() {
+        let cpk = build_cpk(FILES, per_row_extract_size(FILES));
+        assert_eq!(contents(&cpk), owned(FILES));
+    }
+
+    #[test]
+    fn repack_and_patch_replace_entries() {
+        let cpk = build_cpk(FILES, per_row_extract_size(FILES));
+        let dir = tempfile::tempdir().unwrap();
+        let rpk_paths = [replacement(&dir, "script/b.txt", LONG_BRAVO)];
+
+        assert_eq!(
+            contents(&repack(&cpk, &rpk_paths)),
+            expected_after_replacing_bravo()
+        );
+        assert_eq!(
+            contents(&patch(&cpk, &rpk_paths)),
+            expected_after_replacing_bravo()
+        );
+    }
+
+    #[test]
+    fn compact_keeps_entries() {
+        let cpk = build_cpk(FILES, per_row_extract_size(FILES));
+        let archive = CpkArchive::build(&mut Cursor::new(&cpk));
+        let mut compacted = Cursor::new(Vec::new());
+        CpkArchive::compact(&archive, &mut Cursor::new(&cpk), &mut compacted);
+        assert_eq!(contents(&compacted.into_inner()), owned(FILES));
+    }
+
+    #[test]
+    fn zero_extract_size_reads_as_file_size() {
+        let cpk = build_cpk(FILES, TestColumn::Zero("ExtractSize", 0x4));
+        let dir = tempfile::tempdir().unwrap();
+        let rpk_paths = [replacement(&dir, "script/b.txt", LONG_BRAVO)];
+        assert_eq!(
+            contents(&patch(&cpk, &rpk_paths)),
+            expected_after_replacing_bravo()
+        );
+    }
+
+    #[test]
+    fn constant_extract_size_refuses_other_sizes() {
+        let cpk = build_cpk(FILES, TestColumn::Constant("ExtractSize", u32_value(5)));
+        let dir = tempfile::tempdir().unwrap();
+
+        // a same-sized replacement fits the constant
+        let same_size = [replacement(&dir, "script/b.txt", b"BRAVO")];
+        let mut expected = owned(FILES);
+        expected[1].1 = b"BRAVO".to_vec();
+        assert_eq!(contents(&patch(&cpk, &same_size)), expected);
+
+        // anything else is refused before the archive is touched
+        let other_size = [replacement(&dir, "script/b.txt", LONG_BRAVO)];
+        let mut archive = CpkArchive::build(&mut Cursor::new(&cpk));
+        let mut patched = Cursor::new(cpk.clone());
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            archive.patch_entries(&mut patched, &other_size).unwrap();
+        }));
+        let message = *result.unwrap_err().downcast::<String>().unwrap();
+        assert!(
+            message.contains("stores ExtractSize as a constant"),
+            "{message}"
+        );
+        assert_eq!(patched.into_inner(), cpk);
+    }
+
+    #[test]
+    fn replacements_match_the_whole_path() {
+        let files: &[(&str, &str, &[u8])] = &[
+            ("script", "main.txt", b"script"),
+            ("system", "main.txt", b"system"),
+        ];
+        let cpk = build_cpk(files, per_row_extract_size(files));
+        let dir = tempfile::tempdir().unwrap();
+        let rpk_paths = [replacement(&dir, "system/main.txt", b"patched")];
+
+        let expected = vec![
+            ("script/main.txt".to_string(), b"script".to_vec()),
+            ("system/main.txt".to_string(), b"patched".to_vec()),
+        ];
+        assert_eq!(contents(&repack(&cpk, &rpk_paths)), expected);
+        assert_eq!(contents(&patch(&cpk, &rpk_paths)), expected);
+    }
+
+    #[test]
+    fn patch_refuses_unmatched_files() {
+        let cpk = build_cpk(FILES, per_row_extract_size(FILES));
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = CpkArchive::build(&mut Cursor::new(&cpk));
+        let mut patched = Cursor::new(cpk.clone());
+        let rpk_paths = [
+            replacement(&dir, "script/a.txt", b"ALPHA"),
+            replacement(&dir, "system/b.txt", b"bravo"),
+        ];
+        let message = archive.patch_entries(&mut patched, &rpk_paths).unwrap_err();
+        assert!(
+            message.contains("no entry in the archive matches"),
+            "{message}"
+        );
+        assert_eq!(patched.into_inner(), cpk);
+    }
+
+    #[test]
+    fn crilayla_files_are_decompressed_until_replaced() {
+        let compressed = crate::cpk::crilayla::tests::compressed();
+        let deflated = crate::cpk::crilayla::tests::deflated();
+        let files: &[(&str, &str, &[u8])] = &[
+            ("script", "a.txt", b"alpha"),
+            ("movie", "z.bin", &compressed),
+        ];
+        let cpk = build_cpk(
+            files,
+            TestColumn::PerRow("ExtractSize", vec![u32_value(5), u32_value(deflated.len())]),
+        );
+        let mut expected = owned(files);
+        expected[1].1.clone_from(&deflated);
+        assert_eq!(contents(&cpk), expected);
+
+        // replacements go in uncompressed
+        let dir = tempfile::tempdir().unwrap();
+        let rpk_paths = [replacement(&dir, "movie/z.bin", b"zulu")];
+        expected[1].1 = b"zulu".to_vec();
+        assert_eq!(contents(&repack(&cpk, &rpk_paths)), expected);
+        assert_eq!(contents(&patch(&cpk, &rpk_paths)), expected);
+    }
+}
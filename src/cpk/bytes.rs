@@ -0,0 +1,14 @@
+use bincode::{Decode, Encode};
+
+pub use crate::bytes::{read_struct, write_struct};
+
+// every table in a CPK (the CPK header itself, TOC, ITOC, ...) is wrapped in one of these,
+// directly followed by the @UTF table
+#[derive(Debug, Clone, Copy, Decode, Encode)]
+pub(super) struct ChunkHeader {
+    pub signature: [u8; 4],
+    pub flags: u32,
+    pub len: u64,
+}
+
+pub const CHUNK_HEADER_SIZE: u64 = 0x10;
@@ -0,0 +1,157 @@
+// CRILAYLA, the LZ-style compression CPKs use for individual files.
+//
+// A compressed file is a 16-byte header (`CRILAYLA`, u32 uncompressed size, u32 compressed
+// size), the compressed data, and finally the first 0x100 bytes of the file stored raw. The
+// compressed data is a bitstream read backwards from its end, and it also produces output
+// backwards, from the end of the file towards those raw 0x100 bytes.
+
+pub const CRILAYLA_SIG: &[u8] = b"CRILAYLA";
+
+const HEADER_LEN: usize = 0x10;
+const RAW_PREFIX_LEN: usize = 0x100;
+const MIN_BACKREF_LEN: usize = 3;
+const LEN_FIELD_BITS: [u32; 4] = [2, 3, 5, 8];
+
+struct BackwardBits<'a> {
+    data: &'a [u8],
+    // index of the next byte to read, counting down
+    pos: usize,
+    pool: u8,
+    bits_left: u32,
+}
+
+impl BackwardBits<'_> {
+    fn next(&mut self, count: u32) -> usize {
+        let mut out = 0;
+        let mut produced = 0;
+        while produced < count {
+            if self.bits_left == 0 {
+                self.pos -= 1;
+                self.pool = self.data[self.pos];
+                self.bits_left = 8;
+            }
+
+            let take = self.bits_left.min(count - produced);
+            let bits = usize::from(self.pool >> (self.bits_left - take)) & ((1 << take) - 1);
+            out = (out << take) | bits;
+            self.bits_left -= take;
+            produced += take;
+        }
+
+        out
+    }
+}
+
+#[must_use]
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(CRILAYLA_SIG)
+}
+
+/// Decompresses a whole CRILAYLA-compressed file.
+#[must_use]
+pub fn decompress(data: &[u8]) -> Vec<u8> {
+    assert!(is_compressed(data), "invalid CRILAYLA signature");
+    let deflated_len = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+    let compressed_len = u32::from_le_bytes(data[12..16].try_into().unwrap()) as usize;
+
+    let raw_start = HEADER_LEN + compressed_len;
+    assert!(
+        data.len() >= raw_start + RAW_PREFIX_LEN,
+        "CRILAYLA data is truncated"
+    );
+
+    let mut output = vec![0; RAW_PREFIX_LEN + deflated_len];
+    output[..RAW_PREFIX_LEN].copy_from_slice(&data[raw_start..raw_start + RAW_PREFIX_LEN]);
+
+    let mut bits = BackwardBits {
+        data: &data[HEADER_LEN..raw_start],
+        pos: compressed_len,
+        pool: 0,
+        bits_left: 0,
+    };
+
+    // `remaining` counts down the bytes left to produce; the next one goes right before them
+    let mut remaining = deflated_len;
+    while remaining > 0 {
+        let out_pos = RAW_PREFIX_LEN + remaining - 1;
+        if bits.next(1) == 0 {
+            #[allow(clippy::cast_possible_truncation)]
+            let byte = bits.next(8) as u8;
+            output[out_pos] = byte;
+            remaining -= 1;
+            continue;
+        }
+
+        let mut src = out_pos + bits.next(13) + MIN_BACKREF_LEN;
+        let mut len = MIN_BACKREF_LEN;
+        let mut maxed_out = true;
+        for field_bits in LEN_FIELD_BITS {
+            let part = bits.next(field_bits);
+            len += part;
+            if part != (1 << field_bits) - 1 {
+                maxed_out = false;
+                break;
+            }
+        }
+        if maxed_out {
+            loop {
+                let part = bits.next(8);
+                len += part;
+                if part != 0xff {
+                    break;
+                }
+            }
+        }
+
+        for _ in 0..len.min(remaining) {
+            output[RAW_PREFIX_LEN + remaining - 1] = output[src];
+            src -= 1;
+            remaining -= 1;
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub const DEFLATED_TAIL: &[u8] = b"abcabcabcQ";
+
+    /// [`deflated()`], compressed by hand.
+    ///
+    /// Read backwards from the end, the bitstream holds the literals `Q`, `c`, `b` and `a` (a 0
+    /// bit, then the byte), then a 6-byte backreference to the `abc` just written (a 1 bit, a
+    /// 13-bit distance of 0, a maxed-out 2-bit length of 3 and a 3-bit length of 0), and a
+    /// padding bit.
+    pub fn compressed() -> Vec<u8> {
+        let mut data = CRILAYLA_SIG.to_vec();
+        data.extend(10_u32.to_le_bytes());
+        data.extend(7_u32.to_le_bytes());
+        data.extend([0x30, 0x00, 0x18, 0x46, 0xcc, 0x98, 0x28]);
+        data.extend(0..=0xff_u8);
+        data
+    }
+
+    /// 0x100 bytes counting up from 0, followed by [`DEFLATED_TAIL`].
+    pub fn deflated() -> Vec<u8> {
+        let mut data = (0..=0xff).collect::<Vec<u8>>();
+        data.extend(DEFLATED_TAIL);
+        data
+    }
+
+    #[test]
+    fn decompresses_a_known_file() {
+        assert!(is_compressed(&compressed()));
+        assert_eq!(decompress(&compressed()), deflated());
+    }
+
+    #[test]
+    fn files_of_just_the_raw_bytes_decompress() {
+        let mut data = CRILAYLA_SIG.to_vec();
+        data.extend([0; 8]);
+        data.extend([0x42; 0x100]);
+        assert_eq!(decompress(&data), [0x42; 0x100]);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::archive::ArchiveEntry;
+use crate::cpk::crilayla;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+#[derive(Debug)]
+pub struct CpkEntry {
+    id: u32,
+    name: String,
+    offset: u64,
+    len_compressed: u64,
+    len_deflated: u64,
+    // which TOC row describes this entry, if the archive has a TOC
+    pub(super) toc_row: Option<usize>,
+}
+
+impl CpkEntry {
+    pub(super) const fn new(
+        id: u32,
+        name: String,
+        offset: u64,
+        len_compressed: u64,
+        len_deflated: u64,
+        toc_row: Option<usize>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            offset,
+            len_compressed,
+            len_deflated,
+            toc_row,
+        }
+    }
+
+    #[must_use]
+    pub const fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The entry's full path inside the archive, e.g. `script/main.scx`.
+    #[allow(clippy::missing_const_for_fn)] // compilation error if it's made const
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub const fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    #[must_use]
+    pub const fn len_compressed(&self) -> u64 {
+        self.len_compressed
+    }
+
+    #[must_use]
+    pub const fn len_deflated(&self) -> u64 {
+        self.len_deflated
+    }
+
+    #[must_use]
+    pub const fn is_compressed(&self) -> bool {
+        self.len_compressed != self.len_deflated
+    }
+
+    /// Opens a reader over this entry's contents. CRILAYLA-compressed entries are decompressed
+    /// up front, since the format has to be decoded back to front.
+    pub fn open<'r, R: Read + Seek + ?Sized>(&self, reader: &'r mut R) -> Box<dyn Read + 'r> {
+        reader.seek(SeekFrom::Start(self.offset)).unwrap();
+        if !self.is_compressed() {
+            return Box::new(reader.take(self.len_compressed));
+        }
+
+        let mut data = Vec::with_capacity(usize::try_from(self.len_compressed).unwrap());
+        reader
+            .take(self.len_compressed)
+            .read_to_end(&mut data)
+            .expect("failed to read entry from reader");
+        if crilayla::is_compressed(&data) {
+            Box::new(Cursor::new(crilayla::decompress(&data)))
+        } else {
+            Box::new(Cursor::new(data))
+        }
+    }
+
+    #[must_use]
+    pub fn updated(&self, offset: u64, len_compressed: u64, len_deflated: u64) -> Self {
+        Self {
+            id: self.id,
+            name: self.name.clone(),
+            offset,
+            len_compressed,
+            len_deflated,
+            toc_row: self.toc_row,
+        }
+    }
+}
+
+impl ArchiveEntry for CpkEntry {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn len_compressed(&self) -> u64 {
+        self.len_compressed
+    }
+
+    fn len_deflated(&self) -> u64 {
+        self.len_deflated
+    }
+
+    fn is_compressed(&self) -> bool {
+        self.is_compressed()
+    }
+}
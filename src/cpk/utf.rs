@@ -0,0 +1,522 @@
+// CRI's @UTF tables: a small column-oriented database format used for every table in a CPK.
+//
+// Everything in a table is big-endian, and offsets inside it are relative to the end of the
+// 8-byte `@UTF` + size prelude. Tables may also be scrambled with a simple XOR stream, which
+// is undone on read and redone on write.
+
+use std::ffi::CStr;
+
+const UTF_SIG: &[u8] = b"@UTF";
+const PRELUDE_LEN: usize = 8;
+
+const STORAGE_MASK: u8 = 0xf0;
+const STORAGE_ZERO: u8 = 0x10;
+const STORAGE_CONSTANT: u8 = 0x30;
+const STORAGE_PER_ROW: u8 = 0x50;
+const STORAGE_CONSTANT2: u8 = 0x70;
+
+const TYPE_MASK: u8 = 0x0f;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UtfValue {
+    None,
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    String(String),
+    Data(Vec<u8>),
+}
+
+impl UtfValue {
+    #[must_use]
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Self::U8(v) => Some(u64::from(v)),
+            Self::U16(v) => Some(u64::from(v)),
+            Self::U32(v) => Some(u64::from(v)),
+            Self::U64(v) => Some(v),
+            Self::I8(v) => u64::try_from(v).ok(),
+            Self::I16(v) => u64::try_from(v).ok(),
+            Self::I32(v) => u64::try_from(v).ok(),
+            Self::I64(v) => u64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_data(&self) -> Option<&[u8]> {
+        match self {
+            Self::Data(data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Column {
+    name: String,
+    flags: u8,
+    // only for constant storage
+    constant: UtfValue,
+}
+
+impl Column {
+    const fn storage(&self) -> u8 {
+        self.flags & STORAGE_MASK
+    }
+
+    const fn value_type(&self) -> u8 {
+        self.flags & TYPE_MASK
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UtfTable {
+    name: String,
+    columns: Vec<Column>,
+    rows: Vec<Vec<UtfValue>>,
+    // where each per-row value lives in `raw`, so values can be patched without re-laying out
+    // the whole table
+    field_positions: Vec<Vec<Option<usize>>>,
+    raw: Vec<u8>,
+    scrambled: bool,
+}
+
+// undoes (and, being an XOR stream, also redoes) CRI's table scrambling
+fn unscramble(data: &mut [u8]) {
+    let mut m: u32 = 0x655f;
+    for byte in data {
+        #[allow(clippy::cast_possible_truncation)]
+        let key = m as u8;
+        *byte ^= key;
+        m = m.wrapping_mul(0x4115);
+    }
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let bytes = self.buf[self.pos..self.pos + N].try_into().unwrap();
+        self.pos += N;
+        bytes
+    }
+
+    fn u8(&mut self) -> u8 {
+        u8::from_be_bytes(self.take())
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_be_bytes(self.take())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_be_bytes(self.take())
+    }
+}
+
+impl UtfTable {
+    #[must_use]
+    pub fn parse(table: &[u8]) -> Self {
+        let mut raw = table.to_vec();
+        let scrambled = !raw.starts_with(UTF_SIG);
+        if scrambled {
+            unscramble(&mut raw);
+            assert!(raw.starts_with(UTF_SIG), "invalid @UTF table signature");
+        }
+
+        let mut cur = Cursor { buf: &raw, pos: 4 };
+        let table_len = cur.u32() as usize;
+        assert!(
+            table_len + PRELUDE_LEN <= raw.len(),
+            "@UTF table is truncated"
+        );
+        let _version = cur.u16();
+        let rows_offset = usize::from(cur.u16()) + PRELUDE_LEN;
+        let strings_offset = cur.u32() as usize + PRELUDE_LEN;
+        let data_offset = cur.u32() as usize + PRELUDE_LEN;
+        let name_offset = cur.u32() as usize;
+        let column_count = cur.u16();
+        let row_len = usize::from(cur.u16());
+        let row_count = cur.u32() as usize;
+
+        let string_at = |offset: usize| {
+            CStr::from_bytes_until_nul(&raw[strings_offset + offset..])
+                .expect("unterminated @UTF string")
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        let read_value = |cur: &mut Cursor, value_type: u8| match value_type {
+            0x0 => UtfValue::U8(cur.u8()),
+            0x1 => UtfValue::I8(i8::from_be_bytes(cur.take())),
+            0x2 => UtfValue::U16(cur.u16()),
+            0x3 => UtfValue::I16(i16::from_be_bytes(cur.take())),
+            0x4 => UtfValue::U32(cur.u32()),
+            0x5 => UtfValue::I32(i32::from_be_bytes(cur.take())),
+            0x6 => UtfValue::U64(u64::from_be_bytes(cur.take())),
+            0x7 => UtfValue::I64(i64::from_be_bytes(cur.take())),
+            0x8 => UtfValue::F32(f32::from_be_bytes(cur.take())),
+            0x9 => UtfValue::F64(f64::from_be_bytes(cur.take())),
+            0xa => UtfValue::String(string_at(cur.u32() as usize)),
+            0xb => {
+                let offset = cur.u32() as usize;
+                let len = cur.u32() as usize;
+                UtfValue::Data(raw[data_offset + offset..data_offset + offset + len].to_vec())
+            }
+            _ => panic!("unknown @UTF column type 0x{value_type:x}"),
+        };
+
+        let mut columns = Vec::with_capacity(usize::from(column_count));
+        for _ in 0..column_count {
+            let mut flags = cur.u8();
+            if flags == 0 {
+                // some tables pad the flags out to 4 bytes
+                cur.pos += 3;
+                flags = cur.u8();
+            }
+            let name = string_at(cur.u32() as usize);
+            let constant = match flags & STORAGE_MASK {
+                STORAGE_CONSTANT | STORAGE_CONSTANT2 => read_value(&mut cur, flags & TYPE_MASK),
+                STORAGE_ZERO | STORAGE_PER_ROW => UtfValue::None,
+                storage => panic!("unknown @UTF column storage 0x{storage:x}"),
+            };
+            columns.push(Column {
+                name,
+                flags,
+                constant,
+            });
+        }
+
+        let mut rows = Vec::with_capacity(row_count);
+        let mut field_positions = Vec::with_capacity(row_count);
+        for row in 0..row_count {
+            cur.pos = rows_offset + row * row_len;
+            let mut values = Vec::with_capacity(columns.len());
+            let mut positions = Vec::with_capacity(columns.len());
+            for column in &columns {
+                if column.storage() == STORAGE_PER_ROW {
+                    positions.push(Some(cur.pos));
+                    values.push(read_value(&mut cur, column.value_type()));
+                } else {
+                    positions.push(None);
+                    values.push(column.constant.clone());
+                }
+            }
+            rows.push(values);
+            field_positions.push(positions);
+        }
+
+        Self {
+            name: string_at(name_offset),
+            columns,
+            rows,
+            field_positions,
+            raw,
+            scrambled,
+        }
+    }
+
+    #[allow(clippy::missing_const_for_fn)] // compilation error if it's made const
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub const fn row_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    #[must_use]
+    pub fn has_column(&self, column: &str) -> bool {
+        self.column_index(column).is_some()
+    }
+
+    fn column_index(&self, column: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == column)
+    }
+
+    #[must_use]
+    pub fn get(&self, row: usize, column: &str) -> Option<&UtfValue> {
+        let col = self.column_index(column)?;
+        self.rows.get(row).map(|values| &values[col])
+    }
+
+    #[must_use]
+    pub fn get_u64(&self, row: usize, column: &str) -> Option<u64> {
+        self.get(row, column).and_then(UtfValue::as_u64)
+    }
+
+    #[must_use]
+    pub fn get_str(&self, row: usize, column: &str) -> Option<&str> {
+        self.get(row, column).and_then(UtfValue::as_str)
+    }
+
+    #[must_use]
+    pub fn get_data(&self, row: usize, column: &str) -> Option<&[u8]> {
+        self.get(row, column).and_then(UtfValue::as_data)
+    }
+
+    /// Overwrites an integer value in place. Values that aren't stored per row can't be changed,
+    /// so setting one of those to anything but its current value panics.
+    pub fn set_u64(&mut self, row: usize, column: &str, value: u64) {
+        let col = self
+            .column_index(column)
+            .unwrap_or_else(|| panic!("@UTF table '{}' has no column '{column}'", self.name));
+
+        let Some(pos) = self.field_positions[row][col] else {
+            assert_eq!(
+                self.rows[row][col].as_u64(),
+                Some(value),
+                "can't change constant column '{column}' of @UTF table '{}'",
+                self.name
+            );
+            return;
+        };
+
+        let overflow = || format!("{value} doesn't fit in @UTF column '{column}'");
+        let (new_value, bytes) = match self.rows[row][col] {
+            UtfValue::U8(_) => {
+                let v = u8::try_from(value).unwrap_or_else(|_| panic!("{}", overflow()));
+                (UtfValue::U8(v), v.to_be_bytes().to_vec())
+            }
+            UtfValue::U16(_) => {
+                let v = u16::try_from(value).unwrap_or_else(|_| panic!("{}", overflow()));
+                (UtfValue::U16(v), v.to_be_bytes().to_vec())
+            }
+            UtfValue::U32(_) => {
+                let v = u32::try_from(value).unwrap_or_else(|_| panic!("{}", overflow()));
+                (UtfValue::U32(v), v.to_be_bytes().to_vec())
+            }
+            UtfValue::U64(_) => (UtfValue::U64(value), value.to_be_bytes().to_vec()),
+            UtfValue::I32(_) => {
+                let v = i32::try_from(value).unwrap_or_else(|_| panic!("{}", overflow()));
+                (UtfValue::I32(v), v.to_be_bytes().to_vec())
+            }
+            UtfValue::I64(_) => {
+                let v = i64::try_from(value).unwrap_or_else(|_| panic!("{}", overflow()));
+                (UtfValue::I64(v), v.to_be_bytes().to_vec())
+            }
+            _ => panic!("@UTF column '{column}' isn't an unsigned integer column"),
+        };
+
+        self.raw[pos..pos + bytes.len()].copy_from_slice(&bytes);
+        self.rows[row][col] = new_value;
+    }
+
+    /// Sets `column` only if the table stores a value for it in each row, for columns that only
+    /// some CPK versions fill in.
+    pub fn set_u64_if_stored(&mut self, row: usize, column: &str, value: u64) {
+        if self.is_stored(row, column) {
+            self.set_u64(row, column, value);
+        }
+    }
+
+    /// Whether the table stores a value for `column` in `row` itself, rather than one constant
+    /// shared by every row or none at all.
+    #[must_use]
+    pub fn is_stored(&self, row: usize, column: &str) -> bool {
+        self.column_index(column)
+            .is_some_and(|col| self.field_positions[row][col].is_some())
+    }
+
+    /// Whether `set_u64` can set `column` of `row` to `value`: the column is stored per row, or
+    /// its constant already is `value`.
+    #[must_use]
+    pub fn can_set_u64(&self, row: usize, column: &str, value: u64) -> bool {
+        self.is_stored(row, column) || self.get_u64(row, column) == Some(value)
+    }
+
+    /// The table as it should be written back to the archive, scrambled again if it originally
+    /// was.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.raw.clone();
+        if self.scrambled {
+            unscramble(&mut bytes);
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A column for [`build_table`]: its name and how its values are stored.
+    pub enum TestColumn {
+        Zero(&'static str, u8),
+        Constant(&'static str, UtfValue),
+        PerRow(&'static str, Vec<UtfValue>),
+    }
+
+    fn add_string(strings: &mut Vec<u8>, s: &str) -> [u8; 4] {
+        let offset = u32::try_from(strings.len()).unwrap();
+        strings.extend(s.as_bytes());
+        strings.push(0);
+        offset.to_be_bytes()
+    }
+
+    fn value_type(value: &UtfValue) -> u8 {
+        match value {
+            UtfValue::U8(_) => 0x0,
+            UtfValue::U16(_) => 0x2,
+            UtfValue::U32(_) => 0x4,
+            UtfValue::U64(_) => 0x6,
+            UtfValue::String(_) => 0xa,
+            _ => unimplemented!("test tables only hold unsigned integers and strings"),
+        }
+    }
+
+    fn encode(value: &UtfValue, strings: &mut Vec<u8>) -> Vec<u8> {
+        match value {
+            UtfValue::U8(v) => v.to_be_bytes().to_vec(),
+            UtfValue::U16(v) => v.to_be_bytes().to_vec(),
+            UtfValue::U32(v) => v.to_be_bytes().to_vec(),
+            UtfValue::U64(v) => v.to_be_bytes().to_vec(),
+            UtfValue::String(s) => add_string(strings, s).to_vec(),
+            _ => unimplemented!("test tables only hold unsigned integers and strings"),
+        }
+    }
+
+    /// Lays out an unscrambled @UTF table the way CRI's tools do.
+    pub fn build_table(name: &str, row_count: usize, columns: &[TestColumn]) -> Vec<u8> {
+        let mut strings = b"<NULL>\0".to_vec();
+        let name_offset = add_string(&mut strings, name);
+
+        let mut column_bytes = Vec::new();
+        for column in columns {
+            let (column_name, flags, constant) = match column {
+                TestColumn::Zero(name, value_type) => (name, STORAGE_ZERO | value_type, None),
+                TestColumn::Constant(name, value) => {
+                    (name, STORAGE_CONSTANT | value_type(value), Some(value))
+                }
+                TestColumn::PerRow(name, values) => {
+                    (name, STORAGE_PER_ROW | value_type(&values[0]), None)
+                }
+            };
+            column_bytes.push(flags);
+            column_bytes.extend(add_string(&mut strings, column_name));
+            if let Some(value) = constant {
+                column_bytes.extend(encode(value, &mut strings));
+            }
+        }
+
+        let mut rows = Vec::new();
+        for row in 0..row_count {
+            for column in columns {
+                if let TestColumn::PerRow(_, values) = column {
+                    rows.extend(encode(&values[row], &mut strings));
+                }
+            }
+        }
+        let row_len = rows.len().checked_div(row_count).unwrap_or(0);
+
+        let rows_offset = 24 + column_bytes.len();
+        let strings_offset = rows_offset + rows.len();
+        let data_offset = strings_offset + strings.len();
+        let mut body = Vec::new();
+        body.extend(1_u16.to_be_bytes());
+        body.extend(u16::try_from(rows_offset).unwrap().to_be_bytes());
+        body.extend(u32::try_from(strings_offset).unwrap().to_be_bytes());
+        body.extend(u32::try_from(data_offset).unwrap().to_be_bytes());
+        body.extend(name_offset);
+        body.extend(u16::try_from(columns.len()).unwrap().to_be_bytes());
+        body.extend(u16::try_from(row_len).unwrap().to_be_bytes());
+        body.extend(u32::try_from(row_count).unwrap().to_be_bytes());
+        body.extend(column_bytes);
+        body.extend(rows);
+        body.extend(strings);
+
+        let mut table = UTF_SIG.to_vec();
+        table.extend(u32::try_from(body.len()).unwrap().to_be_bytes());
+        table.extend(body);
+        table
+    }
+
+    fn sample_table() -> Vec<u8> {
+        build_table(
+            "CpkTocInfo",
+            2,
+            &[
+                TestColumn::PerRow(
+                    "FileName",
+                    vec![
+                        UtfValue::String("a.txt".to_string()),
+                        UtfValue::String("b.txt".to_string()),
+                    ],
+                ),
+                TestColumn::PerRow("FileSize", vec![UtfValue::U32(10), UtfValue::U32(20)]),
+                TestColumn::Constant("ExtractSize", UtfValue::U32(10)),
+                TestColumn::Zero("UserString", 0xa),
+            ],
+        )
+    }
+
+    #[test]
+    fn parses_every_storage() {
+        let table = UtfTable::parse(&sample_table());
+        assert_eq!(table.name(), "CpkTocInfo");
+        assert_eq!(table.row_count(), 2);
+        assert_eq!(table.get_str(1, "FileName"), Some("b.txt"));
+        assert_eq!(table.get_u64(1, "FileSize"), Some(20));
+        assert_eq!(table.get_u64(1, "ExtractSize"), Some(10));
+        assert_eq!(table.get(1, "UserString"), Some(&UtfValue::None));
+        assert!(table.is_stored(1, "FileSize"));
+        assert!(!table.is_stored(1, "ExtractSize"));
+        assert!(!table.is_stored(1, "UserString"));
+    }
+
+    #[test]
+    fn set_values_survive_a_round_trip() {
+        let mut table = UtfTable::parse(&sample_table());
+        assert!(table.can_set_u64(0, "FileSize", 1234));
+        assert!(table.can_set_u64(0, "ExtractSize", 10));
+        assert!(!table.can_set_u64(0, "ExtractSize", 1234));
+        table.set_u64(0, "FileSize", 1234);
+        table.set_u64(0, "ExtractSize", 10);
+
+        let reparsed = UtfTable::parse(&table.to_bytes());
+        assert_eq!(reparsed.get_u64(0, "FileSize"), Some(1234));
+        assert_eq!(reparsed.get_u64(1, "FileSize"), Some(20));
+        assert_eq!(reparsed.get_str(0, "FileName"), Some("a.txt"));
+    }
+
+    #[test]
+    #[should_panic(expected = "can't change constant column 'ExtractSize'")]
+    fn constant_columns_cant_change() {
+        UtfTable::parse(&sample_table()).set_u64(0, "ExtractSize", 1234);
+    }
+
+    #[test]
+    fn scrambled_tables_stay_scrambled() {
+        let mut scrambled = sample_table();
+        unscramble(&mut scrambled);
+        let mut table = UtfTable::parse(&scrambled);
+        assert_eq!(table.get_str(0, "FileName"), Some("a.txt"));
+
+        table.set_u64(1, "FileSize", 99);
+        let bytes = table.to_bytes();
+        assert!(!bytes.starts_with(UTF_SIG));
+        assert_eq!(UtfTable::parse(&bytes).get_u64(1, "FileSize"), Some(99));
+    }
+}
@@ -0,0 +1,253 @@
+// Game fonts. The font texture is an atlas of square cells, one glyph per cell in glyph order,
+// row by row, drawn white on transparent. Next to it sits a width table with one byte per glyph:
+// how far the text advances after drawing it, in atlas pixels. Glyph indices are the same ones
+// SC3 strings use, so a charset (see `sc3::charset`) says which character each cell holds.
+//
+// Cell sizes differ between games and aren't stored anywhere, so they have to be given.
+
+use crate::image::Image;
+use crate::sc3::Charset;
+use ab_glyph::{Font as _, FontRef, PxScale, ScaleFont as _};
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Font {
+    pub atlas: Image,
+    pub widths: Vec<u8>,
+    pub cell_size: u32,
+}
+
+/// What happened to each glyph `Font::render_ttf` was asked to draw.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderReport {
+    pub rendered: usize,
+    /// Characters the TTF/OTF doesn't have a glyph for, left as they were in the atlas.
+    pub missing: Vec<char>,
+}
+
+impl Font {
+    #[must_use]
+    pub fn new(atlas: Image, widths: Vec<u8>, cell_size: u32) -> Self {
+        assert!(cell_size > 0, "cell size must be positive");
+        assert!(
+            atlas.width >= cell_size,
+            "the atlas is narrower than a single {cell_size}px cell"
+        );
+        Self {
+            atlas,
+            widths,
+            cell_size,
+        }
+    }
+
+    #[must_use]
+    pub const fn columns(&self) -> u32 {
+        self.atlas.width / self.cell_size
+    }
+
+    /// How many glyphs the atlas has room for.
+    #[must_use]
+    pub const fn capacity(&self) -> u32 {
+        self.columns() * (self.atlas.height / self.cell_size)
+    }
+
+    /// Where the cell of `glyph` starts in the atlas.
+    #[must_use]
+    pub fn cell(&self, glyph: u16) -> (i64, i64) {
+        let glyph = u32::from(glyph);
+        let columns = self.columns();
+        (
+            i64::from(glyph % columns * self.cell_size),
+            i64::from(glyph / columns * self.cell_size),
+        )
+    }
+
+    // makes room for `glyph` in both the atlas and the width table, growing them as needed
+    fn reserve(&mut self, glyph: u16) {
+        while u32::from(glyph) >= self.capacity() {
+            let mut atlas = Image::new(self.atlas.width, self.atlas.height + self.cell_size);
+            atlas.pixels[..self.atlas.pixels.len()].copy_from_slice(&self.atlas.pixels);
+            self.atlas = atlas;
+        }
+        if self.widths.len() <= usize::from(glyph) {
+            self.widths.resize(usize::from(glyph) + 1, 0);
+        }
+    }
+
+    /// Draws the characters `charset` assigns to `glyphs` (all of them if `None`) from a TTF/OTF
+    /// font at `size` pixels into their cells, and sets their widths to the font's advances.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn render_ttf(
+        &mut self,
+        ttf: &[u8],
+        size: f32,
+        charset: &Charset,
+        glyphs: Option<&RangeInclusive<u16>>,
+    ) -> Result<RenderReport, String> {
+        let ttf = FontRef::try_from_slice(ttf).map_err(|e| e.to_string())?;
+        let scaled = ttf.as_scaled(PxScale::from(size));
+        let cell = self.cell_size as f32;
+        // center the line's height in the cell
+        let baseline = (cell - (scaled.ascent() - scaled.descent())) / 2.0 + scaled.ascent();
+
+        let mut report = RenderReport::default();
+        for (glyph, c) in charset.iter() {
+            if glyphs.is_some_and(|glyphs| !glyphs.contains(&glyph)) {
+                continue;
+            }
+            let id = scaled.glyph_id(c);
+            if id.0 == 0 && !c.is_whitespace() {
+                report.missing.push(c);
+                continue;
+            }
+
+            self.reserve(glyph);
+            let (cell_x, cell_y) = self.cell(glyph);
+            let mut tile = Image::new(self.cell_size, self.cell_size);
+            let outline = ttf.outline_glyph(
+                id.with_scale_and_position(PxScale::from(size), ab_glyph::point(0.0, baseline)),
+            );
+            if let Some(outline) = outline {
+                let bounds = outline.px_bounds();
+                outline.draw(|x, y, coverage| {
+                    let x = i64::from(x) + bounds.min.x as i64;
+                    let y = i64::from(y) + bounds.min.y as i64;
+                    let alpha = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+                    tile.set_pixel(x, y, [0xff, 0xff, 0xff, alpha]);
+                });
+            }
+            // the cell is replaced outright, not drawn over
+            for y in 0..i64::from(self.cell_size) {
+                for x in 0..i64::from(self.cell_size) {
+                    self.atlas
+                        .set_pixel(cell_x + x, cell_y + y, tile.pixel(x, y));
+                }
+            }
+
+            let advance = scaled.h_advance(id).round().clamp(0.0, cell.min(255.0)) as u8;
+            self.widths[usize::from(glyph)] = advance;
+            report.rendered += 1;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: [u8; 4] = [0xff; 4];
+
+    // a TrueType font 1000 units to the em, whose only glyph is an "A" drawn as a square from
+    // (100, 0) to (500, 400), advancing 600; the empty glyph 0 advances 500
+    fn tiny_ttf() -> Vec<u8> {
+        fn be(values: &[i16]) -> Vec<u8> {
+            values.iter().flat_map(|v| v.to_be_bytes()).collect()
+        }
+        let head = [
+            &[0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0x5f, 0x0f, 0x3c, 0xf5][..],
+            &be(&[0, 1000]),
+            &[0; 16],
+            &be(&[100, 0, 500, 400, 0, 8, 2, 0, 0]),
+        ]
+        .concat();
+        let hhea = [
+            &[0, 1, 0, 0][..],
+            &be(&[800, -200, 0, 600, 0, 0, 500, 1, 0, 0, 0, 0, 0, 0, 0, 2]),
+        ]
+        .concat();
+        let maxp = [&[0, 0, 0x50, 0][..], &be(&[2])].concat();
+        // a format 6 subtable mapping just U+0041
+        let cmap = be(&[0, 1, 3, 1, 0, 12, 6, 12, 0, 0x41, 1, 1]);
+        let hmtx = be(&[500, 0, 600, 100]);
+        let glyf = be(&[1, 100, 0, 500, 400, 3, 0])
+            .into_iter()
+            .chain([1; 4])
+            .chain(be(&[100, 400, 0, -400, 0, 0, 400, 0]))
+            .collect::<Vec<_>>();
+        let loca = be(&[0, 0, i16::try_from(glyf.len() / 2).unwrap()]);
+
+        let tables: [(&[u8], &[u8]); 7] = [
+            (b"cmap", &cmap),
+            (b"glyf", &glyf),
+            (b"head", &head),
+            (b"hhea", &hhea),
+            (b"hmtx", &hmtx),
+            (b"loca", &loca),
+            (b"maxp", &maxp),
+        ];
+        let mut font = [&[0, 1, 0, 0][..], &be(&[7, 64, 2, 48])].concat();
+        let mut data = Vec::new();
+        for (tag, table) in tables {
+            let offset = 12 + 16 * tables.len() + data.len();
+            font.extend(tag);
+            font.extend([0; 4]);
+            font.extend(u32::try_from(offset).unwrap().to_be_bytes());
+            font.extend(u32::try_from(table.len()).unwrap().to_be_bytes());
+            data.extend(table);
+            data.resize(data.len().next_multiple_of(4), 0);
+        }
+        font.extend(data);
+        font
+    }
+
+    #[test]
+    fn cells_run_row_by_row() {
+        let font = Font::new(Image::new(100, 64), vec![], 32);
+        assert_eq!((font.columns(), font.capacity()), (3, 6));
+        assert_eq!(font.cell(2), (64, 0));
+        assert_eq!(font.cell(4), (32, 32));
+    }
+
+    #[test]
+    fn renders_glyphs_into_their_cells() {
+        let mut atlas = Image::new(64, 32);
+        atlas.set_pixel(20, 20, WHITE);
+        let mut font = Font::new(atlas, vec![9, 9], 32);
+        let charset = Charset::parse("test", " éxA").unwrap();
+
+        let report = font.render_ttf(&tiny_ttf(), 32.0, &charset, None).unwrap();
+        assert_eq!(
+            report,
+            RenderReport {
+                rendered: 2,
+                missing: vec!['é', 'x'],
+            }
+        );
+        // "A" is glyph 3, so a row of cells was added for it
+        assert_eq!((font.atlas.width, font.atlas.height), (64, 64));
+        assert_eq!(font.widths, [16, 9, 0, 19]);
+
+        // the space's cell was cleared, and the square sits on a baseline centered in its cell
+        assert_eq!(font.atlas.pixel(20, 20), [0; 4]);
+        let (x, y) = font.cell(3);
+        assert_eq!(font.atlas.pixel(x + 8, y + 20), WHITE);
+        assert_eq!(font.atlas.pixel(x + 8, y + 8), [0; 4]);
+        assert_eq!(font.atlas.pixel(x + 20, y + 20), [0; 4]);
+    }
+
+    #[test]
+    fn only_renders_the_given_glyphs() {
+        let mut font = Font::new(Image::new(64, 64), vec![], 32);
+        let charset = Charset::parse("test", " éxA").unwrap();
+
+        let report = font
+            .render_ttf(&tiny_ttf(), 32.0, &charset, Some(&(3..=3)))
+            .unwrap();
+        assert_eq!(report.rendered, 1);
+        assert!(report.missing.is_empty());
+        assert_eq!(font.widths, [0, 0, 0, 19]);
+    }
+
+    #[test]
+    fn rejects_files_that_arent_fonts() {
+        let mut font = Font::new(Image::new(32, 32), vec![], 32);
+        assert!(font
+            .render_ttf(b"not a font", 32.0, &Charset::default(), None)
+            .is_err());
+    }
+}
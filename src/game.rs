@@ -0,0 +1,385 @@
+// Game profiles. Each release has its own set of archives and its own settings for reading them:
+// MPK layout and codecs, the font's charset, the script instruction set, the NPA key. A profile
+// bundles these so `--game` is all it takes, and lists the archives an install has so ungelify
+// can tell which game a directory holds. Profiles are TOML, e.g. the built-in `cclcc` one with
+// the files it leaves out:
+//
+//   id = "cclcc"
+//   title = "Chaos;Child Love Chu Chu!!"
+//   install_dirs = ["CHAOS;CHILD LOVE CHU CHU!!"]
+//   archives = ["bg.mpk", "chara.mpk", "script.mpk", "system.mpk"]
+//   padded_entry_tables = ["chara.mpk"]
+//   mpk_name_encoding = "shift-jis"
+//   codecs = ["1=zlib"]
+//   charset = "cclcc-charset.txt"
+//   instructions = "cclcc.ins"
+//
+// Archive paths are relative to the install's root, other relative paths to the profile. Games
+// with encrypted NPA archives also set `npa_key` to a key file (see `npa::keys`), which is only
+// looked up for encrypted archives.
+//
+// Several releases of the engine ship the same set of archives, so an install is told apart by
+// quirks of its archives, like an entry table padded with empty headers, and failing that by the
+// name of its directory (Steam's, by default).
+
+use crate::mpk::{codec, EntryLayout, MagesArchive, NameEncoding};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GameProfile {
+    pub id: String,
+    pub title: String,
+    /// The names the game's install directory goes by.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub install_dirs: Vec<String>,
+    /// The archives an install of the game has, relative to its root.
+    #[serde(default)]
+    pub archives: Vec<String>,
+    /// Archives whose header counts more entries than they have, padding the entry table with
+    /// empty headers.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub padded_entry_tables: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mpk_layout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mpk_name_encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub codecs: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub npa_key: Option<String>,
+}
+
+/// Profiles for the PC releases ungelify is used on, as TOML.
+///
+/// They only set what every release of the engine agrees on; charsets, instruction sets and
+/// keys are left to the user until they're known to match retail data.
+pub static KNOWN_PROFILES: &[&str] = &[
+    r#"
+    id = "sg"
+    title = "Steins;Gate"
+    install_dirs = ["STEINS;GATE"]
+    archives = ["bg.mpk", "chara.mpk", "script.mpk", "system.mpk"]
+    mpk_name_encoding = "shift-jis"
+    codecs = ["1=zlib"]
+    "#,
+    r#"
+    id = "cc"
+    title = "Chaos;Child"
+    install_dirs = ["CHAOS;CHILD"]
+    archives = ["bg.mpk", "chara.mpk", "script.mpk", "system.mpk"]
+    mpk_name_encoding = "shift-jis"
+    codecs = ["1=zlib"]
+    "#,
+    // chara.mpk's last entry header is all 0s, see `MagesArchive::build_with()`
+    r#"
+    id = "cclcc"
+    title = "Chaos;Child Love Chu Chu!!"
+    install_dirs = ["CHAOS;CHILD LOVE CHU CHU!!"]
+    archives = ["bg.mpk", "chara.mpk", "script.mpk", "system.mpk"]
+    padded_entry_tables = ["chara.mpk"]
+    mpk_name_encoding = "shift-jis"
+    codecs = ["1=zlib"]
+    "#,
+    r#"
+    id = "rne"
+    title = "Robotics;Notes Elite"
+    install_dirs = ["ROBOTICS;NOTES ELITE"]
+    archives = ["bg.mpk", "chara.mpk", "script.mpk", "system.mpk"]
+    mpk_name_encoding = "shift-jis"
+    codecs = ["1=zlib"]
+    "#,
+];
+
+impl GameProfile {
+    /// Every profile in [`KNOWN_PROFILES`].
+    #[must_use]
+    pub fn known() -> Vec<Self> {
+        KNOWN_PROFILES
+            .iter()
+            .map(|profile| {
+                Self::parse(profile)
+                    .unwrap_or_else(|e| panic!("built-in game profile is invalid: {e}"))
+            })
+            .collect()
+    }
+
+    /// Looks up `game` in [`KNOWN_PROFILES`] by ID, falling back to treating it as the path to a
+    /// profile file. `None` if it's neither, since `--game` can also just name an NPA key.
+    #[must_use]
+    pub fn for_game(game: &str) -> Option<Self> {
+        if let Some(profile) = Self::known().into_iter().find(|profile| profile.id == game) {
+            return Some(profile);
+        }
+
+        let path = Path::new(game);
+        let is_profile = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+        (is_profile && path.is_file()).then(|| Self::from_file(path))
+    }
+
+    #[must_use]
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).unwrap();
+        let mut profile = Self::parse(&text)
+            .unwrap_or_else(|e| panic!("invalid game profile {}: {e}", path.display()));
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for value in [
+            &mut profile.charset,
+            &mut profile.instructions,
+            &mut profile.npa_key,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            let file = dir.join(&*value);
+            if file.is_file() {
+                *value = file.display().to_string();
+            }
+        }
+        profile
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let profile: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        if profile.id.is_empty() {
+            return Err("the profile has no ID".to_string());
+        }
+        if let Some(layout) = &profile.mpk_layout {
+            layout.parse::<EntryLayout>()?;
+        }
+        if let Some(encoding) = &profile.mpk_name_encoding {
+            encoding.parse::<NameEncoding>()?;
+        }
+        for assignment in &profile.codecs {
+            codec::parse_assignment(assignment)?;
+        }
+        Ok(profile)
+    }
+
+    /// The archives of the profile that `dir` has.
+    #[must_use]
+    pub fn archives_in(&self, dir: &Path) -> Vec<&str> {
+        self.archives
+            .iter()
+            .map(String::as_str)
+            .filter(|archive| dir.join(archive).is_file())
+            .collect()
+    }
+
+    /// Whether `archive_path` is one of the archives the profile says pads its entry table.
+    #[must_use]
+    pub fn has_padded_entry_table(&self, archive_path: &Path) -> bool {
+        self.padded_entry_tables
+            .iter()
+            .any(|archive| archive_path.ends_with(archive))
+    }
+
+    /// Whether the archives in `dir` have the profile's quirks: every archive it says pads its
+    /// entry table does.
+    fn has_quirks_in(&self, dir: &Path) -> bool {
+        let layout = self
+            .mpk_layout
+            .as_ref()
+            .map(|layout| layout.parse::<EntryLayout>().unwrap());
+        self.padded_entry_tables.iter().all(|archive| {
+            File::open(dir.join(archive)).ok().and_then(|file| {
+                MagesArchive::pads_entry_table(&mut BufReader::new(file), layout.as_ref())
+            }) == Some(true)
+        })
+    }
+
+    /// Which known game is installed in `dir`. Of the ones whose archives `dir` all has, with
+    /// the quirks the profile expects, the one with the most quirks wins, then the one its name
+    /// says, then the one with the most archives. `None` if that's a tie.
+    #[must_use]
+    pub fn identify(dir: &Path) -> Option<Self> {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let dir_name = dir.file_name().map(|name| name.to_string_lossy());
+        let mut candidates = Self::known()
+            .into_iter()
+            .filter(|profile| {
+                !profile.archives.is_empty()
+                    && profile.archives_in(&dir).len() == profile.archives.len()
+                    && profile.has_quirks_in(&dir)
+            })
+            .map(|profile| {
+                let is_named = dir_name.as_ref().is_some_and(|dir_name| {
+                    profile
+                        .install_dirs
+                        .iter()
+                        .any(|install_dir| install_dir.eq_ignore_ascii_case(dir_name))
+                });
+                let quirks = profile.padded_entry_tables.len();
+                ((quirks, is_named, profile.archives.len()), profile)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|(rank, _)| std::cmp::Reverse(*rank));
+
+        match candidates.as_slice() {
+            [(best, _), (next, _), ..] if best == next => None,
+            _ => candidates.into_iter().next().map(|(_, profile)| profile),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpk::Endian;
+    use std::collections::HashSet;
+
+    fn install(root: &Path, dir_name: &str, archives: &[String]) -> std::path::PathBuf {
+        let dir = root.join(dir_name);
+        std::fs::create_dir(&dir).unwrap();
+        for archive in archives {
+            std::fs::write(dir.join(archive), b"MPK\0").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn known_profiles_are_valid() {
+        let profiles = GameProfile::known();
+        let ids = profiles
+            .iter()
+            .map(|profile| profile.id.as_str())
+            .collect::<HashSet<_>>();
+        assert_eq!(ids.len(), profiles.len(), "profile IDs must be unique");
+        for profile in &profiles {
+            assert!(
+                !profile.archives.is_empty(),
+                "{} has no archives",
+                profile.id
+            );
+            for archive in &profile.padded_entry_tables {
+                assert!(
+                    profile.archives.contains(archive),
+                    "{}: {archive}",
+                    profile.id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn identifies_each_game_from_its_install() {
+        let root = tempfile::tempdir().unwrap();
+        for (id, dir_name) in [
+            ("sg", "STEINS;GATE"),
+            ("cc", "CHAOS;CHILD"),
+            ("rne", "ROBOTICS;NOTES ELITE"),
+        ] {
+            let profile = GameProfile::for_game(id).unwrap();
+            let dir = install(root.path(), dir_name, &profile.archives);
+            assert_eq!(GameProfile::identify(&dir).unwrap().id, id);
+            // the directory's name alone isn't enough
+            std::fs::remove_file(dir.join(&profile.archives[0])).unwrap();
+            assert_eq!(GameProfile::identify(&dir), None, "{id}");
+        }
+    }
+
+    #[test]
+    fn same_archives_under_another_name_are_ambiguous() {
+        let root = tempfile::tempdir().unwrap();
+        let archives = GameProfile::for_game("sg").unwrap().archives;
+        let dir = install(root.path(), "game", &archives);
+        assert_eq!(GameProfile::identify(&dir), None);
+    }
+
+    #[test]
+    fn chaos_child_lcc_is_told_apart_by_its_padded_chara_mpk() {
+        let root = tempfile::tempdir().unwrap();
+        let archives = GameProfile::for_game("cc").unwrap().archives;
+        let entries: &[(&str, &[u8], u32)] = &[("KUN_A.png", b"png", 0)];
+        for (dir_name, empty_headers, id) in [
+            ("game", 1, Some("cclcc")),
+            ("CHAOS;CHILD", 0, Some("cc")),
+            // the name says cclcc, but the archive doesn't, which leaves any of the others
+            ("CHAOS;CHILD LOVE CHU CHU!!", 0, None),
+        ] {
+            let dir = install(root.path(), dir_name, &archives);
+            let chara = crate::mpk::build_mpk((2, 0), Endian::Little, entries, empty_headers);
+            std::fs::write(dir.join("chara.mpk"), chara).unwrap();
+            assert_eq!(
+                GameProfile::identify(&dir).map(|profile| profile.id),
+                id.map(str::to_string),
+                "{dir_name}"
+            );
+        }
+    }
+
+    #[test]
+    fn chaos_child_lcc_pads_charas_entry_table() {
+        let profile = GameProfile::for_game("cclcc").unwrap();
+        assert!(profile.has_padded_entry_table(Path::new("install/chara.mpk")));
+        assert!(!profile.has_padded_entry_table(Path::new("install/script.mpk")));
+        assert!(!GameProfile::for_game("cc")
+            .unwrap()
+            .has_padded_entry_table(Path::new("chara.mpk")));
+    }
+
+    const PROFILE: &str = r#"
+        id = "test"
+        title = "Test;Game"
+        archives = ["script.mpk", "sub/chara.mpk"]
+        mpk_layout = "v1"
+        codecs = ["2=xor:0x5a"]
+        charset = "charset.txt"
+        instructions = "builtin"
+    "#;
+
+    #[test]
+    fn profile_files_resolve_paths_next_to_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.toml");
+        std::fs::write(&path, PROFILE).unwrap();
+        std::fs::write(dir.path().join("charset.txt"), "abc").unwrap();
+
+        let profile = GameProfile::for_game(&path.display().to_string()).unwrap();
+        assert_eq!(profile.id, "test");
+        assert_eq!(
+            profile.charset,
+            Some(dir.path().join("charset.txt").display().to_string())
+        );
+        // no file by that name, so it's a built-in one
+        assert_eq!(profile.instructions.as_deref(), Some("builtin"));
+
+        // anything else could be an NPA key's name
+        assert_eq!(GameProfile::for_game("some-key"), None);
+    }
+
+    #[test]
+    fn rejects_bad_profiles() {
+        for text in [
+            "title = \"no ID\"",
+            "id = \"\"\ntitle = \"\"",
+            "id = \"x\"\ntitle = \"x\"\nmpk_layout = \"v9\"",
+            "id = \"x\"\ntitle = \"x\"\ncodecs = [\"2=rot13\"]",
+            "id = \"x\"\ntitle = \"x\"\narchvies = []",
+        ] {
+            assert!(GameProfile::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn lists_the_archives_an_install_has() {
+        let profile = GameProfile::parse(PROFILE).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        assert!(profile.archives_in(dir.path()).is_empty());
+
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/chara.mpk"), b"MPK\0").unwrap();
+        assert_eq!(profile.archives_in(dir.path()), ["sub/chara.mpk"]);
+    }
+}
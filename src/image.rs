@@ -0,0 +1,141 @@
+// A minimal 8-bit RGBA image, just enough to cut sprites and fonts out of the PNG atlases the
+// games ship and put them back together.
+
+use png::{BitDepth, ColorType, Transformations};
+use std::io::{Cursor, Write};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA, row by row.
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// A fully transparent image.
+    #[must_use]
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    /// Decodes a PNG of any color type into RGBA.
+    #[must_use]
+    pub fn from_png(data: &[u8]) -> Self {
+        let mut decoder = png::Decoder::new(Cursor::new(data));
+        decoder
+            .set_transformations(Transformations::normalize_to_color8() | Transformations::ALPHA);
+        let mut reader = decoder.read_info().expect("invalid PNG");
+        let mut buf = vec![0; reader.output_buffer_size().expect("PNG too large")];
+        let info = reader.next_frame(&mut buf).expect("failed to decode PNG");
+        buf.truncate(info.buffer_size());
+
+        let pixels = match info.color_type {
+            ColorType::Rgba => buf,
+            ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 0xff])
+                .collect(),
+            ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            ColorType::Grayscale => buf.iter().flat_map(|&p| [p, p, p, 0xff]).collect(),
+            ColorType::Indexed => unreachable!("palettes are expanded while decoding"),
+        };
+        Self {
+            width: info.width,
+            height: info.height,
+            pixels,
+        }
+    }
+
+    pub fn write_png<W: Write>(&self, writer: W) {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder.write_header().expect("failed to write PNG header");
+        writer
+            .write_image_data(&self.pixels)
+            .expect("failed to write PNG");
+    }
+
+    #[must_use]
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut png = Vec::new();
+        self.write_png(&mut png);
+        png
+    }
+
+    // where the pixel at (`x`, `y`) starts, if it's inside the image
+    fn index(&self, x: i64, y: i64) -> Option<usize> {
+        let x = u32::try_from(x).ok().filter(|&x| x < self.width)?;
+        let y = u32::try_from(y).ok().filter(|&y| y < self.height)?;
+        Some((y as usize * self.width as usize + x as usize) * 4)
+    }
+
+    /// The pixel at (`x`, `y`), or transparent outside the image.
+    #[must_use]
+    pub fn pixel(&self, x: i64, y: i64) -> [u8; 4] {
+        self.index(x, y)
+            .map_or([0; 4], |i| self.pixels[i..i + 4].try_into().unwrap())
+    }
+
+    /// Sets the pixel at (`x`, `y`), ignoring anything outside the image.
+    pub fn set_pixel(&mut self, x: i64, y: i64, pixel: [u8; 4]) {
+        if let Some(i) = self.index(x, y) {
+            self.pixels[i..i + 4].copy_from_slice(&pixel);
+        }
+    }
+
+    /// The `width`×`height` block at (`x`, `y`), transparent wherever it's outside the image.
+    #[must_use]
+    pub fn crop(&self, (x, y): (i64, i64), (width, height): (u32, u32)) -> Self {
+        let mut image = Self::new(width, height);
+        image.draw(self, (x, y), (0, 0), (width, height));
+        image
+    }
+
+    /// Copies a `width`×`height` block from `src` at (`src_x`, `src_y`) to (`x`, `y`), drawing it
+    /// over what's already there.
+    pub fn draw(
+        &mut self,
+        src: &Self,
+        (src_x, src_y): (i64, i64),
+        (x, y): (i64, i64),
+        (width, height): (u32, u32),
+    ) {
+        for dy in 0..i64::from(height) {
+            for dx in 0..i64::from(width) {
+                let over = src.pixel(src_x + dx, src_y + dy);
+                let under = self.pixel(x + dx, y + dy);
+                self.set_pixel(x + dx, y + dy, blend(over, under));
+            }
+        }
+    }
+}
+
+// Porter-Duff "over" with straight alpha
+fn blend(over: [u8; 4], under: [u8; 4]) -> [u8; 4] {
+    let over_a = u32::from(over[3]);
+    if over_a == 0xff || under[3] == 0 {
+        return over;
+    }
+    if over_a == 0 {
+        return under;
+    }
+
+    let under_a = u32::from(under[3]) * (0xff - over_a) / 0xff;
+    let out_a = over_a + under_a;
+    let mut out = [0; 4];
+    for c in 0..3 {
+        let value = (u32::from(over[c]) * over_a + u32::from(under[c]) * under_a) / out_a;
+        out[c] = u8::try_from(value).unwrap();
+    }
+    out[3] = u8::try_from(out_a).unwrap();
+    out
+}
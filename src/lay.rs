@@ -0,0 +1,494 @@
+// Sprite layouts (`.lay`). Character sprites are shipped as a PNG atlas of square tiles plus a
+// layout saying where each tile goes on screen. The layout is a list of states (a pose, a set of
+// eyes, a mouth, ...), each of which is a run of chunks, and a chunk places one tile:
+//
+//   u32 state count
+//   u32 chunk count
+//   states, 12 bytes each:
+//     u32 ID
+//     u32 index of its first chunk
+//     u32 number of chunks
+//   chunks, 16 bytes each:
+//     f32 x, y    where the tile goes, relative to the sprite's origin
+//     f32 u, v    the tile's top-left corner in the atlas
+//
+// Layouts don't have a magic number, and all values are little-endian. A full character is
+// composited by drawing a base state and then any face states over it, in the same coordinates.
+
+use crate::image::Image;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+pub(crate) const HEADER_LEN: u64 = 8;
+pub(crate) const STATE_LEN: u64 = 12;
+pub(crate) const CHUNK_LEN: u64 = 16;
+
+/// The width and height of a tile in the atlas.
+pub const TILE_SIZE: u32 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    pub id: u32,
+    pub first_chunk: u32,
+    pub chunk_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Chunk {
+    pub x: f32,
+    pub y: f32,
+    pub u: f32,
+    pub v: f32,
+}
+
+impl Chunk {
+    #[allow(clippy::cast_possible_truncation)]
+    const fn pos(self) -> (i64, i64) {
+        (self.x.round() as i64, self.y.round() as i64)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    const fn tile(self) -> (i64, i64) {
+        (self.u.round() as i64, self.v.round() as i64)
+    }
+}
+
+/// The area a set of states covers, in layout coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    pub x: i64,
+    pub y: i64,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    pub states: Vec<State>,
+    pub chunks: Vec<Chunk>,
+}
+
+fn read_u32(data: &[u8], pos: u64) -> u32 {
+    let pos = usize::try_from(pos).unwrap();
+    let bytes = data
+        .get(pos..pos + 4)
+        .unwrap_or_else(|| panic!("layout truncated at {pos:#x}"));
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
+
+fn read_f32(data: &[u8], pos: u64) -> f32 {
+    f32::from_bits(read_u32(data, pos))
+}
+
+impl Layout {
+    #[must_use]
+    pub fn parse(data: &[u8]) -> Self {
+        let state_count = u64::from(read_u32(data, 0));
+        let chunk_count = u64::from(read_u32(data, 4));
+        let chunks_start = HEADER_LEN + state_count * STATE_LEN;
+
+        let states = (0..state_count)
+            .map(|i| {
+                let pos = HEADER_LEN + i * STATE_LEN;
+                State {
+                    id: read_u32(data, pos),
+                    first_chunk: read_u32(data, pos + 4),
+                    chunk_count: read_u32(data, pos + 8),
+                }
+            })
+            .collect::<Vec<_>>();
+        let chunks = (0..chunk_count)
+            .map(|i| {
+                let pos = chunks_start + i * CHUNK_LEN;
+                Chunk {
+                    x: read_f32(data, pos),
+                    y: read_f32(data, pos + 4),
+                    u: read_f32(data, pos + 8),
+                    v: read_f32(data, pos + 12),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for (i, state) in states.iter().enumerate() {
+            assert!(
+                u64::from(state.first_chunk) + u64::from(state.chunk_count) <= chunk_count,
+                "state {i} refers to chunks past the end of the layout"
+            );
+        }
+        Self { states, chunks }
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(u32::try_from(self.states.len()).unwrap().to_le_bytes());
+        data.extend(u32::try_from(self.chunks.len()).unwrap().to_le_bytes());
+        for state in &self.states {
+            data.extend(state.id.to_le_bytes());
+            data.extend(state.first_chunk.to_le_bytes());
+            data.extend(state.chunk_count.to_le_bytes());
+        }
+        for chunk in &self.chunks {
+            for value in [chunk.x, chunk.y, chunk.u, chunk.v] {
+                data.extend(value.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    /// The chunks that make up state `index`.
+    #[must_use]
+    pub fn chunks_of(&self, index: usize) -> &[Chunk] {
+        let state = self.states[index];
+        let start = state.first_chunk as usize;
+        &self.chunks[start..start + state.chunk_count as usize]
+    }
+
+    /// The smallest area covering every tile of `states`, or `None` if they have no chunks.
+    #[must_use]
+    pub fn bounds(&self, states: &[usize]) -> Option<Bounds> {
+        let positions = states
+            .iter()
+            .flat_map(|&state| self.chunks_of(state))
+            .map(|chunk| chunk.pos());
+        let (min_x, min_y, max_x, max_y) = positions.fold(None, |bounds, (x, y)| {
+            let (min_x, min_y, max_x, max_y) = bounds.unwrap_or((x, y, x, y));
+            Some((min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)))
+        })?;
+
+        let tile = i64::from(TILE_SIZE);
+        Some(Bounds {
+            x: min_x,
+            y: min_y,
+            width: u32::try_from(max_x + tile - min_x).expect("sprite too wide"),
+            height: u32::try_from(max_y + tile - min_y).expect("sprite too tall"),
+        })
+    }
+
+    /// Composites `states` from the tiles in `atlas`, drawing each over the ones before it.
+    #[must_use]
+    pub fn render(&self, atlas: &Image, states: &[usize]) -> Image {
+        for &state in states {
+            assert!(
+                state < self.states.len(),
+                "no state {state}, the layout has {}",
+                self.states.len()
+            );
+        }
+        let bounds = self
+            .bounds(states)
+            .expect("the selected states have no chunks to draw");
+
+        let mut image = Image::new(bounds.width, bounds.height);
+        for &state in states {
+            for chunk in self.chunks_of(state) {
+                let (x, y) = chunk.pos();
+                image.draw(
+                    atlas,
+                    chunk.tile(),
+                    (x - bounds.x, y - bounds.y),
+                    (TILE_SIZE, TILE_SIZE),
+                );
+            }
+        }
+        image
+    }
+
+    /// Re-slices edited renders of single states back into tiles, giving a new layout and atlas.
+    ///
+    /// Each edit is a state and an image of it as [`render`](Self::render) drew it. Tiles of other
+    /// states are kept as they were. The atlas is rebuilt from scratch with identical tiles
+    /// merged, so edits never leak into other states sharing a tile; it keeps the old atlas's
+    /// width and grows taller only if the tiles don't fit anymore.
+    #[must_use]
+    pub fn rebuild(&self, atlas: &Image, edits: &[(usize, Image)]) -> (Self, Image) {
+        let mut edited = HashMap::new();
+        for (state, image) in edits {
+            let bounds = self.bounds(&[*state]).unwrap_or_else(|| {
+                panic!("state {state} has no chunks, so there's nothing to rebuild")
+            });
+            assert_eq!(
+                (image.width, image.height),
+                (bounds.width, bounds.height),
+                "the image for state {state} has to be the same size as its render"
+            );
+            assert!(
+                edited.insert(*state, (image, bounds)).is_none(),
+                "state {state} was given more than once"
+            );
+        }
+
+        // which state each chunk belongs to, if any
+        let mut chunk_states = vec![None; self.chunks.len()];
+        for (i, state) in self.states.iter().enumerate() {
+            let start = state.first_chunk as usize;
+            for chunk_state in &mut chunk_states[start..start + state.chunk_count as usize] {
+                *chunk_state = Some(i);
+            }
+        }
+
+        let mut tiles = Vec::<Image>::new();
+        let mut tile_indices = HashMap::<Vec<u8>, usize>::new();
+        let mut chunk_tiles = Vec::with_capacity(self.chunks.len());
+        for (chunk, state) in self.chunks.iter().zip(chunk_states) {
+            let tile = match state.and_then(|state| edited.get(&state)) {
+                Some((image, bounds)) => {
+                    let (x, y) = chunk.pos();
+                    image.crop((x - bounds.x, y - bounds.y), (TILE_SIZE, TILE_SIZE))
+                }
+                None => atlas.crop(chunk.tile(), (TILE_SIZE, TILE_SIZE)),
+            };
+            let index = *tile_indices.entry(tile.pixels.clone()).or_insert_with(|| {
+                tiles.push(tile);
+                tiles.len() - 1
+            });
+            chunk_tiles.push(index);
+        }
+
+        let columns = (atlas.width / TILE_SIZE).max(1);
+        let rows = u32::try_from(tiles.len()).unwrap().div_ceil(columns);
+        let mut height = atlas.height.max(TILE_SIZE);
+        while height / TILE_SIZE < rows {
+            height *= 2;
+        }
+        let tile_pos = |index: usize| {
+            let index = u32::try_from(index).unwrap();
+            ((index % columns) * TILE_SIZE, (index / columns) * TILE_SIZE)
+        };
+
+        let mut new_atlas = Image::new(atlas.width.max(TILE_SIZE), height);
+        for (i, tile) in tiles.iter().enumerate() {
+            let (u, v) = tile_pos(i);
+            new_atlas.draw(
+                tile,
+                (0, 0),
+                (i64::from(u), i64::from(v)),
+                (TILE_SIZE, TILE_SIZE),
+            );
+        }
+
+        let mut layout = self.clone();
+        for (chunk, index) in layout.chunks.iter_mut().zip(chunk_tiles) {
+            let (u, v) = tile_pos(index);
+            // atlases are far smaller than 2^16 pixels across, so these are exact
+            chunk.u = f32::from(u16::try_from(u).expect("atlas too large"));
+            chunk.v = f32::from(u16::try_from(v).expect("atlas too large"));
+        }
+        (layout, new_atlas)
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<7}{:<12}{:<8}Size", "State", "ID", "Chunks")?;
+        for (i, state) in self.states.iter().enumerate() {
+            let size = self.bounds(&[i]).map_or_else(String::new, |bounds| {
+                format!("{}x{}", bounds.width, bounds.height)
+            });
+            writeln!(
+                f,
+                "{i:<7}{:<12}{:<8}{size}",
+                format!("{:#010x}", state.id),
+                state.chunk_count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 4] = [0xff, 0, 0, 0xff];
+    const GREEN: [u8; 4] = [0, 0xff, 0, 0xff];
+    const BLUE: [u8; 4] = [0, 0, 0xff, 0xff];
+
+    // a body of two tiles and a face covering the left half of the second one
+    fn layout() -> Layout {
+        let chunk = |x, y, u, v| Chunk { x, y, u, v };
+        Layout {
+            states: vec![
+                State {
+                    id: 0x10,
+                    first_chunk: 0,
+                    chunk_count: 2,
+                },
+                State {
+                    id: 0x20,
+                    first_chunk: 2,
+                    chunk_count: 1,
+                },
+            ],
+            chunks: vec![
+                chunk(-32.0, 0.0, 0.0, 0.0),
+                chunk(0.0, 0.0, 32.0, 0.0),
+                chunk(0.0, 0.0, 0.0, 32.0),
+            ],
+        }
+    }
+
+    fn atlas() -> Image {
+        let mut atlas = Image::new(64, 64);
+        for y in 0..32 {
+            for x in 0..32 {
+                atlas.set_pixel(x, y, RED);
+                atlas.set_pixel(x + 32, y, GREEN);
+                if x < 16 {
+                    atlas.set_pixel(x, y + 32, BLUE);
+                }
+            }
+        }
+        atlas
+    }
+
+    #[test]
+    fn parses_and_writes_back_layouts() {
+        let mut data = Vec::new();
+        for value in [2u32, 3, 0x10, 0, 2, 0x20, 2, 1] {
+            data.extend(value.to_le_bytes());
+        }
+        for value in [
+            -32.0f32, 0.0, 0.0, 0.0, 0.0, 0.0, 32.0, 0.0, 0.0, 0.0, 0.0, 32.0,
+        ] {
+            data.extend(value.to_le_bytes());
+        }
+
+        let parsed = Layout::parse(&data);
+        assert_eq!(parsed, layout());
+        assert_eq!(parsed.to_bytes(), data);
+        assert_eq!(parsed.chunks_of(1), &parsed.chunks[2..]);
+    }
+
+    #[test]
+    #[should_panic(expected = "state 1 refers to chunks past the end of the layout")]
+    fn rejects_states_past_the_last_chunk() {
+        let mut layout = layout();
+        layout.states[1].chunk_count = 2;
+        let _ = Layout::parse(&layout.to_bytes());
+    }
+
+    #[test]
+    fn renders_states_over_each_other() {
+        let layout = layout();
+        assert_eq!(
+            layout.bounds(&[0, 1]),
+            Some(Bounds {
+                x: -32,
+                y: 0,
+                width: 64,
+                height: 32,
+            })
+        );
+
+        let body = layout.render(&atlas(), &[0]);
+        assert_eq!((body.pixel(0, 0), body.pixel(32, 0)), (RED, GREEN));
+        let face = layout.render(&atlas(), &[1]);
+        assert_eq!((face.width, face.height), (32, 32));
+        assert_eq!((face.pixel(0, 0), face.pixel(16, 0)), (BLUE, [0; 4]));
+
+        let both = layout.render(&atlas(), &[0, 1]);
+        assert_eq!(
+            [0, 31, 32, 47, 48, 63].map(|x| both.pixel(x, 31)),
+            [RED, RED, BLUE, BLUE, GREEN, GREEN]
+        );
+    }
+
+    #[test]
+    fn lists_states_with_their_sizes() {
+        assert_eq!(
+            layout().to_string(),
+            "State  ID          Chunks  Size\n\
+             0      0x00000010  2       64x32\n\
+             1      0x00000020  1       32x32\n"
+        );
+    }
+
+    // the body, the face, and a second face reusing the body's green tile
+    fn shared_layout() -> Layout {
+        let mut layout = layout();
+        layout.states.push(State {
+            id: 0x30,
+            first_chunk: 3,
+            chunk_count: 1,
+        });
+        layout.chunks.push(layout.chunks[1]);
+        layout
+    }
+
+    fn filled(width: u32, colors: &[[u8; 4]]) -> Image {
+        let mut image = Image::new(width, TILE_SIZE);
+        for x in 0..i64::from(width) {
+            for y in 0..i64::from(TILE_SIZE) {
+                image.set_pixel(x, y, colors[usize::try_from(x / 32).unwrap()]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn rebuilding_without_edits_keeps_every_render() {
+        let layout = shared_layout();
+        let atlas = atlas();
+        let (rebuilt, new_atlas) = layout.rebuild(&atlas, &[]);
+
+        assert_eq!(rebuilt.states, layout.states);
+        assert_eq!((new_atlas.width, new_atlas.height), (64, 64));
+        for states in [&[0][..], &[1], &[2], &[0, 1]] {
+            assert_eq!(
+                rebuilt.render(&new_atlas, states),
+                layout.render(&atlas, states)
+            );
+        }
+        // the shared tile is still stored once
+        assert_eq!(rebuilt.chunks[1].tile(), rebuilt.chunks[3].tile());
+    }
+
+    #[test]
+    fn edits_dont_leak_into_states_sharing_a_tile() {
+        let layout = shared_layout();
+        let atlas = atlas();
+        let yellow = filled(32, &[[0xff, 0xff, 0, 0xff]]);
+        let (rebuilt, new_atlas) = layout.rebuild(&atlas, &[(2, yellow.clone())]);
+
+        assert_eq!(rebuilt.render(&new_atlas, &[2]), yellow);
+        for states in [&[0][..], &[1], &[0, 1]] {
+            assert_eq!(
+                rebuilt.render(&new_atlas, states),
+                layout.render(&atlas, states)
+            );
+        }
+        assert_ne!(rebuilt.chunks[1].tile(), rebuilt.chunks[3].tile());
+    }
+
+    #[test]
+    fn the_atlas_grows_taller_when_tiles_dont_fit() {
+        let layout = Layout {
+            states: vec![State {
+                id: 0,
+                first_chunk: 0,
+                chunk_count: 5,
+            }],
+            chunks: [0.0, 32.0, 64.0, 96.0, 128.0]
+                .map(|x| Chunk {
+                    x,
+                    y: 0.0,
+                    u: 0.0,
+                    v: 0.0,
+                })
+                .to_vec(),
+        };
+        let edit = filled(160, &[RED, GREEN, BLUE, [0xff; 4], [0, 0, 0, 0xff]]);
+        let (rebuilt, new_atlas) = layout.rebuild(&atlas(), &[(0, edit.clone())]);
+
+        assert_eq!((new_atlas.width, new_atlas.height), (64, 128));
+        assert_eq!(rebuilt.render(&new_atlas, &[0]), edit);
+    }
+
+    #[test]
+    #[should_panic(expected = "the image for state 1 has to be the same size as its render")]
+    fn edits_have_to_match_their_render() {
+        let _ = layout().rebuild(&atlas(), &[(1, Image::new(64, 32))]);
+    }
+}
@@ -5,7 +5,23 @@
 
 use std::path::{Path, PathBuf};
 
+pub mod afs;
+pub mod archive;
+pub mod batch;
+mod bytes;
+pub mod config;
+pub mod convert;
+pub mod cpk;
+pub mod font;
+pub mod game;
+pub mod image;
+pub mod lay;
 pub mod mpk;
+pub mod npa;
+pub mod sc3;
+pub mod select;
+pub mod sniff;
+pub mod stats;
 
 // If the archive path has an extension, use the stem as the output directory.
 // Otherwise, use the archive name with a ".d" suffix.
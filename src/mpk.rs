@@ -1,10 +1,19 @@
 mod archive;
 mod bytes;
+pub mod codec;
+mod compression;
 mod entry;
 mod iter;
+mod layout;
 
 pub use archive::MagesArchive;
+pub use bytes::{Endian, NameEncoding};
+pub use codec::{Codec, Codecs};
+pub use compression::Compression;
+#[cfg(test)]
+pub(crate) use archive::tests::build_mpk;
 pub use entry::MagesEntry;
+pub use layout::{EntryLayout, Field, KNOWN_VERSIONS};
 
 pub use iter::Entries;
 pub use iter::EntriesMut;
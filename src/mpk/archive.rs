@@ -1,11 +1,14 @@
+use crate::archive::{Archive, ArchiveEntry, ReadSeek, WriteSeek};
 use crate::mpk::bytes;
-use crate::mpk::bytes::{MpkEntryV1, MpkEntryV2, MpkHeader};
+use crate::mpk::bytes::{Endian, MpkHeader, NameEncoding};
+use crate::mpk::codec::Codecs;
 use crate::mpk::entry::MagesEntry;
 use crate::mpk::iter::{Entries, EntriesMut, IntoEntries};
-use bytesize::ByteSize;
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use crate::mpk::layout::EntryLayout;
+use crate::select::EntrySelector;
+use crate::sniff::FileType;
 use indexmap::IndexMap;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io;
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
@@ -15,7 +18,11 @@ use std::path::{Path, PathBuf};
 pub struct MagesArchive {
     entries: IndexMap<u32, MagesEntry>,
     names_to_ids: HashMap<String, u32>,
-    is_old_format: bool,
+    header_slots: HashMap<u32, u64>, // ID => index of the entry's header in the table
+    layout: EntryLayout,
+    is_custom_layout: bool,
+    endian: Endian,
+    names: NameEncoding,
     // Bookkeeping for repacking
     pub(super) ver_major: u16,
     pub(super) ver_minor: u16,
@@ -27,9 +34,43 @@ impl MagesArchive {
     const FIRST_HEADER_OFFSET: u64 = 0x40; // first entry header, aka size of the MPK header
 
     pub fn build<R: Read>(reader: &mut R) -> Self {
-        let header: MpkHeader = bytes::read_struct(reader);
+        Self::build_with(reader, None, NameEncoding::default())
+    }
+
+    /// Like `build()`, but reads the entry table with `layout` instead of the one registered
+    /// for the archive's version, which is the only way to open versions ungelify doesn't know,
+    /// and entry names as `names`.
+    pub fn build_with<R: Read>(
+        reader: &mut R,
+        layout: Option<&EntryLayout>,
+        names: NameEncoding,
+    ) -> Self {
+        let mut raw_header = [0; MpkHeader::SIZE];
+        reader
+            .read_exact(&mut raw_header)
+            .expect("failed to read MPK header");
+        let endian = MpkHeader::detect_endian(&raw_header);
+        let header: MpkHeader = bytes::read_struct_as(&mut raw_header.as_slice(), endian);
         assert_eq!(header.signature, Self::MPK_SIG, "invalid MPK signature");
-        let is_old_format = header.ver_major == 1;
+        let codecs = Codecs::default();
+        let is_custom_layout = layout.is_some();
+        let layout = layout
+            .or_else(|| EntryLayout::for_version(header.ver_major, header.ver_minor))
+            .or_else(|| {
+                let layout = EntryLayout::for_major_version(header.ver_major)?;
+                eprintln!(
+                    "warning: unknown MPK version {}.{}, reading it like other {}.x archives",
+                    header.ver_major, header.ver_minor, header.ver_major
+                );
+                Some(layout)
+            })
+            .unwrap_or_else(|| {
+                panic!(
+                    "unsupported MPK version {}.{}; pass --mpk-layout to describe its entry table",
+                    header.ver_major, header.ver_minor
+                )
+            })
+            .clone();
 
         // if usize is 32 and there's (somehow) more than 2^32 entries, we at
         // least want to give it the most capacity possible
@@ -37,15 +78,16 @@ impl MagesArchive {
         let mut entries = IndexMap::with_capacity(header.entry_count as usize);
         #[allow(clippy::cast_possible_truncation)]
         let mut names_to_ids = HashMap::with_capacity(header.entry_count as usize);
+        #[allow(clippy::cast_possible_truncation)]
+        let mut header_slots = HashMap::with_capacity(header.entry_count as usize);
 
-        for _ in 0..header.entry_count {
-            let entry: MagesEntry = if is_old_format {
-                let v1_entry: MpkEntryV1 = bytes::read_struct(reader);
-                v1_entry.into()
-            } else {
-                let v2_entry: MpkEntryV2 = bytes::read_struct(reader);
-                v2_entry.into()
-            };
+        for slot in 0..header.entry_count {
+            let mut raw_entry = vec![0; layout.entry_size];
+            reader
+                .read_exact(&mut raw_entry)
+                .expect("failed to read MPK entry header");
+            let mut entry = layout.read_entry(raw_entry, endian, names);
+            entry.resolve_codec(&codecs);
 
             // there's a known issue where some archives just straight up lie about how many entries
             // they have and at least one entry header is all 0s.
@@ -63,26 +105,76 @@ impl MagesArchive {
             }
 
             names_to_ids.insert(entry.name().to_string(), entry.id());
+            header_slots.insert(entry.id(), slot);
             entries.insert(entry.id(), entry);
         }
 
         Self {
             entries,
             names_to_ids,
-            is_old_format,
+            header_slots,
+            layout,
+            is_custom_layout,
+            endian,
+            names,
             ver_major: header.ver_major,
             ver_minor: header.ver_minor,
             reported_entry_count: header.entry_count,
         }
     }
 
+    /// Whether the archive in `reader` counts more entries in its header than its entry table
+    /// holds, padding the table with all-0 headers the way `build_with()` skips. Only the entry
+    /// table is read, so names in any encoding are fine. `None` if it isn't an MPK archive that
+    /// `layout` (or the one registered for its version) can read.
+    pub fn pads_entry_table<R: Read>(reader: &mut R, layout: Option<&EntryLayout>) -> Option<bool> {
+        let mut raw_header = [0; MpkHeader::SIZE];
+        reader.read_exact(&mut raw_header).ok()?;
+        let endian = MpkHeader::detect_endian(&raw_header);
+        let header: MpkHeader = bytes::read_struct_as(&mut raw_header.as_slice(), endian);
+        if header.signature != Self::MPK_SIG {
+            return None;
+        }
+        let layout = layout
+            .or_else(|| EntryLayout::for_version(header.ver_major, header.ver_minor))
+            .or_else(|| EntryLayout::for_major_version(header.ver_major))?;
+
+        let mut raw_entry = vec![0; layout.entry_size];
+        for _ in 0..header.entry_count {
+            reader.read_exact(&mut raw_entry).ok()?;
+            if layout.offset.read(&raw_entry, endian) == 0 {
+                return Some(true);
+            }
+        }
+        Some(false)
+    }
+
+    /// Decodes and encodes entry data with `codecs` instead of the default ones.
+    pub fn set_codecs(&mut self, codecs: &Codecs) {
+        self.entries
+            .values_mut()
+            .for_each(|entry| entry.resolve_codec(codecs));
+    }
+
+    /// The layout of the archive's entry table.
     #[must_use]
-    pub fn iter(&self) -> Entries {
+    pub const fn layout(&self) -> &EntryLayout {
+        &self.layout
+    }
+
+    /// The byte order of the archive's header and entry table.
+    #[must_use]
+    pub const fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    #[must_use]
+    pub fn iter(&self) -> Entries<'_> {
         Entries::new(&self.entries)
     }
 
     #[must_use]
-    pub fn iter_mut(&mut self) -> EntriesMut {
+    pub fn iter_mut(&mut self) -> EntriesMut<'_> {
         EntriesMut::new(&mut self.entries)
     }
 
@@ -98,24 +190,6 @@ impl MagesArchive {
             .and_then(|id| self.get_entry_by_id(*id))
     }
 
-    #[allow(clippy::print_literal)] // readability >>>
-    pub fn list_entries(&self) {
-        println!("{:<5} {:<20} {:<12} {}", "ID", "Name", "Size", "Offset");
-        println!("================================================");
-
-        for entry in self {
-            // TODO implement Display for entries
-            let cpr_suffix = if entry.is_compressed() { "*" } else { "" };
-            println!(
-                "{:<5} {:<20} {:<12} 0x{:x}",
-                entry.id(),
-                entry.name(),
-                format!("{}{cpr_suffix}", ByteSize::b(entry.len_deflated())),
-                entry.offset()
-            );
-        }
-    }
-
     // Helps with the actual extraction for an entry since the basic functionality is shared
     // between extract() and extract_entries()
     fn do_extraction<R: Read + Seek, P: AsRef<Path>>(
@@ -134,44 +208,29 @@ impl MagesArchive {
             .for_each(|entry| Self::do_extraction(entry, reader, &output_dir));
     }
 
-    // build up efficient structures that we can then query when we run through all the entries
-    // to decide which ones to extract
-    fn build_search_structures(entries_or_ids: &[String]) -> (GlobSet, HashSet<u32>) {
-        let mut globset_builder = GlobSetBuilder::new();
-        let mut extract_ids = HashSet::new();
-        for entry_name in entries_or_ids {
-            if let Ok(id) = entry_name.parse::<u32>() {
-                extract_ids.insert(id);
-            } else {
-                globset_builder.add(Glob::new(entry_name).unwrap());
-            }
-        }
-
-        (
-            globset_builder
-                .build()
-                .expect("error building entry name globset"),
-            extract_ids,
-        )
-    }
-
+    /// Extracts the entries matching any of `entries_or_ids`, which use the syntax of
+    /// [`EntrySelectorBuilder::include()`](crate::select::EntrySelectorBuilder::include).
+    ///
+    /// # Errors
+    ///
+    /// Fails if a pattern is an invalid regex or glob.
     pub fn extract_entries<R: Read + Seek, P: AsRef<Path>>(
         &self,
         reader: &mut R,
         output_dir: P,
         entries_or_ids: &[String],
-    ) {
-        let (extract_globset, extract_ids) = Self::build_search_structures(entries_or_ids);
-        self.iter()
-            .filter(|&entry| {
-                extract_ids.contains(&entry.id()) || extract_globset.is_match(entry.name())
-            })
-            .for_each(|entry| Self::do_extraction(entry, reader, &output_dir));
+    ) -> Result<(), String> {
+        let selector = EntrySelector::builder().includes(entries_or_ids).build()?;
+        let selection = Archive::select(self, reader, &selector);
+        for entry in selection.entries {
+            Self::do_extraction(&self.entries[&entry.id()], reader, &output_dir);
+        }
+        Ok(())
     }
 
     fn write_archive_header<W: Write>(&self, writer: &mut W) {
         let header: MpkHeader = self.into();
-        bytes::write_struct(writer, &header);
+        bytes::write_struct_as(writer, &header, self.endian);
     }
 
     // map of filename => PathBuf so that we can check whether we need to repack an entry
@@ -242,40 +301,29 @@ impl MagesArchive {
         rpk_writer: &mut W,
         rpk_entries: &IndexMap<u32, MagesEntry>,
     ) {
-        if self.is_old_format {
-            rpk_entries
-                .values()
-                .for_each(|rpk_entry| bytes::write_struct(rpk_writer, MpkEntryV1::from(rpk_entry)));
-        } else {
-            rpk_entries
-                .values()
-                .for_each(|rpk_entry| bytes::write_struct(rpk_writer, MpkEntryV2::from(rpk_entry)));
+        for rpk_entry in rpk_entries.values() {
+            rpk_writer
+                .write_all(&self.layout.write_entry(rpk_entry, self.endian, self.names))
+                .unwrap();
         }
     }
 
-    #[allow(clippy::return_self_not_must_use)] // I just wanna repack and be done with it
-    pub fn repack_entries<R, W, P>(
+    // Writes out a whole new archive, laying out entries in their current order starting at
+    // `data_start`. Shared by repack_entries() and compact().
+    fn rebuild<R: Read + Seek, W: Write + Seek>(
         &self,
         orig_reader: &mut R,
         rpk_writer: &mut W,
-        rpk_paths: &[P],
-    ) -> Self
-    where
-        R: Read + Seek,
-        W: Write + Seek,
-        P: AsRef<Path>,
-    {
-        let rpk_paths = Self::build_repack_map(rpk_paths);
-
+        rpk_paths: &HashMap<String, PathBuf>,
+        data_start: u64,
+    ) -> Self {
         self.write_archive_header(rpk_writer);
 
-        rpk_writer
-            .seek(SeekFrom::Start(self.entries[0].offset()))
-            .unwrap();
+        rpk_writer.seek(SeekFrom::Start(data_start)).unwrap();
         let rpk_entries = self
             .iter()
             .map(|entry| {
-                let new_entry = Self::repack_entry(orig_reader, rpk_writer, &rpk_paths, entry);
+                let new_entry = Self::repack_entry(orig_reader, rpk_writer, rpk_paths, entry);
                 (entry.id(), new_entry)
             })
             .collect::<IndexMap<_, _>>();
@@ -288,15 +336,249 @@ impl MagesArchive {
 
         rpk_writer.flush().unwrap();
 
+        // the headers were written back-to-back, so any skipped all-0 slots are gone now
+        let header_slots = rpk_entries
+            .keys()
+            .zip(0..)
+            .map(|(&id, slot)| (id, slot))
+            .collect();
+
         Self {
             entries: rpk_entries,
             names_to_ids: self.names_to_ids.clone(),
-            is_old_format: self.is_old_format,
+            header_slots,
+            layout: self.layout.clone(),
+            is_custom_layout: self.is_custom_layout,
+            endian: self.endian,
+            names: self.names,
             ver_major: self.ver_major,
             ver_minor: self.ver_minor,
             reported_entry_count: self.reported_entry_count,
         }
     }
+
+    #[allow(clippy::return_self_not_must_use)] // I just wanna repack and be done with it
+    pub fn repack_entries<R, W, P>(
+        &self,
+        orig_reader: &mut R,
+        rpk_writer: &mut W,
+        rpk_paths: &[P],
+    ) -> Self
+    where
+        R: Read + Seek,
+        W: Write + Seek,
+        P: AsRef<Path>,
+    {
+        let rpk_paths = Self::build_repack_map(rpk_paths);
+        self.rebuild(
+            orig_reader,
+            rpk_writer,
+            &rpk_paths,
+            self.entries[0].offset(),
+        )
+    }
+
+    // where the entry header table ends, i.e. the earliest point entry data could start
+    const fn header_table_end(&self) -> u64 {
+        Self::FIRST_HEADER_OFFSET + self.reported_entry_count * self.layout.entry_size as u64
+    }
+
+    /// Rewrites the archive with no unused space, keeping only the alignment padding each
+    /// entry requires. Entries are laid out in the archive's current order.
+    #[allow(clippy::return_self_not_must_use)]
+    pub fn compact<R, W>(&self, orig_reader: &mut R, rpk_writer: &mut W) -> Self
+    where
+        R: Read + Seek,
+        W: Write + Seek,
+    {
+        self.rebuild(
+            orig_reader,
+            rpk_writer,
+            &HashMap::new(),
+            self.header_table_end(),
+        )
+    }
+
+    fn write_entry_header<W: Write + Seek>(&self, writer: &mut W, entry: &MagesEntry) {
+        let slot = self.header_slots[&entry.id()];
+        writer
+            .seek(SeekFrom::Start(
+                Self::FIRST_HEADER_OFFSET + slot * self.layout.entry_size as u64,
+            ))
+            .unwrap();
+        writer
+            .write_all(&self.layout.write_entry(entry, self.endian, self.names))
+            .unwrap();
+    }
+
+    // The space an entry is allowed to occupy without clobbering its neighbor, i.e. up to the
+    // start of the next entry's data. The last entry in the file can grow freely.
+    fn available_space(&self, entry: &MagesEntry) -> Option<u64> {
+        self.iter()
+            .map(MagesEntry::offset)
+            .filter(|&offset| offset > entry.offset())
+            .min()
+            .map(|next_offset| next_offset - entry.offset())
+    }
+
+    fn patch_entry<F: Write + Seek>(&mut self, archive: &mut F, entry_id: u32, rpk_path: &Path) {
+        let entry = &self.entries[&entry_id];
+        let src_len = rpk_path.metadata().unwrap().len();
+        let mut src_reader = BufReader::new(File::open(rpk_path).unwrap());
+
+        // the final (compressed) size decides where the data goes, so compressed entries are
+        // encoded into a temp file first
+        let (mut rpk_reader, rpk_len): (Box<dyn Read>, u64) = if entry.is_compressed() {
+            let mut encoded = BufWriter::new(tempfile::tempfile().unwrap());
+            let rpk_len = entry.repack(&mut src_reader, &mut encoded);
+            let mut encoded = encoded.into_inner().unwrap();
+            encoded.rewind().unwrap();
+            (Box::new(BufReader::new(encoded)), rpk_len)
+        } else {
+            (Box::new(src_reader), src_len)
+        };
+
+        let new_offset = if self
+            .available_space(entry)
+            .is_none_or(|space| rpk_len <= space)
+        {
+            archive.seek(SeekFrom::Start(entry.offset())).unwrap()
+        } else {
+            let end = archive.seek(SeekFrom::End(0)).unwrap();
+            bytes::write_alignment_padding(archive, end);
+            archive.stream_position().unwrap()
+        };
+        io::copy(&mut rpk_reader, archive).unwrap();
+
+        let new_entry = entry.updated(new_offset, src_len, rpk_len);
+        self.write_entry_header(archive, &new_entry);
+        self.entries.insert(entry_id, new_entry);
+    }
+
+    /// Replaces entries directly inside `archive` instead of rebuilding it.
+    ///
+    /// A replacement that fits in the space before the next entry overwrites the original
+    /// data; anything larger is appended to the end of the file. Only the headers of replaced
+    /// entries are rewritten. Space left behind by shrunk or relocated entries stays unused
+    /// until the archive is compacted.
+    ///
+    /// Fails without touching `archive` if a file doesn't match any entry.
+    pub fn patch_entries<F, P>(&mut self, archive: &mut F, rpk_paths: &[P]) -> Result<(), String>
+    where
+        F: Write + Seek,
+        P: AsRef<Path>,
+    {
+        let rpk_paths = Self::build_repack_map(rpk_paths);
+        if let Some(name) = rpk_paths
+            .keys()
+            .filter(|name| !self.names_to_ids.contains_key(*name))
+            .min()
+        {
+            return Err(format!("no entry named '{name}' in archive"));
+        }
+
+        let replacements: Vec<_> = self
+            .entries
+            .iter()
+            .filter_map(|(&entry_id, entry)| {
+                rpk_paths
+                    .get(entry.name())
+                    .map(|rpk_path| (entry_id, rpk_path))
+            })
+            .collect();
+        for (entry_id, rpk_path) in replacements {
+            self.patch_entry(archive, entry_id, rpk_path);
+        }
+
+        archive.flush().unwrap();
+        Ok(())
+    }
+
+    /// Rewrites the header and entry table of `archive` in the given byte order, e.g. to turn a
+    /// PC archive into one for a console port. Entry data is left untouched.
+    pub fn convert_endian<F: Write + Seek>(&mut self, archive: &mut F, endian: Endian) {
+        self.endian = endian;
+
+        archive.seek(SeekFrom::Start(0)).unwrap();
+        self.write_archive_header(archive);
+        for entry in self.entries.values() {
+            self.write_entry_header(archive, entry);
+        }
+
+        archive.flush().unwrap();
+    }
+}
+
+impl Archive for MagesArchive {
+    fn description(&self) -> String {
+        let mut description = format!("MPK v{}.{}", self.ver_major, self.ver_minor);
+        if self.endian == Endian::Big {
+            description.push_str(" (big-endian)");
+        }
+        if self.is_custom_layout {
+            description.push_str(" (custom layout)");
+        }
+        description
+    }
+
+    fn entries(&self) -> Vec<&dyn ArchiveEntry> {
+        self.iter()
+            .map(|entry| entry as &dyn ArchiveEntry)
+            .collect()
+    }
+
+    fn open_entry<'r>(
+        &self,
+        reader: &'r mut dyn ReadSeek,
+        entry: &dyn ArchiveEntry,
+    ) -> Box<dyn Read + 'r> {
+        self.entries[&entry.id()].open(reader)
+    }
+
+    fn sniff_entry(&self, reader: &mut dyn ReadSeek, entry: &dyn ArchiveEntry) -> FileType {
+        self.entries[&entry.id()].sniff(reader)
+    }
+
+    fn repack(
+        &self,
+        mut orig_reader: &mut dyn ReadSeek,
+        mut rpk_writer: &mut dyn WriteSeek,
+        rpk_paths: &[PathBuf],
+    ) {
+        self.repack_entries(&mut orig_reader, &mut rpk_writer, rpk_paths);
+    }
+
+    fn patch(
+        &mut self,
+        mut archive: &mut dyn WriteSeek,
+        rpk_paths: &[PathBuf],
+    ) -> Result<(), String> {
+        self.patch_entries(&mut archive, rpk_paths)
+    }
+
+    fn compact(&self, mut orig_reader: &mut dyn ReadSeek, mut writer: &mut dyn WriteSeek) {
+        Self::compact(self, &mut orig_reader, &mut writer);
+    }
+
+    fn sort_by_id(&mut self) {
+        self.entries.sort_keys();
+    }
+
+    fn sort_by_name(&mut self) {
+        self.entries.sort_by(|_, a, _, b| a.name().cmp(b.name()));
+    }
+
+    fn header_table_len(&self) -> u64 {
+        self.header_table_end()
+    }
+
+    fn alignment(&self) -> u64 {
+        bytes::ALIGNMENT
+    }
+
+    fn reported_entry_count(&self) -> u64 {
+        self.reported_entry_count
+    }
 }
 
 impl<'a> IntoIterator for &'a MagesArchive {
@@ -325,3 +607,416 @@ impl IntoIterator for MagesArchive {
         IntoEntries::new(self.entries)
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::mpk::{codec, Compression};
+    use flate2::write::ZlibEncoder;
+    use std::io::Cursor;
+
+    // the indicator of test entries XORed with 0x5a, see `xor_codecs()`
+    const XOR_INDICATOR: u32 = 2;
+
+    fn put(raw: &mut [u8], pos: usize, size: usize, value: u64, endian: Endian) {
+        let field = &mut raw[pos..pos + size];
+        match endian {
+            Endian::Little => field.copy_from_slice(&value.to_le_bytes()[..size]),
+            Endian::Big => field.copy_from_slice(&value.to_be_bytes()[8 - size..]),
+        }
+    }
+
+    fn stored_data(data: &[u8], indicator: u32) -> Vec<u8> {
+        match indicator {
+            1 => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            XOR_INDICATOR => data.iter().map(|b| b ^ 0x5a).collect(),
+            // stored, or left as-is for indicators standing in for unknown compressors
+            _ => data.to_vec(),
+        }
+    }
+
+    // an MPK of `version` holding `entries`, each a name, contents and compression indicator,
+    // written by hand rather than through `EntryLayout` so the layouts get checked too, with
+    // `empty_headers` all-0 entry headers counted after them
+    pub fn build_mpk(
+        version: (u16, u16),
+        endian: Endian,
+        entries: &[(&str, &[u8], u32)],
+        empty_headers: usize,
+    ) -> Vec<u8> {
+        let (ver_major, ver_minor) = version;
+        let entry_count = entries.len() + empty_headers;
+        let mut mpk = vec![0; 0x40 + entry_count * 0x100];
+        mpk[..4].copy_from_slice(b"MPK\0");
+        put(&mut mpk, 4, 2, ver_minor.into(), endian);
+        put(&mut mpk, 6, 2, ver_major.into(), endian);
+        put(&mut mpk, 8, 8, entry_count as u64, endian);
+
+        for (id, &(name, data, indicator)) in entries.iter().enumerate() {
+            let stored = stored_data(data, indicator);
+            let offset = mpk.len().next_multiple_of(2048);
+            mpk.resize(offset, 0);
+            mpk.extend(&stored);
+
+            let header = &mut mpk[0x40 + id * 0x100..][..0x100];
+            let (offset, len_compressed) = (offset as u64, stored.len() as u64);
+            if ver_major == 1 {
+                put(header, 0, 4, id as u64, endian);
+                put(header, 4, 4, offset, endian);
+                put(header, 8, 4, len_compressed, endian);
+                put(header, 12, 4, data.len() as u64, endian);
+            } else {
+                put(header, 0, 4, indicator.into(), endian);
+                put(header, 4, 4, id as u64, endian);
+                put(header, 8, 8, offset, endian);
+                put(header, 16, 8, len_compressed, endian);
+                put(header, 24, 8, data.len() as u64, endian);
+            }
+            header[32..32 + name.len()].copy_from_slice(name.as_bytes());
+        }
+        mpk
+    }
+
+    fn open(mpk: &[u8], codecs: &Codecs) -> MagesArchive {
+        let mut archive = MagesArchive::build(&mut Cursor::new(mpk));
+        archive.set_codecs(codecs);
+        archive
+    }
+
+    fn contents_with(mpk: &[u8], codecs: &Codecs) -> Vec<(String, Vec<u8>)> {
+        let archive = open(mpk, codecs);
+        let mut reader = Cursor::new(mpk);
+        archive
+            .iter()
+            .map(|entry| {
+                let mut data = Vec::new();
+                entry.open(&mut reader).read_to_end(&mut data).unwrap();
+                (entry.name().to_string(), data)
+            })
+            .collect()
+    }
+
+    fn contents(mpk: &[u8]) -> Vec<(String, Vec<u8>)> {
+        contents_with(mpk, &Codecs::default())
+    }
+
+    fn owned(entries: &[(&str, &[u8], u32)]) -> Vec<(String, Vec<u8>)> {
+        entries
+            .iter()
+            .map(|(name, data, _)| ((*name).to_string(), data.to_vec()))
+            .collect()
+    }
+
+    const ENTRIES: &[(&str, &[u8], u32)] = &[
+        ("a.txt", b"alpha", 0),
+        ("b.txt", b"bravo bravo bravo bravo", 1),
+        ("c.txt", b"charlie", 0),
+    ];
+
+    fn replacement(dir: &tempfile::TempDir, name: &str, data: &[u8]) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn repack_with(mpk: &[u8], codecs: &Codecs, rpk_paths: &[PathBuf]) -> Vec<u8> {
+        let archive = open(mpk, codecs);
+        let mut rpk = Cursor::new(Vec::new());
+        archive.repack_entries(&mut Cursor::new(mpk), &mut rpk, rpk_paths);
+        rpk.into_inner()
+    }
+
+    fn patch_with(mpk: &[u8], codecs: &Codecs, rpk_paths: &[PathBuf]) -> Vec<u8> {
+        let mut archive = open(mpk, codecs);
+        let mut patched = Cursor::new(mpk.to_vec());
+        archive.patch_entries(&mut patched, rpk_paths).unwrap();
+        patched.into_inner()
+    }
+
+    #[test]
+    fn big_endian_archives_round_trip() {
+        let mpk = build_mpk((2, 0), Endian::Big, ENTRIES, 0);
+        let archive = MagesArchive::build(&mut Cursor::new(&mpk));
+        assert_eq!(archive.endian(), Endian::Big);
+        assert_eq!(contents(&mpk), owned(ENTRIES));
+
+        let dir = tempfile::tempdir().unwrap();
+        let rpk_paths = [replacement(&dir, "c.txt", b"CHARLIE, but longer")];
+        let mut expected = owned(ENTRIES);
+        expected[2].1 = b"CHARLIE, but longer".to_vec();
+        for rpk in [
+            repack_with(&mpk, &Codecs::default(), &rpk_paths),
+            patch_with(&mpk, &Codecs::default(), &rpk_paths),
+        ] {
+            let archive = MagesArchive::build(&mut Cursor::new(&rpk));
+            assert_eq!(archive.endian(), Endian::Big);
+            assert_eq!(contents(&rpk), expected);
+        }
+    }
+
+    #[test]
+    fn converting_endian_only_rewrites_headers() {
+        for version in [(1, 0), (2, 0)] {
+            let big = build_mpk(version, Endian::Big, ENTRIES, 0);
+            let little = build_mpk(version, Endian::Little, ENTRIES, 0);
+
+            let mut archive = MagesArchive::build(&mut Cursor::new(&big));
+            let mut converted = Cursor::new(big.clone());
+            archive.convert_endian(&mut converted, Endian::Little);
+            assert_eq!(converted.into_inner(), little, "{version:?}");
+
+            let mut archive = MagesArchive::build(&mut Cursor::new(&little));
+            let mut converted = Cursor::new(little.clone());
+            archive.convert_endian(&mut converted, Endian::Big);
+            assert_eq!(converted.into_inner(), big, "{version:?}");
+        }
+    }
+
+    #[test]
+    fn v1_and_v2_archives_repack_patch_and_compact() {
+        let dir = tempfile::tempdir().unwrap();
+        let long_alpha = vec![b'A'; 3000];
+        let rpk_paths = [
+            replacement(&dir, "a.txt", &long_alpha),
+            replacement(&dir, "b.txt", b"BRAVO"),
+        ];
+        let mut expected = owned(ENTRIES);
+        expected[0].1.clone_from(&long_alpha);
+        expected[1].1 = b"BRAVO".to_vec();
+
+        for version in [(1, 0), (2, 0)] {
+            let mpk = build_mpk(version, Endian::Little, ENTRIES, 0);
+            assert_eq!(contents(&mpk), owned(ENTRIES), "{version:?}");
+
+            let repacked = repack_with(&mpk, &Codecs::default(), &rpk_paths);
+            assert_eq!(contents(&repacked), expected, "{version:?}");
+
+            // the smaller entry stays put, the one that outgrew its space moves to the end
+            let patched = patch_with(&mpk, &Codecs::default(), &rpk_paths);
+            assert_eq!(contents(&patched), expected, "{version:?}");
+            let before = MagesArchive::build(&mut Cursor::new(&mpk));
+            let after = MagesArchive::build(&mut Cursor::new(&patched));
+            let offset =
+                |archive: &MagesArchive, name| archive.get_entry_by_name(name).unwrap().offset();
+            assert_eq!(offset(&after, "b.txt"), offset(&before, "b.txt"));
+            assert!(offset(&after, "a.txt") > offset(&before, "c.txt"));
+
+            let archive = MagesArchive::build(&mut Cursor::new(&patched));
+            let mut compacted = Cursor::new(Vec::new());
+            archive.compact(&mut Cursor::new(&patched), &mut compacted);
+            let compacted = compacted.into_inner();
+            assert_eq!(contents(&compacted), expected, "{version:?}");
+            assert!(compacted.len() < patched.len());
+        }
+    }
+
+    #[test]
+    fn patching_goes_in_entry_order_and_refuses_unmatched_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mpk = build_mpk((2, 0), Endian::Little, ENTRIES, 0);
+
+        // both outgrow their space, so they're appended in the order of their entries
+        let rpk_paths = [
+            replacement(&dir, "c.txt", &[b'C'; 3000]),
+            replacement(&dir, "a.txt", &[b'A'; 3000]),
+        ];
+        let patched = patch_with(&mpk, &Codecs::default(), &rpk_paths);
+        let archive = MagesArchive::build(&mut Cursor::new(&patched));
+        let offset = |name| archive.get_entry_by_name(name).unwrap().offset();
+        assert!(offset("a.txt") < offset("c.txt"));
+
+        let rpk_paths = [
+            replacement(&dir, "a.txt", b"ALPHA"),
+            replacement(&dir, "d.txt", b"delta"),
+        ];
+        let mut archive = MagesArchive::build(&mut Cursor::new(&mpk));
+        let mut patched = Cursor::new(mpk.clone());
+        let message = archive.patch_entries(&mut patched, &rpk_paths).unwrap_err();
+        assert_eq!(message, "no entry named 'd.txt' in archive");
+        assert_eq!(patched.into_inner(), mpk);
+    }
+
+    #[test]
+    fn empty_entry_headers_are_skipped_but_still_counted() {
+        let mpk = build_mpk((2, 0), Endian::Little, ENTRIES, 1);
+        let archive = MagesArchive::build(&mut Cursor::new(&mpk));
+        assert_eq!(archive.reported_entry_count, 4);
+        assert_eq!(contents(&mpk), owned(ENTRIES));
+
+        let dir = tempfile::tempdir().unwrap();
+        let rpk_paths = [replacement(&dir, "c.txt", b"CHARLIE")];
+        let repacked = repack_with(&mpk, &Codecs::default(), &rpk_paths);
+        let archive = MagesArchive::build(&mut Cursor::new(&repacked));
+        assert_eq!(archive.reported_entry_count, 4);
+        assert_eq!(archive.iter().count(), 3);
+    }
+
+    #[test]
+    fn tells_padded_entry_tables_from_their_headers() {
+        let pads = |mpk: &[u8]| MagesArchive::pads_entry_table(&mut Cursor::new(mpk), None);
+        assert_eq!(
+            pads(&build_mpk((2, 0), Endian::Little, ENTRIES, 1)),
+            Some(true)
+        );
+        assert_eq!(
+            pads(&build_mpk((1, 0), Endian::Big, ENTRIES, 0)),
+            Some(false)
+        );
+
+        // not an MPK archive, or one cut off in its entry table
+        assert_eq!(pads(b"MPK\0"), None);
+        assert_eq!(pads(&[0; 0x200]), None);
+        let mpk = build_mpk((2, 0), Endian::Little, ENTRIES, 0);
+        assert_eq!(pads(&mpk[..0x140]), None);
+    }
+
+    #[test]
+    fn unknown_versions_need_a_layout() {
+        let mpk = build_mpk((3, 0), Endian::Little, ENTRIES, 0);
+        let e =
+            std::panic::catch_unwind(|| MagesArchive::build(&mut Cursor::new(&mpk))).unwrap_err();
+        assert!(e
+            .downcast_ref::<String>()
+            .unwrap()
+            .starts_with("unsupported MPK version 3.0"));
+
+        let layout = EntryLayout::V2.to_string().parse::<EntryLayout>().unwrap();
+        let archive =
+            MagesArchive::build_with(&mut Cursor::new(&mpk), Some(&layout), NameEncoding::Utf8);
+        assert_eq!(archive.description(), "MPK v3.0 (custom layout)");
+        assert_eq!(archive.iter().count(), ENTRIES.len());
+    }
+
+    #[test]
+    fn compression_comes_from_the_indicator_or_sizes() {
+        let entries = [
+            ("a.txt", &b"alpha"[..], 0),
+            ("b.txt", b"bravo bravo bravo bravo", 1),
+            ("c.txt", b"charlie", 7),
+        ];
+        let archive = MagesArchive::build(&mut Cursor::new(build_mpk(
+            (2, 0),
+            Endian::Little,
+            &entries,
+            0,
+        )));
+        let compression = |archive: &MagesArchive| {
+            archive
+                .iter()
+                .map(MagesEntry::compression)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            compression(&archive),
+            [
+                Compression::Stored,
+                Compression::Zlib,
+                Compression::Unknown(7)
+            ]
+        );
+
+        // V1 has no indicator, only whether the entry shrank
+        let archive = MagesArchive::build(&mut Cursor::new(build_mpk(
+            (1, 0),
+            Endian::Little,
+            &entries[..2],
+            0,
+        )));
+        assert_eq!(
+            compression(&archive),
+            [Compression::Stored, Compression::Zlib]
+        );
+    }
+
+    #[test]
+    fn repacking_keeps_each_entrys_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let rpk_paths = [
+            replacement(&dir, "a.txt", b"ALPHA ALPHA ALPHA ALPHA"),
+            replacement(&dir, "b.txt", b"BRAVO BRAVO BRAVO BRAVO"),
+        ];
+        let mpk = build_mpk((2, 0), Endian::Little, ENTRIES, 0);
+        for rpk in [
+            repack_with(&mpk, &Codecs::default(), &rpk_paths),
+            patch_with(&mpk, &Codecs::default(), &rpk_paths),
+        ] {
+            let archive = MagesArchive::build(&mut Cursor::new(&rpk));
+            let a = archive.get_entry_by_name("a.txt").unwrap();
+            assert_eq!(a.compression(), Compression::Stored);
+            let offset = usize::try_from(a.offset()).unwrap();
+            assert_eq!(&rpk[offset..][..23], b"ALPHA ALPHA ALPHA ALPHA");
+
+            let b = archive.get_entry_by_name("b.txt").unwrap();
+            assert_eq!(b.compression(), Compression::Zlib);
+            assert!(b.len_compressed() < b.len_deflated());
+        }
+    }
+
+    #[test]
+    fn unknown_compression_is_refused() {
+        let mpk = build_mpk((2, 0), Endian::Little, &[("c.txt", b"charlie", 7)], 0);
+        let archive = MagesArchive::build(&mut Cursor::new(&mpk));
+        let entry = archive.get_entry_by_name("c.txt").unwrap();
+        assert_eq!(entry.sniff(&mut Cursor::new(&mpk)), FileType::Unknown);
+
+        let e = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            entry.extract(&mut Cursor::new(&mpk), &mut Vec::new());
+        }))
+        .unwrap_err();
+        assert!(e
+            .downcast_ref::<String>()
+            .unwrap()
+            .contains("register a codec for it with --codec 7=CODEC"));
+    }
+
+    fn xor_codecs() -> Codecs {
+        let mut codecs = Codecs::default();
+        let (indicator, codec) = codec::parse_assignment("2=xor:0x5a").unwrap();
+        codecs.register(indicator, codec);
+        codecs
+    }
+
+    #[test]
+    fn registered_codecs_read_and_repack_entries() {
+        let entries = [
+            ("a.txt", &b"alpha"[..], XOR_INDICATOR),
+            ("b.txt", b"bravo bravo bravo bravo", 1),
+        ];
+        let mpk = build_mpk((2, 0), Endian::Little, &entries, 0);
+        assert_eq!(contents_with(&mpk, &xor_codecs()), owned(&entries));
+
+        let dir = tempfile::tempdir().unwrap();
+        let rpk_paths = [replacement(&dir, "a.txt", b"ALPHA")];
+        let mut expected = owned(&entries);
+        expected[0].1 = b"ALPHA".to_vec();
+        for rpk in [
+            repack_with(&mpk, &xor_codecs(), &rpk_paths),
+            patch_with(&mpk, &xor_codecs(), &rpk_paths),
+        ] {
+            assert_eq!(contents_with(&rpk, &xor_codecs()), expected);
+            let archive = open(&rpk, &xor_codecs());
+            let offset = archive.get_entry_by_name("a.txt").unwrap().offset();
+            let offset = usize::try_from(offset).unwrap();
+            assert_eq!(
+                &rpk[offset..offset + 5],
+                stored_data(b"ALPHA", XOR_INDICATOR)
+            );
+        }
+    }
+
+    #[test]
+    fn codecs_replace_zlib_for_compressed_entries() {
+        let entries = [("a.txt", &b"alpha"[..], XOR_INDICATOR)];
+        let mut mpk = build_mpk((2, 0), Endian::Little, &entries, 0);
+        // relabel the entry as compressed, then read it with XOR in place of zlib
+        mpk[0x40] = 1;
+        let mut codecs = Codecs::default();
+        let (indicator, codec) = codec::parse_assignment("xor:0x5a").unwrap();
+        codecs.register(indicator, codec);
+        assert_eq!(contents_with(&mpk, &codecs), owned(&entries));
+    }
+}
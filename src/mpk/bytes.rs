@@ -1,8 +1,12 @@
-use crate::mpk::{MagesArchive, MagesEntry};
-use bincode::config::{Configuration as BincodeConfig, Fixint, LittleEndian};
+use crate::mpk::MagesArchive;
 use bincode::{Decode, Encode};
+use encoding_rs::SHIFT_JIS;
 use std::ffi::CStr;
-use std::io::{Read, Write};
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+pub use crate::bytes::{read_struct_as, write_struct_as, Endian};
 
 #[derive(Debug, Decode, Encode)]
 pub(super) struct MpkHeader {
@@ -13,54 +17,102 @@ pub(super) struct MpkHeader {
     _padding: [u8; 0x30],
 }
 
-#[derive(Debug, Decode, Encode)]
-pub(super) struct MpkEntryV1 {
-    pub id: u32,
-    pub offset: u32,
-    pub len_compressed: u32,
-    pub len_deflated: u32,
-    _padding: [u8; 16],
-    //   256 bytes per entry header
-    // -  32 bytes for other data
-    // = 224 bytes max for string
-    pub name: [u8; 224],
-}
+impl MpkHeader {
+    pub const SIZE: usize = 0x40;
 
-#[derive(Debug, Decode, Encode)]
-pub(super) struct MpkEntryV2 {
-    pub cpr_indicator: u32,
-    pub id: u32,
-    pub offset: u64,
-    pub len_compressed: u64,
-    pub len_deflated: u64,
-    pub name: [u8; 224],
+    // Console ports store the header and entry table big-endian. The entry count is the most
+    // telling field: read in the wrong byte order, any realistic count becomes enormous. The
+    // major version settles archives with no entries.
+    pub fn detect_endian(raw: &[u8; Self::SIZE]) -> Endian {
+        let is_plausible = |ver_major: u16, entry_count: u64| {
+            (1..=0xff).contains(&ver_major) && u32::try_from(entry_count).is_ok()
+        };
+
+        let ver_major = raw[6..8].try_into().unwrap();
+        let entry_count = raw[8..16].try_into().unwrap();
+        let is_little = is_plausible(
+            u16::from_le_bytes(ver_major),
+            u64::from_le_bytes(entry_count),
+        );
+        let is_big = is_plausible(
+            u16::from_be_bytes(ver_major),
+            u64::from_be_bytes(entry_count),
+        );
+
+        if is_big && !is_little {
+            Endian::Big
+        } else {
+            Endian::Little
+        }
+    }
 }
 
-type MpkConfig = BincodeConfig<LittleEndian, Fixint>;
+/// How entry names are encoded in the entry table. PC releases use plain ASCII names, which both
+/// read the same, but older releases can have Shift-JIS ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameEncoding {
+    #[default]
+    Utf8,
+    ShiftJis,
+}
 
-const BINCODE_CONFIG: MpkConfig = bincode::config::standard()
-    .with_little_endian()
-    .with_fixed_int_encoding();
+impl NameEncoding {
+    pub(super) fn decode(self, name: &[u8]) -> String {
+        let name = CStr::from_bytes_until_nul(name)
+            .expect("entry name isn't NUL-terminated")
+            .to_bytes();
+        match self {
+            Self::Utf8 => std::str::from_utf8(name)
+                .expect("entry name isn't valid UTF-8, is the archive's name encoding shift-jis?")
+                .into(),
+            Self::ShiftJis => {
+                let (name, had_errors) = SHIFT_JIS.decode_without_bom_handling(name);
+                assert!(!had_errors, "entry name isn't valid Shift-JIS");
+                name.into_owned()
+            }
+        }
+    }
 
-pub fn read_struct<D: Decode<()>, R: Read>(reader: &mut R) -> D {
-    bincode::decode_from_std_read::<D, MpkConfig, R>(reader, BINCODE_CONFIG)
-        .expect("failed to decode")
+    pub(super) fn encode(self, name: &str) -> Vec<u8> {
+        match self {
+            Self::Utf8 => name.as_bytes().to_vec(),
+            Self::ShiftJis => {
+                let (encoded, _, had_errors) = SHIFT_JIS.encode(name);
+                assert!(
+                    !had_errors,
+                    "entry name '{name}' can't be encoded as Shift-JIS"
+                );
+                encoded.into_owned()
+            }
+        }
+    }
 }
 
-pub fn write_struct<E: Encode, W: Write>(writer: &mut W, val: E) {
-    bincode::encode_into_std_write::<E, MpkConfig, W>(val, writer, BINCODE_CONFIG)
-        .expect("failed to encode");
+impl fmt::Display for NameEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Utf8 => "utf-8",
+            Self::ShiftJis => "shift-jis",
+        })
+    }
 }
 
-pub fn entry_name_from_bytes(name: &[u8]) -> String {
-    CStr::from_bytes_until_nul(name)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .into()
+impl FromStr for NameEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Self::Utf8),
+            "shift-jis" | "shift_jis" | "sjis" => Ok(Self::ShiftJis),
+            _ => Err(format!(
+                "unknown name encoding '{s}', expected 'utf-8' or 'shift-jis'"
+            )),
+        }
+    }
 }
 
 // MPK aligns the actual start of each entry's data on offsets of 2048
+pub const ALIGNMENT: u64 = 2048;
 const PADDING_BUF: [u8; 2048] = [0; 2048];
 pub fn write_alignment_padding<W: Write>(writer: &mut W, pos: u64) {
     let remainder = pos % 2048;
@@ -88,37 +140,3 @@ impl From<&MagesArchive> for MpkHeader {
         }
     }
 }
-
-fn copy_name_bytes(entry_name: &str) -> [u8; 224] {
-    let mut name_buf = [0u8; 224];
-    name_buf[..entry_name.len()].copy_from_slice(entry_name.as_bytes());
-    name_buf
-}
-
-impl From<&MagesEntry> for MpkEntryV1 {
-    fn from(entry: &MagesEntry) -> Self {
-        Self {
-            id: entry.id(),
-            offset: u32::try_from(entry.offset()).expect("V1 entry offset too large for u32"),
-            len_compressed: u32::try_from(entry.len_compressed())
-                .expect("V1 entry compressed size too large for u32"),
-            len_deflated: u32::try_from(entry.len_deflated())
-                .expect("V1 entry size too large for u32"),
-            _padding: [0; 16],
-            name: copy_name_bytes(entry.name()),
-        }
-    }
-}
-
-impl From<&MagesEntry> for MpkEntryV2 {
-    fn from(entry: &MagesEntry) -> Self {
-        Self {
-            cpr_indicator: entry.cpr_indicator,
-            id: entry.id(),
-            offset: entry.offset(),
-            len_compressed: entry.len_compressed(),
-            len_deflated: entry.len_deflated(),
-            name: copy_name_bytes(entry.name()),
-        }
-    }
-}
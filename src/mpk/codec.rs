@@ -0,0 +1,206 @@
+// Entry data codecs. Each compression indicator an MPK entry header can hold maps to a codec
+// that turns the stored bytes back into the original file and vice versa. Zlib is the only one
+// the engine is known to use on PC, but some releases use other compressors or obfuscate their
+// data, so more can be registered without touching the archive code.
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+/// Transforms entry data between the form stored in the archive and the original file.
+pub trait Codec: fmt::Debug + Send + Sync {
+    /// The name the codec is selected by on the command line.
+    fn name(&self) -> String;
+
+    /// Wraps `reader`, which yields the stored data, in a reader that yields the original file.
+    fn decode<'r>(&self, reader: Box<dyn Read + 'r>) -> Box<dyn Read + 'r>;
+
+    /// Encodes everything in `reader` into `writer`, returning the number of bytes written.
+    fn encode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> u64;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Zlib;
+
+impl Codec for Zlib {
+    fn name(&self) -> String {
+        "zlib".to_string()
+    }
+
+    fn decode<'r>(&self, reader: Box<dyn Read + 'r>) -> Box<dyn Read + 'r> {
+        Box::new(ZlibDecoder::new(reader))
+    }
+
+    fn encode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> u64 {
+        let mut zlib_writer = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        io::copy(reader, &mut zlib_writer).expect("failed to copy entry from reader");
+        let compressed = zlib_writer.finish().expect("failed to finish zlib writer");
+        writer
+            .write_all(&compressed)
+            .expect("failed to write compressed entry");
+        compressed.len() as u64
+    }
+}
+
+/// XORs every byte with a fixed key, a common way of keeping casual eyes off entry data.
+#[derive(Debug, Clone, Copy)]
+pub struct Xor {
+    pub key: u8,
+}
+
+struct XorReader<R> {
+    inner: R,
+    key: u8,
+}
+
+impl<R: Read> Read for XorReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        buf[..len].iter_mut().for_each(|b| *b ^= self.key);
+        Ok(len)
+    }
+}
+
+impl Codec for Xor {
+    fn name(&self) -> String {
+        format!("xor:{:#04x}", self.key)
+    }
+
+    fn decode<'r>(&self, reader: Box<dyn Read + 'r>) -> Box<dyn Read + 'r> {
+        Box::new(XorReader {
+            inner: reader,
+            key: self.key,
+        })
+    }
+
+    fn encode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> u64 {
+        let mut xor_reader = XorReader {
+            inner: reader,
+            key: self.key,
+        };
+        io::copy(&mut xor_reader, writer).expect("failed to copy entry from reader")
+    }
+}
+
+/// Parses the name of a built-in codec: `zlib`, or `xor:KEY` with a decimal or hex key.
+pub fn parse_codec(s: &str) -> Result<Arc<dyn Codec>, String> {
+    let (name, arg) = s.split_once(':').unwrap_or((s, ""));
+    match (name.to_ascii_lowercase().as_str(), arg) {
+        ("zlib", "") => Ok(Arc::new(Zlib)),
+        ("xor", key) if !key.is_empty() => {
+            let key = crate::select::parse_offset(key)?;
+            let key = u8::try_from(key).map_err(|_| format!("XOR key {key} is not a byte"))?;
+            Ok(Arc::new(Xor { key }))
+        }
+        _ => Err(format!("unknown codec '{s}' (known codecs: zlib, xor:KEY)")),
+    }
+}
+
+/// Parses `INDICATOR=CODEC`, or just `CODEC` to replace the codec of compressed entries
+/// (indicator 1).
+pub fn parse_assignment(s: &str) -> Result<(u32, Arc<dyn Codec>), String> {
+    match s.split_once('=') {
+        Some((indicator, codec)) => {
+            let indicator = indicator
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid compression indicator '{indicator}': {e}"))?;
+            Ok((indicator, parse_codec(codec)?))
+        }
+        None => Ok((Codecs::ZLIB_INDICATOR, parse_codec(s)?)),
+    }
+}
+
+/// Which codec handles the entries with each compression indicator.
+///
+/// Entries whose indicator has no codec are either stored as-is (indicator 0) or can't be read.
+#[derive(Debug, Clone)]
+pub struct Codecs {
+    by_indicator: HashMap<u32, Arc<dyn Codec>>,
+}
+
+impl Default for Codecs {
+    fn default() -> Self {
+        let mut codecs = Self {
+            by_indicator: HashMap::new(),
+        };
+        codecs.register(Self::ZLIB_INDICATOR, Arc::new(Zlib));
+        codecs
+    }
+}
+
+impl Codecs {
+    pub const ZLIB_INDICATOR: u32 = 1;
+
+    /// Makes `codec` handle entries with the given compression indicator, replacing any codec
+    /// registered for it before.
+    pub fn register(&mut self, indicator: u32, codec: Arc<dyn Codec>) {
+        self.by_indicator.insert(indicator, codec);
+    }
+
+    #[must_use]
+    pub fn get(&self, indicator: u32) -> Option<&Arc<dyn Codec>> {
+        self.by_indicator.get(&indicator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(codec: &dyn Codec, data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let len = codec.encode(&mut &data[..], &mut encoded);
+        assert_eq!(len, encoded.len() as u64, "{}", codec.name());
+
+        let mut decoded = Vec::new();
+        codec
+            .decode(Box::new(encoded.as_slice()))
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data, "{}", codec.name());
+        encoded
+    }
+
+    #[test]
+    fn built_in_codecs_round_trip() {
+        let data = b"MAGES. MAGES. MAGES. MAGES. MAGES.";
+        assert!(round_trip(&Zlib, data).len() < data.len());
+        assert_eq!(
+            round_trip(&Xor { key: 0xff }, b"\x00\x0f\xff"),
+            b"\xff\xf0\x00"
+        );
+    }
+
+    #[test]
+    fn parses_codec_assignments() {
+        let (indicator, codec) = parse_assignment("2=xor:0x5a").unwrap();
+        assert_eq!((indicator, codec.name()), (2, "xor:0x5a".to_string()));
+        let (indicator, codec) = parse_assignment("xor:90").unwrap();
+        assert_eq!((indicator, codec.name()), (1, "xor:0x5a".to_string()));
+        assert_eq!(parse_assignment("ZLIB").unwrap().1.name(), "zlib");
+
+        for (assignment, error) in [
+            ("lz4", "unknown codec 'lz4' (known codecs: zlib, xor:KEY)"),
+            ("xor", "unknown codec 'xor' (known codecs: zlib, xor:KEY)"),
+            ("xor:256", "XOR key 256 is not a byte"),
+            ("x=zlib", "invalid compression indicator 'x'"),
+        ] {
+            let e = parse_assignment(assignment).err().unwrap();
+            assert!(e.starts_with(error), "{assignment}: {e}");
+        }
+    }
+
+    #[test]
+    fn registering_replaces_codecs() {
+        let mut codecs = Codecs::default();
+        assert_eq!(codecs.get(1).unwrap().name(), "zlib");
+        assert!(codecs.get(0).is_none());
+        codecs.register(1, Arc::new(Xor { key: 1 }));
+        assert_eq!(codecs.get(1).unwrap().name(), "xor:0x01");
+    }
+}
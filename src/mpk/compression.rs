@@ -0,0 +1,52 @@
+use std::fmt;
+
+/// How an entry's data is stored in the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Stored,
+    Zlib,
+    /// A compression indicator ungelify doesn't know how to handle.
+    Unknown(u32),
+}
+
+impl Compression {
+    /// Maps the compression indicator stored in V2 entry headers.
+    #[must_use]
+    pub const fn from_indicator(indicator: u32) -> Self {
+        match indicator {
+            0 => Self::Stored,
+            1 => Self::Zlib,
+            _ => Self::Unknown(indicator),
+        }
+    }
+
+    /// V1 entry headers have no compression indicator, so the only hint is whether the entry
+    /// shrank when it was packed.
+    #[must_use]
+    pub const fn from_sizes(len_compressed: u64, len_deflated: u64) -> Self {
+        if len_compressed == len_deflated {
+            Self::Stored
+        } else {
+            Self::Zlib
+        }
+    }
+
+    #[must_use]
+    pub const fn indicator(self) -> u32 {
+        match self {
+            Self::Stored => 0,
+            Self::Zlib => 1,
+            Self::Unknown(indicator) => indicator,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stored => write!(f, "stored"),
+            Self::Zlib => write!(f, "zlib"),
+            Self::Unknown(indicator) => write!(f, "unknown ({indicator})"),
+        }
+    }
+}
@@ -1,10 +1,10 @@
-use crate::mpk::bytes;
-use crate::mpk::bytes::{MpkEntryV1, MpkEntryV2};
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
+use crate::archive::ArchiveEntry;
+use crate::mpk::codec::{Codec, Codecs};
+use crate::mpk::compression::Compression;
+use crate::sniff::FileType;
 use std::io;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct MagesEntry {
@@ -13,10 +13,40 @@ pub struct MagesEntry {
     offset: u64,
     len_deflated: u64,
     len_compressed: u64,
-    pub(super) cpr_indicator: u32,
+    compression: Compression,
+    codec: Option<Arc<dyn Codec>>,
+    // the entry's header as read from the table, so bytes the layout doesn't cover survive a
+    // rewrite
+    pub(super) raw_header: Vec<u8>,
 }
 
 impl MagesEntry {
+    pub(super) const fn new(
+        id: u32,
+        name: String,
+        offset: u64,
+        len_deflated: u64,
+        len_compressed: u64,
+        compression: Compression,
+        raw_header: Vec<u8>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            offset,
+            len_deflated,
+            len_compressed,
+            compression,
+            codec: None,
+            raw_header,
+        }
+    }
+
+    // Picks the codec for this entry's compression indicator out of `codecs`.
+    pub(super) fn resolve_codec(&mut self, codecs: &Codecs) {
+        self.codec = codecs.get(self.compression.indicator()).cloned();
+    }
+
     #[must_use]
     pub const fn id(&self) -> u32 {
         self.id
@@ -43,35 +73,87 @@ impl MagesEntry {
         self.len_compressed
     }
 
+    #[must_use]
+    pub const fn compression(&self) -> Compression {
+        self.compression
+    }
+
     #[must_use]
     pub const fn is_compressed(&self) -> bool {
-        self.len_compressed != self.len_deflated
+        !matches!(self.compression, Compression::Stored)
+    }
+
+    /// The codec this entry's data is decoded with, or `None` if it's stored as-is or uses a
+    /// compression method with no codec registered.
+    #[must_use]
+    pub fn codec(&self) -> Option<&dyn Codec> {
+        self.codec.as_deref()
+    }
+
+    // whether there's any way to get at the original contents
+    const fn is_readable(&self) -> bool {
+        self.codec.is_some() || matches!(self.compression, Compression::Stored)
+    }
+
+    fn unknown_compression(&self) -> ! {
+        panic!(
+            "entry '{}' uses an unknown compression method (indicator {}); register a codec for \
+             it with --codec {}=CODEC",
+            self.name,
+            self.compression.indicator(),
+            self.compression.indicator()
+        )
+    }
+
+    fn decode<'r>(&self, reader: Box<dyn Read + 'r>) -> Box<dyn Read + 'r> {
+        match &self.codec {
+            Some(codec) => codec.decode(reader),
+            None if self.is_readable() => reader,
+            None => self.unknown_compression(),
+        }
     }
 
     pub fn extract<R: Read, W: Write>(&self, reader: &mut R, writer: &mut W) {
-        let mut reader = reader.take(self.len_compressed);
-        if self.is_compressed() {
-            let mut zlib_reader = ZlibDecoder::new(reader);
-            io::copy(&mut zlib_reader, writer).expect("failed to copy entry from zlib reader");
-        } else {
-            io::copy(&mut reader, writer).expect("failed to copy entry from reader");
+        let mut reader = self.decode(Box::new(reader.take(self.len_compressed)));
+        io::copy(&mut reader, writer).expect("failed to copy entry from reader");
+    }
+
+    /// Opens a reader over this entry's extracted contents. Unlike `extract()`, this seeks
+    /// `reader` to the entry's data itself.
+    pub fn open<'r, R: Read + Seek + ?Sized>(&self, reader: &'r mut R) -> Box<dyn Read + 'r> {
+        reader.seek(SeekFrom::Start(self.offset)).unwrap();
+        self.decode(Box::new(reader.take(self.len_compressed)))
+    }
+
+    /// Detects what kind of file this entry holds by looking at the start of its (decompressed)
+    /// contents. Unlike `extract()`, this seeks `reader` to the entry's data itself.
+    pub fn sniff<R: Read + Seek + ?Sized>(&self, reader: &mut R) -> FileType {
+        // the raw data of an unknown codec would only be mistaken for something else
+        if !self.is_readable() {
+            return FileType::Unknown;
         }
+
+        let mut head = Vec::with_capacity(FileType::SNIFF_LEN);
+        self.open(reader)
+            .take(FileType::SNIFF_LEN as u64)
+            .read_to_end(&mut head)
+            .expect("failed to read entry from reader");
+
+        FileType::sniff(&head, self.len_deflated)
     }
 
     /// Writes the contents of `reader` into `writer` to replace the contents of
-    /// an entry, performing zlib compression if this entry was originally compressed.
+    /// an entry, encoding it with the entry's codec.
     ///
     /// Returns the number of bytes written to `writer`, functionally equivalent
     /// to `len_compressed`.
     pub fn repack<R: Read, W: Write>(&self, reader: &mut R, writer: &mut W) -> u64 {
-        if self.is_compressed() {
-            let mut zlib_writer = ZlibEncoder::new(writer, Compression::default());
-            let written_bytes =
-                io::copy(reader, &mut zlib_writer).expect("failed to copy entry from reader");
-            zlib_writer.finish().expect("failed to finish zlib writer");
-            written_bytes
-        } else {
-            io::copy(reader, writer).expect("failed to copy entry from reader")
+        match &self.codec {
+            Some(codec) => codec.encode(reader, writer),
+            None if self.is_readable() => {
+                io::copy(reader, writer).expect("failed to copy entry from reader")
+            }
+            None => self.unknown_compression(),
         }
     }
 
@@ -83,33 +165,35 @@ impl MagesEntry {
             offset,
             len_deflated,
             len_compressed,
-            cpr_indicator: self.cpr_indicator,
+            compression: self.compression,
+            codec: self.codec.clone(),
+            raw_header: self.raw_header.clone(),
         }
     }
 }
 
-impl From<MpkEntryV1> for MagesEntry {
-    fn from(entry: MpkEntryV1) -> Self {
-        Self {
-            id: entry.id,
-            name: bytes::entry_name_from_bytes(&entry.name),
-            offset: u64::from(entry.offset),
-            len_deflated: u64::from(entry.len_deflated),
-            len_compressed: u64::from(entry.len_compressed),
-            cpr_indicator: 0,
-        }
+impl ArchiveEntry for MagesEntry {
+    fn id(&self) -> u32 {
+        self.id
     }
-}
 
-impl From<MpkEntryV2> for MagesEntry {
-    fn from(entry: MpkEntryV2) -> Self {
-        Self {
-            id: entry.id,
-            name: bytes::entry_name_from_bytes(&entry.name),
-            offset: entry.offset,
-            len_deflated: entry.len_deflated,
-            len_compressed: entry.len_compressed,
-            cpr_indicator: entry.cpr_indicator,
-        }
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn len_compressed(&self) -> u64 {
+        self.len_compressed
+    }
+
+    fn len_deflated(&self) -> u64 {
+        self.len_deflated
+    }
+
+    fn is_compressed(&self) -> bool {
+        self.is_compressed()
     }
 }
@@ -6,7 +6,7 @@ pub struct Entries<'a> {
 }
 
 impl Entries<'_> {
-    pub(in crate::mpk) fn new(entry_map: &IndexMap<u32, MagesEntry>) -> Entries {
+    pub(in crate::mpk) fn new(entry_map: &IndexMap<u32, MagesEntry>) -> Entries<'_> {
         Entries {
             entry_values: entry_map.values(),
         }
@@ -26,7 +26,7 @@ pub struct EntriesMut<'a> {
 }
 
 impl EntriesMut<'_> {
-    pub(in crate::mpk) fn new(entry_map: &mut IndexMap<u32, MagesEntry>) -> EntriesMut {
+    pub(in crate::mpk) fn new(entry_map: &mut IndexMap<u32, MagesEntry>) -> EntriesMut<'_> {
         EntriesMut {
             entry_values: entry_map.values_mut(),
         }
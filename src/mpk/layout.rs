@@ -0,0 +1,357 @@
+use crate::mpk::bytes::{Endian, NameEncoding};
+use crate::mpk::compression::Compression;
+use crate::mpk::entry::MagesEntry;
+use crate::select::parse_offset;
+use std::fmt;
+use std::str::FromStr;
+
+/// An integer field within an entry header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub offset: usize,
+    /// Either 4 or 8 bytes.
+    pub size: usize,
+}
+
+impl Field {
+    const fn new(offset: usize, size: usize) -> Self {
+        Self { offset, size }
+    }
+
+    pub(super) fn read(self, raw: &[u8], endian: Endian) -> u64 {
+        let bytes = &raw[self.offset..self.offset + self.size];
+        match (self.size, endian) {
+            (4, Endian::Little) => u64::from(u32::from_le_bytes(bytes.try_into().unwrap())),
+            (4, Endian::Big) => u64::from(u32::from_be_bytes(bytes.try_into().unwrap())),
+            (8, Endian::Little) => u64::from_le_bytes(bytes.try_into().unwrap()),
+            (8, Endian::Big) => u64::from_be_bytes(bytes.try_into().unwrap()),
+            _ => unreachable!("field sizes are checked when a layout is parsed"),
+        }
+    }
+
+    fn write(self, raw: &mut [u8], value: u64, endian: Endian, field_name: &str) {
+        let bytes = &mut raw[self.offset..self.offset + self.size];
+        if self.size == 4 {
+            let value = u32::try_from(value)
+                .unwrap_or_else(|_| panic!("{field_name} {value} too large for a 4-byte field"));
+            bytes.copy_from_slice(&match endian {
+                Endian::Little => value.to_le_bytes(),
+                Endian::Big => value.to_be_bytes(),
+            });
+        } else {
+            bytes.copy_from_slice(&match endian {
+                Endian::Little => value.to_le_bytes(),
+                Endian::Big => value.to_be_bytes(),
+            });
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.offset, self.size)
+    }
+}
+
+/// Where each field of an entry header lives, for one revision of the MPK format.
+///
+/// Bytes of the header that aren't covered by a field are kept as they were when an entry is
+/// written back out, so a layout only has to describe the fields ungelify needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryLayout {
+    pub entry_size: usize,
+    /// Only present from V2 on.
+    pub cpr_indicator: Option<Field>,
+    pub id: Field,
+    pub offset: Field,
+    pub len_compressed: Field,
+    pub len_deflated: Field,
+    pub name_offset: usize,
+    pub name_len: usize,
+}
+
+/// Every MPK version whose entry table layout is known, by `(ver_major, ver_minor)`.
+pub static KNOWN_VERSIONS: &[((u16, u16), EntryLayout)] =
+    &[((1, 0), EntryLayout::V1), ((2, 0), EntryLayout::V2)];
+
+impl EntryLayout {
+    pub const V1: Self = Self {
+        entry_size: 0x100,
+        cpr_indicator: None,
+        id: Field::new(0, 4),
+        offset: Field::new(4, 4),
+        len_compressed: Field::new(8, 4),
+        len_deflated: Field::new(12, 4),
+        // 16 bytes of padding in between
+        name_offset: 32,
+        name_len: 224,
+    };
+
+    pub const V2: Self = Self {
+        entry_size: 0x100,
+        cpr_indicator: Some(Field::new(0, 4)),
+        id: Field::new(4, 4),
+        offset: Field::new(8, 8),
+        len_compressed: Field::new(16, 8),
+        len_deflated: Field::new(24, 8),
+        name_offset: 32,
+        name_len: 224,
+    };
+
+    /// The registered layout for an MPK version, if there is one.
+    #[must_use]
+    pub fn for_version(ver_major: u16, ver_minor: u16) -> Option<&'static Self> {
+        KNOWN_VERSIONS
+            .iter()
+            .find(|(version, _)| *version == (ver_major, ver_minor))
+            .map(|(_, layout)| layout)
+    }
+
+    /// The layout of the oldest registered version with the same major version. Minor revisions
+    /// haven't changed the entry table so far, so this is the best guess for an unknown minor.
+    #[must_use]
+    pub fn for_major_version(ver_major: u16) -> Option<&'static Self> {
+        KNOWN_VERSIONS
+            .iter()
+            .filter(|((major, _), _)| *major == ver_major)
+            .min_by_key(|((_, minor), _)| *minor)
+            .map(|(_, layout)| layout)
+    }
+
+    fn fields(&self) -> impl Iterator<Item = (&'static str, Field)> {
+        [
+            ("id", Some(self.id)),
+            ("offset", Some(self.offset)),
+            ("len_compressed", Some(self.len_compressed)),
+            ("len_deflated", Some(self.len_deflated)),
+            ("cpr_indicator", self.cpr_indicator),
+        ]
+        .into_iter()
+        .filter_map(|(name, field)| field.map(|field| (name, field)))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (name, field) in self.fields() {
+            if field.size != 4 && field.size != 8 {
+                return Err(format!("{name} must be 4 or 8 bytes, not {}", field.size));
+            }
+            if field.offset + field.size > self.entry_size {
+                return Err(format!("{name} extends past the end of the entry header"));
+            }
+        }
+        if self.name_offset + self.name_len > self.entry_size {
+            return Err("name extends past the end of the entry header".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn read_entry(
+        &self,
+        raw_header: Vec<u8>,
+        endian: Endian,
+        names: NameEncoding,
+    ) -> MagesEntry {
+        let name = names.decode(&raw_header[self.name_offset..self.name_offset + self.name_len]);
+
+        let len_compressed = self.len_compressed.read(&raw_header, endian);
+        let len_deflated = self.len_deflated.read(&raw_header, endian);
+        let compression = self.cpr_indicator.map_or_else(
+            || Compression::from_sizes(len_compressed, len_deflated),
+            |field| {
+                Compression::from_indicator(
+                    u32::try_from(field.read(&raw_header, endian))
+                        .expect("compression indicator too large for u32"),
+                )
+            },
+        );
+
+        MagesEntry::new(
+            u32::try_from(self.id.read(&raw_header, endian)).expect("entry ID too large for u32"),
+            name,
+            self.offset.read(&raw_header, endian),
+            len_deflated,
+            len_compressed,
+            compression,
+            raw_header,
+        )
+    }
+
+    pub(super) fn write_entry(
+        &self,
+        entry: &MagesEntry,
+        endian: Endian,
+        names: NameEncoding,
+    ) -> Vec<u8> {
+        let mut raw = entry.raw_header.clone();
+        raw.resize(self.entry_size, 0);
+
+        self.id
+            .write(&mut raw, u64::from(entry.id()), endian, "entry ID");
+        self.offset
+            .write(&mut raw, entry.offset(), endian, "entry offset");
+        self.len_compressed
+            .write(&mut raw, entry.len_compressed(), endian, "entry size");
+        self.len_deflated
+            .write(&mut raw, entry.len_deflated(), endian, "entry size");
+        if let Some(field) = self.cpr_indicator {
+            field.write(
+                &mut raw,
+                u64::from(entry.compression().indicator()),
+                endian,
+                "compression indicator",
+            );
+        }
+
+        let name = names.encode(entry.name());
+        assert!(
+            name.len() < self.name_len,
+            "entry name '{}' is too long for a {}-byte name field",
+            entry.name(),
+            self.name_len
+        );
+        let name_field = &mut raw[self.name_offset..self.name_offset + self.name_len];
+        name_field.fill(0);
+        name_field[..name.len()].copy_from_slice(&name);
+
+        raw
+    }
+}
+
+impl fmt::Display for EntryLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry_size={}", self.entry_size)?;
+        for (name, field) in self.fields() {
+            write!(f, ",{name}={field}")?;
+        }
+        write!(f, ",name={}:{}", self.name_offset, self.name_len)
+    }
+}
+
+// `OFFSET:SIZE`, either of which may be hex
+fn parse_field(s: &str) -> Result<(usize, usize), String> {
+    let (offset, size) = s
+        .split_once(':')
+        .ok_or_else(|| format!("invalid field '{s}', expected OFFSET:SIZE"))?;
+    let to_usize = |n: u64| usize::try_from(n).map_err(|e| e.to_string());
+    Ok((
+        to_usize(parse_offset(offset)?)?,
+        to_usize(parse_offset(size)?)?,
+    ))
+}
+
+impl FromStr for EntryLayout {
+    type Err = String;
+
+    /// Parses either the name of a known layout (`v1`, `v2`) or a full description like
+    /// `entry_size=0x100,id=4:4,offset=8:8,len_compressed=16:8,len_deflated=24:8,name=32:224`,
+    /// optionally with `cpr_indicator=0:4`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "v1" => return Ok(Self::V1),
+            "v2" => return Ok(Self::V2),
+            _ => {}
+        }
+
+        let mut entry_size = None;
+        let mut fields = std::collections::HashMap::new();
+        let mut name = None;
+        for part in s.split(',').map(str::trim) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid layout part '{part}', expected KEY=VALUE"))?;
+            match key {
+                "entry_size" => {
+                    entry_size =
+                        Some(usize::try_from(parse_offset(value)?).map_err(|e| e.to_string())?);
+                }
+                "name" => name = Some(parse_field(value)?),
+                "id" | "offset" | "len_compressed" | "len_deflated" | "cpr_indicator" => {
+                    let (offset, size) = parse_field(value)?;
+                    fields.insert(key, Field::new(offset, size));
+                }
+                _ => return Err(format!("unknown layout field '{key}'")),
+            }
+        }
+
+        let required = |key: &str| {
+            fields
+                .get(key)
+                .copied()
+                .ok_or_else(|| format!("layout is missing '{key}'"))
+        };
+        let (name_offset, name_len) = name.ok_or("layout is missing 'name'")?;
+        let layout = Self {
+            entry_size: entry_size.ok_or("layout is missing 'entry_size'")?,
+            cpr_indicator: fields.get("cpr_indicator").copied(),
+            id: required("id")?,
+            offset: required("offset")?,
+            len_compressed: required("len_compressed")?,
+            len_deflated: required("len_deflated")?,
+            name_offset,
+            name_len,
+        };
+        layout.validate()?;
+
+        Ok(layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpk::MagesArchive;
+
+    #[test]
+    fn unknown_minor_versions_use_their_major_layout() {
+        assert_eq!(EntryLayout::for_version(2, 1), None);
+        assert_eq!(EntryLayout::for_major_version(1), Some(&EntryLayout::V1));
+        assert_eq!(EntryLayout::for_major_version(2), Some(&EntryLayout::V2));
+        assert_eq!(EntryLayout::for_major_version(3), None);
+    }
+
+    #[test]
+    fn opens_unknown_minor_version() {
+        let mut header = b"MPK\0".to_vec();
+        header.extend(1_u16.to_le_bytes());
+        header.extend(2_u16.to_le_bytes());
+        header.extend(0_u64.to_le_bytes());
+        header.resize(0x40, 0);
+
+        let archive = MagesArchive::build(&mut header.as_slice());
+        assert_eq!(archive.iter().count(), 0);
+    }
+
+    #[test]
+    fn names_round_trip_in_each_encoding() {
+        for (names, name, raw) in [
+            (
+                NameEncoding::Utf8,
+                "スクリプト.scx",
+                "スクリプト.scx".as_bytes().to_vec(),
+            ),
+            (
+                NameEncoding::ShiftJis,
+                "スクリプト.scx",
+                b"\x83\x58\x83\x4e\x83\x8a\x83\x76\x83\x67.scx".to_vec(),
+            ),
+        ] {
+            let entry = MagesEntry::new(
+                1,
+                name.to_string(),
+                0x800,
+                4,
+                4,
+                Compression::Stored,
+                vec![0; EntryLayout::V2.entry_size],
+            );
+            let header = EntryLayout::V2.write_entry(&entry, Endian::Little, names);
+            assert_eq!(&header[32..32 + raw.len()], raw, "{names}");
+            assert_eq!(header[32 + raw.len()], 0);
+
+            let entry = EntryLayout::V2.read_entry(header, Endian::Little, names);
+            assert_eq!(entry.name(), name);
+            assert_eq!(entry.offset(), 0x800);
+        }
+    }
+}
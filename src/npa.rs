@@ -0,0 +1,8 @@
+mod archive;
+mod bytes;
+mod entry;
+pub mod keys;
+
+pub use archive::NpaArchive;
+pub use entry::NpaEntry;
+pub use keys::NpaKey;
@@ -0,0 +1,647 @@
+use crate::archive::{match_replacements, Archive, ArchiveEntry, ReadSeek, WriteSeek};
+use crate::npa::bytes;
+use crate::npa::bytes::{NpaHeader, NpaRecord};
+use crate::npa::entry::NpaEntry;
+use crate::npa::keys;
+use crate::npa::keys::NpaKey;
+use crate::sniff::FileType;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct NpaArchive {
+    header: NpaHeader,
+    // directories are entries too, but they're kept out of `entries()`
+    entries: IndexMap<u32, NpaEntry>,
+    names_to_ids: HashMap<String, u32>,
+    // why there's no key, if there isn't one
+    key: Result<NpaKey, String>,
+}
+
+impl NpaArchive {
+    pub const NPA_SIG: &'static [u8] = b"NPA\x01\0\0\0";
+
+    /// Reads the archive's entry table. `key` is only needed to read or write the contents of
+    /// encrypted archives; names can always be read.
+    pub fn build<R: Read + Seek>(reader: &mut R, key: Option<NpaKey>) -> Self {
+        let key = key.ok_or_else(|| "pass --game to pick its key".to_string());
+        Self::build_with_key(reader, key)
+    }
+
+    /// Like [`build()`](Self::build), but only looks up the key named `key` (see
+    /// [`NpaKey::for_game()`]) if the archive is encrypted. A key that can't be found is only
+    /// an error once the encrypted contents are needed, so the archive can still be listed.
+    pub fn build_for_key<R: Read + Seek>(reader: &mut R, key: Option<&str>) -> Self {
+        let mut archive = Self::build(reader, None);
+        if archive.is_encrypted() {
+            if let Some(key) = key {
+                archive.key = NpaKey::for_game(key);
+            }
+        }
+        archive
+    }
+
+    fn build_with_key<R: Read + Seek>(reader: &mut R, key: Result<NpaKey, String>) -> Self {
+        let header: NpaHeader = bytes::read_struct(reader);
+        assert_eq!(header.signature, Self::NPA_SIG, "invalid NPA signature");
+
+        let header_key = Self::header_key(&header);
+        let data_start = bytes::HEADER_SIZE + u64::from(header.table_len);
+
+        let mut entries = IndexMap::with_capacity(header.total_count as usize);
+        let mut names_to_ids = HashMap::with_capacity(header.total_count as usize);
+        for id in 0..header.total_count {
+            let name_len: u32 = bytes::read_struct(reader);
+            let mut raw_name = vec![0; name_len as usize];
+            reader
+                .read_exact(&mut raw_name)
+                .expect("failed to read NPA entry name");
+            keys::decrypt_name(&mut raw_name, header_key, id);
+            let record: NpaRecord = bytes::read_struct(reader);
+
+            let entry = NpaEntry::new(
+                id,
+                raw_name,
+                record.kind == bytes::KIND_DIR,
+                record.file_id,
+                data_start + u64::from(record.offset),
+                u64::from(record.len_compressed),
+                u64::from(record.len_deflated),
+                header.compressed != 0,
+            );
+            names_to_ids.insert(entry.name().to_string(), id);
+            entries.insert(id, entry);
+        }
+
+        Self {
+            header,
+            entries,
+            names_to_ids,
+            key,
+        }
+    }
+
+    const fn header_key(header: &NpaHeader) -> u32 {
+        header.key1.wrapping_mul(header.key2)
+    }
+
+    #[must_use]
+    pub const fn is_encrypted(&self) -> bool {
+        self.header.encrypted != 0
+    }
+
+    /// Every file entry, leaving out directories.
+    pub fn iter(&self) -> impl Iterator<Item = &NpaEntry> {
+        self.entries.values().filter(|entry| !entry.is_dir)
+    }
+
+    #[must_use]
+    pub fn get_entry_by_id(&self, id: u32) -> Option<&NpaEntry> {
+        self.entries.get(&id)
+    }
+
+    #[must_use]
+    pub fn get_entry_by_name(&self, name: &str) -> Option<&NpaEntry> {
+        self.names_to_ids
+            .get(name)
+            .and_then(|id| self.get_entry_by_id(*id))
+    }
+
+    // the key is only ever needed for encrypted archives, and then it's required
+    fn data_key(&self) -> Option<&NpaKey> {
+        self.is_encrypted().then(|| {
+            self.key
+                .as_ref()
+                .unwrap_or_else(|e| panic!("NPA archive is encrypted: {e}"))
+        })
+    }
+
+    const fn record_len(entry: &NpaEntry) -> u64 {
+        4 + entry.raw_name.len() as u64 + bytes::RECORD_SIZE
+    }
+
+    fn table_len<'a, I: IntoIterator<Item = &'a NpaEntry>>(entries: I) -> u64 {
+        entries.into_iter().map(Self::record_len).sum()
+    }
+
+    fn data_start(&self) -> u64 {
+        bytes::HEADER_SIZE + u64::from(self.header.table_len)
+    }
+
+    // map of entry name => replacement file
+    fn build_repack_map<P: AsRef<Path>>(&self, rpk_paths: &[P]) -> HashMap<String, PathBuf> {
+        match_replacements(self.iter().map(NpaEntry::name), rpk_paths)
+            .into_iter()
+            .zip(rpk_paths)
+            .filter_map(|(name, path)| Some((name?.to_string(), path.as_ref().to_path_buf())))
+            .collect()
+    }
+
+    // Compresses and encrypts a replacement file the way the archive expects. Returns the data
+    // to store along with the file's original length.
+    fn prepare_replacement(&self, entry: &NpaEntry, rpk_path: &Path) -> (Vec<u8>, u64) {
+        let contents = std::fs::read(rpk_path).unwrap();
+        let len_deflated = contents.len() as u64;
+
+        let mut data = if entry.is_compressed() {
+            let mut zlib_writer = ZlibEncoder::new(Vec::new(), Compression::default());
+            zlib_writer.write_all(&contents).unwrap();
+            zlib_writer.finish().expect("failed to finish zlib writer")
+        } else {
+            contents
+        };
+        if let Some(key) = self.data_key() {
+            key.encrypt(&mut data, &entry.raw_name, len_deflated);
+        }
+
+        (data, len_deflated)
+    }
+
+    fn write_record<W: Write>(&self, writer: &mut W, id: u32, entry: &NpaEntry, data_start: u64) {
+        let mut name = entry.raw_name.clone();
+        keys::encrypt_name(&mut name, Self::header_key(&self.header), id);
+        bytes::write_struct(writer, u32::try_from(name.len()).unwrap());
+        writer.write_all(&name).unwrap();
+
+        let record = NpaRecord {
+            kind: if entry.is_dir {
+                bytes::KIND_DIR
+            } else {
+                bytes::KIND_FILE
+            },
+            file_id: entry.file_id,
+            offset: u32::try_from(entry.offset() - data_start)
+                .expect("NPA entry offset too large for u32"),
+            len_compressed: u32::try_from(entry.len_compressed())
+                .expect("NPA entry size too large for u32"),
+            len_deflated: u32::try_from(entry.len_deflated())
+                .expect("NPA entry size too large for u32"),
+        };
+        bytes::write_struct(writer, record);
+    }
+
+    // Writes out a whole new archive with entries in their current order. Names are scrambled
+    // by position, so each entry's ID becomes its new position in the table.
+    fn rebuild<R: Read + Seek, W: Write + Seek>(
+        &self,
+        orig_reader: &mut R,
+        rpk_writer: &mut W,
+        rpk_paths: &HashMap<String, PathBuf>,
+    ) -> Self {
+        let table_len = Self::table_len(self.entries.values());
+        let data_start = bytes::HEADER_SIZE + table_len;
+        rpk_writer.seek(SeekFrom::Start(data_start)).unwrap();
+
+        let mut rpk_entries = IndexMap::with_capacity(self.entries.len());
+        for (id, entry) in (0..).zip(self.entries.values()) {
+            let offset = rpk_writer.stream_position().unwrap();
+            let new_entry = if entry.is_dir {
+                entry.updated(id, data_start, 0, 0)
+            } else if let Some(rpk_path) = rpk_paths.get(entry.name()) {
+                let (data, len_deflated) = self.prepare_replacement(entry, rpk_path);
+                rpk_writer.write_all(&data).unwrap();
+                entry.updated(id, offset, data.len() as u64, len_deflated)
+            } else {
+                // encryption only depends on the name and size, so the data can be copied as-is
+                orig_reader.seek(SeekFrom::Start(entry.offset())).unwrap();
+                io::copy(&mut orig_reader.take(entry.len_compressed()), rpk_writer)
+                    .expect("failed to copy entry from reader");
+                entry.updated(id, offset, entry.len_compressed(), entry.len_deflated())
+            };
+            rpk_entries.insert(id, new_entry);
+        }
+
+        let header = NpaHeader {
+            table_len: u32::try_from(table_len).expect("NPA entry table too large"),
+            ..self.header
+        };
+        let rpk_archive = Self {
+            header,
+            names_to_ids: rpk_entries
+                .values()
+                .map(|entry| (entry.name().to_string(), entry.id()))
+                .collect(),
+            entries: rpk_entries,
+            key: self.key.clone(),
+        };
+
+        rpk_writer.seek(SeekFrom::Start(0)).unwrap();
+        bytes::write_struct(rpk_writer, header);
+        for (&id, entry) in &rpk_archive.entries {
+            rpk_archive.write_record(rpk_writer, id, entry, data_start);
+        }
+        rpk_writer.flush().unwrap();
+
+        rpk_archive
+    }
+
+    #[allow(clippy::return_self_not_must_use)]
+    pub fn repack_entries<R, W, P>(
+        &self,
+        orig_reader: &mut R,
+        rpk_writer: &mut W,
+        rpk_paths: &[P],
+    ) -> Self
+    where
+        R: Read + Seek,
+        W: Write + Seek,
+        P: AsRef<Path>,
+    {
+        let rpk_paths = self.build_repack_map(rpk_paths);
+        self.rebuild(orig_reader, rpk_writer, &rpk_paths)
+    }
+
+    // The space an entry is allowed to occupy without clobbering its neighbor. The last entry
+    // in the file can grow freely.
+    fn available_space(&self, entry: &NpaEntry) -> Option<u64> {
+        self.iter()
+            .map(NpaEntry::offset)
+            .filter(|&offset| offset > entry.offset())
+            .min()
+            .map(|next_offset| next_offset - entry.offset())
+    }
+
+    fn record_pos(&self, entry_id: u32) -> u64 {
+        bytes::HEADER_SIZE
+            + Self::table_len(
+                self.entries
+                    .values()
+                    .take_while(|entry| entry.id() != entry_id),
+            )
+    }
+
+    fn patch_entry<F: Write + Seek>(&mut self, archive: &mut F, entry_id: u32, rpk_path: &Path) {
+        let entry = &self.entries[&entry_id];
+        let (data, len_deflated) = self.prepare_replacement(entry, rpk_path);
+        let len_compressed = data.len() as u64;
+
+        let new_offset = if self
+            .available_space(entry)
+            .is_none_or(|space| len_compressed <= space)
+        {
+            archive.seek(SeekFrom::Start(entry.offset())).unwrap()
+        } else {
+            archive.seek(SeekFrom::End(0)).unwrap()
+        };
+        archive.write_all(&data).unwrap();
+
+        let new_entry = entry.updated(entry_id, new_offset, len_compressed, len_deflated);
+        archive
+            .seek(SeekFrom::Start(self.record_pos(entry_id)))
+            .unwrap();
+        self.write_record(archive, entry_id, &new_entry, self.data_start());
+        self.entries.insert(entry_id, new_entry);
+    }
+
+    /// Replaces entries directly inside `archive` instead of rebuilding it, the same way as
+    /// `MagesArchive::patch_entries()`. Replacement files are matched on their path inside the
+    /// archive, see [`match_replacements()`].
+    pub fn patch_entries<F, P>(&mut self, archive: &mut F, rpk_paths: &[P]) -> Result<(), String>
+    where
+        F: Write + Seek,
+        P: AsRef<Path>,
+    {
+        let names = match_replacements(self.iter().map(NpaEntry::name), rpk_paths);
+
+        let mut replacements = Vec::new();
+        for (name, rpk_path) in names.into_iter().zip(rpk_paths) {
+            let rpk_path = rpk_path.as_ref();
+            let name = name.ok_or_else(|| {
+                format!("no entry in the archive matches '{}'", rpk_path.display())
+            })?;
+            for entry in self.iter().filter(|entry| entry.name() == name) {
+                replacements.push((entry.id(), rpk_path));
+            }
+        }
+        for (entry_id, rpk_path) in replacements {
+            self.patch_entry(archive, entry_id, rpk_path);
+        }
+
+        archive.flush().unwrap();
+        Ok(())
+    }
+
+    /// Rewrites the archive with no unused space. NPA entries don't need any alignment, so
+    /// they end up packed back to back.
+    #[allow(clippy::return_self_not_must_use)]
+    pub fn compact<R, W>(&self, orig_reader: &mut R, rpk_writer: &mut W) -> Self
+    where
+        R: Read + Seek,
+        W: Write + Seek,
+    {
+        self.rebuild(orig_reader, rpk_writer, &HashMap::new())
+    }
+}
+
+impl Archive for NpaArchive {
+    fn description(&self) -> String {
+        let mut flags = Vec::new();
+        if self.header.compressed != 0 {
+            flags.push("zlib");
+        }
+        if self.is_encrypted() {
+            flags.push("encrypted");
+        }
+
+        if flags.is_empty() {
+            "NPA".to_string()
+        } else {
+            format!("NPA ({})", flags.join(", "))
+        }
+    }
+
+    fn entries(&self) -> Vec<&dyn ArchiveEntry> {
+        self.iter()
+            .map(|entry| entry as &dyn ArchiveEntry)
+            .collect()
+    }
+
+    fn open_entry<'r>(
+        &self,
+        reader: &'r mut dyn ReadSeek,
+        entry: &dyn ArchiveEntry,
+    ) -> Box<dyn Read + 'r> {
+        self.entries[&entry.id()].open(reader, self.data_key())
+    }
+
+    fn sniff_entry(&self, reader: &mut dyn ReadSeek, entry: &dyn ArchiveEntry) -> FileType {
+        // without a key, the best we can do for encrypted archives is to go by the name
+        if self.is_encrypted() && self.key.is_err() {
+            return FileType::from_extension(entry.name()).unwrap_or(FileType::Unknown);
+        }
+
+        let mut head = Vec::with_capacity(FileType::SNIFF_LEN);
+        self.open_entry(reader, entry)
+            .take(FileType::SNIFF_LEN as u64)
+            .read_to_end(&mut head)
+            .expect("failed to read entry from reader");
+
+        FileType::sniff(&head, entry.len_deflated())
+    }
+
+    fn repack(
+        &self,
+        mut orig_reader: &mut dyn ReadSeek,
+        mut rpk_writer: &mut dyn WriteSeek,
+        rpk_paths: &[PathBuf],
+    ) {
+        self.repack_entries(&mut orig_reader, &mut rpk_writer, rpk_paths);
+    }
+
+    fn patch(
+        &mut self,
+        mut archive: &mut dyn WriteSeek,
+        rpk_paths: &[PathBuf],
+    ) -> Result<(), String> {
+        self.patch_entries(&mut archive, rpk_paths)
+    }
+
+    fn compact(&self, mut orig_reader: &mut dyn ReadSeek, mut writer: &mut dyn WriteSeek) {
+        Self::compact(self, &mut orig_reader, &mut writer);
+    }
+
+    fn sort_by_id(&mut self) {
+        self.entries.sort_keys();
+    }
+
+    fn sort_by_name(&mut self) {
+        // directories sort before the files in them, since their names are prefixes
+        self.entries
+            .sort_by(|_, e1, _, e2| e1.raw_name.cmp(&e2.raw_name));
+    }
+
+    fn header_table_len(&self) -> u64 {
+        self.data_start()
+    }
+
+    fn alignment(&self) -> u64 {
+        1
+    }
+
+    fn reported_entry_count(&self) -> u64 {
+        u64::from(self.header.file_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npa::keys::tests::test_key;
+    use std::io::Cursor;
+    use std::panic;
+
+    const KEY1: u32 = 0x4d2;
+    const KEY2: u32 = 0x162e;
+
+    // an archive of `entries`, each a backslash-separated name and contents, or no contents
+    // for directories
+    fn build_npa(
+        entries: &[(&str, Option<&[u8]>)],
+        compressed: bool,
+        key: Option<&NpaKey>,
+    ) -> Vec<u8> {
+        let mut table = Vec::new();
+        let mut data = Vec::new();
+        let mut file_id = 0;
+        for (id, (name, contents)) in (0..).zip(entries) {
+            let mut raw_name = name.as_bytes().to_vec();
+            keys::encrypt_name(&mut raw_name, KEY1.wrapping_mul(KEY2), id);
+            bytes::write_struct(&mut table, u32::try_from(raw_name.len()).unwrap());
+            table.extend(raw_name);
+
+            let record = contents.map_or(
+                NpaRecord {
+                    kind: bytes::KIND_DIR,
+                    file_id: 0,
+                    offset: 0,
+                    len_compressed: 0,
+                    len_deflated: 0,
+                },
+                |contents| {
+                    let mut stored = if compressed {
+                        let mut zlib_writer = ZlibEncoder::new(Vec::new(), Compression::default());
+                        zlib_writer.write_all(contents).unwrap();
+                        zlib_writer.finish().unwrap()
+                    } else {
+                        contents.to_vec()
+                    };
+                    if let Some(key) = key {
+                        key.encrypt(&mut stored, name.as_bytes(), contents.len() as u64);
+                    }
+                    let record = NpaRecord {
+                        kind: bytes::KIND_FILE,
+                        file_id,
+                        offset: u32::try_from(data.len()).unwrap(),
+                        len_compressed: u32::try_from(stored.len()).unwrap(),
+                        len_deflated: u32::try_from(contents.len()).unwrap(),
+                    };
+                    file_id += 1;
+                    data.extend(stored);
+                    record
+                },
+            );
+            bytes::write_struct(&mut table, record);
+        }
+
+        let folder_count = entries
+            .iter()
+            .filter(|(_, contents)| contents.is_none())
+            .count();
+        let header = NpaHeader {
+            signature: NpaArchive::NPA_SIG.try_into().unwrap(),
+            key1: KEY1,
+            key2: KEY2,
+            compressed: u8::from(compressed),
+            encrypted: u8::from(key.is_some()),
+            total_count: u32::try_from(entries.len()).unwrap(),
+            folder_count: u32::try_from(folder_count).unwrap(),
+            file_count: u32::try_from(entries.len() - folder_count).unwrap(),
+            unknown: [0; 2],
+            table_len: u32::try_from(table.len()).unwrap(),
+        };
+        let mut npa = Vec::new();
+        bytes::write_struct(&mut npa, header);
+        npa.extend(table);
+        npa.extend(data);
+        npa
+    }
+
+    fn contents(npa: &[u8], key: Option<&NpaKey>) -> Vec<(String, Vec<u8>)> {
+        let mut reader = Cursor::new(npa);
+        let archive = NpaArchive::build(&mut reader, key.cloned());
+        archive
+            .iter()
+            .map(|entry| {
+                let mut data = Vec::new();
+                entry
+                    .open(&mut reader, archive.data_key())
+                    .read_to_end(&mut data)
+                    .unwrap();
+                (entry.name().to_string(), data)
+            })
+            .collect()
+    }
+
+    fn repack(npa: &[u8], key: Option<&NpaKey>, rpk_paths: &[PathBuf]) -> Vec<u8> {
+        let archive = NpaArchive::build(&mut Cursor::new(npa), key.cloned());
+        let mut rpk = Cursor::new(Vec::new());
+        archive.repack_entries(&mut Cursor::new(npa), &mut rpk, rpk_paths);
+        rpk.into_inner()
+    }
+
+    fn patch(npa: &[u8], key: Option<&NpaKey>, rpk_paths: &[PathBuf]) -> Vec<u8> {
+        let mut archive = NpaArchive::build(&mut Cursor::new(npa), key.cloned());
+        let mut patched = Cursor::new(npa.to_vec());
+        archive.patch_entries(&mut patched, rpk_paths).unwrap();
+        patched.into_inner()
+    }
+
+    // writes a replacement for the entry `name` where extracting to `dir` would put it
+    fn replacement(dir: &tempfile::TempDir, name: &str, data: &[u8]) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    const ENTRIES: &[(&str, Option<&[u8]>)] = &[
+        ("nss", None),
+        ("nss\\boot.nss", Some(b"boot")),
+        ("nss\\main.nss", Some(b"main script")),
+        ("sys", None),
+        ("sys\\main.nss", Some(b"system script")),
+    ];
+
+    fn expected(replaced: Option<(usize, &[u8])>) -> Vec<(String, Vec<u8>)> {
+        let mut expected = ENTRIES
+            .iter()
+            .filter_map(|(name, contents)| Some((name.replace('\\', "/"), (*contents)?.to_vec())))
+            .collect::<Vec<_>>();
+        if let Some((index, data)) = replaced {
+            expected[index].1 = data.to_vec();
+        }
+        expected
+    }
+
+    #[test]
+    fn reads_every_kind_of_archive() {
+        let key = test_key();
+        for compressed in [false, true] {
+            for key in [None, Some(&key)] {
+                let npa = build_npa(ENTRIES, compressed, key);
+                assert_eq!(contents(&npa, key), expected(None));
+            }
+        }
+    }
+
+    #[test]
+    fn repack_and_patch_replace_entries() {
+        let key = test_key();
+        let dir = tempfile::tempdir().unwrap();
+        let new_main = b"a longer main script than the one it replaces";
+        let rpk_paths = [replacement(&dir, "nss/main.nss", new_main)];
+
+        for compressed in [false, true] {
+            for key in [None, Some(&key)] {
+                let npa = build_npa(ENTRIES, compressed, key);
+                let expected = expected(Some((1, new_main)));
+                assert_eq!(contents(&repack(&npa, key, &rpk_paths), key), expected);
+                assert_eq!(contents(&patch(&npa, key, &rpk_paths), key), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn replacements_match_the_whole_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let rpk_paths = [replacement(&dir, "sys/main.nss", b"patched")];
+        let npa = build_npa(ENTRIES, false, None);
+        let expected = expected(Some((2, b"patched")));
+        assert_eq!(contents(&repack(&npa, None, &rpk_paths), None), expected);
+        assert_eq!(contents(&patch(&npa, None, &rpk_paths), None), expected);
+    }
+
+    #[test]
+    fn patch_refuses_unmatched_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let npa = build_npa(ENTRIES, false, None);
+        let mut archive = NpaArchive::build(&mut Cursor::new(&npa), None);
+        let mut patched = Cursor::new(npa.clone());
+        let rpk_paths = [
+            replacement(&dir, "nss/boot.nss", b"BOOT"),
+            replacement(&dir, "other/main.nss", b"main"),
+        ];
+        let message = archive.patch_entries(&mut patched, &rpk_paths).unwrap_err();
+        assert!(
+            message.contains("no entry in the archive matches"),
+            "{message}"
+        );
+        assert_eq!(patched.into_inner(), npa);
+    }
+
+    #[test]
+    fn keys_are_only_looked_up_for_encrypted_archives() {
+        let npa = build_npa(ENTRIES, false, None);
+        let archive = NpaArchive::build_for_key(&mut Cursor::new(&npa), Some("no-such-game"));
+        assert!(archive.data_key().is_none());
+    }
+
+    #[test]
+    fn missing_keys_only_fail_reading_contents() {
+        let npa = build_npa(ENTRIES, false, Some(&test_key()));
+        let archive = NpaArchive::build_for_key(&mut Cursor::new(&npa), Some("no-such-game"));
+        assert_eq!(archive.iter().count(), 3);
+
+        let error = panic::catch_unwind(|| archive.data_key().map(|key| key.seed)).unwrap_err();
+        let message = error.downcast::<String>().unwrap();
+        assert!(
+            message.contains("unknown NPA key 'no-such-game'"),
+            "{message}"
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use bincode::{Decode, Encode};
+
+pub use crate::bytes::{read_struct, write_struct};
+
+#[derive(Debug, Clone, Copy, Decode, Encode)]
+pub(super) struct NpaHeader {
+    pub signature: [u8; 7],
+    // the product of the two keys obfuscates entry names
+    pub key1: u32,
+    pub key2: u32,
+    pub compressed: u8,
+    pub encrypted: u8,
+    pub total_count: u32,
+    pub folder_count: u32,
+    pub file_count: u32,
+    pub unknown: [u32; 2],
+    // length of the entry table, which the data directly follows
+    pub table_len: u32,
+}
+
+// the fixed-size part of an entry table record, which follows the record's length-prefixed name
+#[derive(Debug, Clone, Copy, Decode, Encode)]
+pub(super) struct NpaRecord {
+    pub kind: u8,
+    pub file_id: u32,
+    // relative to the end of the entry table
+    pub offset: u32,
+    pub len_compressed: u32,
+    pub len_deflated: u32,
+}
+
+pub const HEADER_SIZE: u64 = 0x29;
+pub const RECORD_SIZE: u64 = 0x11;
+
+pub const KIND_DIR: u8 = 1;
+pub const KIND_FILE: u8 = 2;
@@ -0,0 +1,150 @@
+use crate::archive::ArchiveEntry;
+use crate::npa::keys::NpaKey;
+use flate2::read::ZlibDecoder;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+#[derive(Debug)]
+pub struct NpaEntry {
+    // position in the entry table, which the name obfuscation depends on
+    id: u32,
+    name: String,
+    // the name as stored (once unscrambled), which data encryption depends on
+    pub(super) raw_name: Vec<u8>,
+    pub(super) is_dir: bool,
+    pub(super) file_id: u32,
+    offset: u64,
+    len_compressed: u64,
+    len_deflated: u64,
+    compressed: bool,
+}
+
+impl NpaEntry {
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn new(
+        id: u32,
+        raw_name: Vec<u8>,
+        is_dir: bool,
+        file_id: u32,
+        offset: u64,
+        len_compressed: u64,
+        len_deflated: u64,
+        compressed: bool,
+    ) -> Self {
+        // NPA paths use backslashes
+        let name = String::from_utf8_lossy(&raw_name).replace('\\', "/");
+        Self {
+            id,
+            name,
+            raw_name,
+            is_dir,
+            file_id,
+            offset,
+            len_compressed,
+            len_deflated,
+            compressed,
+        }
+    }
+
+    #[must_use]
+    pub const fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The entry's path inside the archive with `/` separators, e.g. `nss/boot.nss`.
+    #[allow(clippy::missing_const_for_fn)] // compilation error if it's made const
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub const fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    #[must_use]
+    pub const fn len_compressed(&self) -> u64 {
+        self.len_compressed
+    }
+
+    #[must_use]
+    pub const fn len_deflated(&self) -> u64 {
+        self.len_deflated
+    }
+
+    #[must_use]
+    pub const fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Opens a reader over this entry's contents, decrypting them with `key` if the archive is
+    /// encrypted.
+    pub fn open<'r, R: Read + Seek + ?Sized>(
+        &self,
+        reader: &'r mut R,
+        key: Option<&NpaKey>,
+    ) -> Box<dyn Read + 'r> {
+        reader.seek(SeekFrom::Start(self.offset)).unwrap();
+        let mut reader = reader.take(self.len_compressed);
+        let Some(key) = key else {
+            return if self.compressed {
+                Box::new(ZlibDecoder::new(reader))
+            } else {
+                Box::new(reader)
+            };
+        };
+
+        let mut data = Vec::with_capacity(usize::try_from(self.len_compressed).unwrap());
+        reader
+            .read_to_end(&mut data)
+            .expect("failed to read entry from reader");
+        key.decrypt(&mut data, &self.raw_name, self.len_deflated);
+
+        if self.compressed {
+            Box::new(ZlibDecoder::new(Cursor::new(data)))
+        } else {
+            Box::new(Cursor::new(data))
+        }
+    }
+
+    #[must_use]
+    pub fn updated(&self, id: u32, offset: u64, len_compressed: u64, len_deflated: u64) -> Self {
+        Self {
+            id,
+            name: self.name.clone(),
+            raw_name: self.raw_name.clone(),
+            is_dir: self.is_dir,
+            file_id: self.file_id,
+            offset,
+            len_compressed,
+            len_deflated,
+            compressed: self.compressed,
+        }
+    }
+}
+
+impl ArchiveEntry for NpaEntry {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn len_compressed(&self) -> u64 {
+        self.len_compressed
+    }
+
+    fn len_deflated(&self) -> u64 {
+        self.len_deflated
+    }
+
+    fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+}
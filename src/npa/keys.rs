@@ -0,0 +1,203 @@
+// NPA obfuscation. Entry names are scrambled with keys stored in the archive header, so they
+// can always be read. Entry data in encrypted archives is additionally run through a
+// game-specific substitution table, which has to be supplied by the user.
+
+use std::path::Path;
+
+// only the start of each entry's data is encrypted
+const ENCRYPTED_LEN: usize = 0x1000;
+const KEY_FILE_LEN: usize = 4 + 256;
+
+/// A game's data encryption key: a seed that's mixed with each entry's name and size, and a
+/// byte substitution table.
+#[derive(Debug, Clone)]
+pub struct NpaKey {
+    pub seed: u32,
+    pub table: [u8; 256],
+}
+
+/// Keys for games whose tables have been checked against retail archives, by the name passed to
+/// `--game`. There are none yet, so keys are only read from key files for now.
+pub static KNOWN_KEYS: &[(&str, NpaKey)] = &[];
+
+impl NpaKey {
+    /// Looks up `game` in [`KNOWN_KEYS`], falling back to treating it as the path to a key file.
+    pub fn for_game(game: &str) -> Result<Self, String> {
+        if let Some((_, key)) = KNOWN_KEYS.iter().find(|(name, _)| *name == game) {
+            return Ok(key.clone());
+        }
+
+        let path = Path::new(game);
+        if !path.is_file() {
+            let known = KNOWN_KEYS.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            let known = if known.is_empty() {
+                "none yet, pass a key file instead".to_string()
+            } else {
+                known.join(", ")
+            };
+            return Err(format!("unknown NPA key '{game}' (known keys: {known})"));
+        }
+        Self::from_file(path)
+    }
+
+    /// Reads a key file: the seed as a little-endian u32, followed by the 256-byte table.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        if bytes.len() != KEY_FILE_LEN {
+            return Err(format!(
+                "{}: NPA key files must be exactly {KEY_FILE_LEN} bytes",
+                path.display()
+            ));
+        }
+
+        let key = Self {
+            seed: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            table: bytes[4..].try_into().unwrap(),
+        };
+        let mut seen = [false; 256];
+        for &b in &key.table {
+            seen[usize::from(b)] = true;
+        }
+        if !seen.iter().all(|&s| s) {
+            return Err(format!(
+                "{}: NPA key table must contain every byte value exactly once",
+                path.display()
+            ));
+        }
+        Ok(key)
+    }
+
+    #[allow(clippy::cast_possible_truncation)] // only the low byte is ever used
+    fn entry_key(&self, name: &[u8], len_deflated: u64) -> u8 {
+        let key = name
+            .iter()
+            .fold(self.seed, |key, &b| key.wrapping_sub(u32::from(b)));
+        key.wrapping_mul(name.len() as u32)
+            .wrapping_add(len_deflated as u32) as u8
+    }
+
+    /// Decrypts the (possibly compressed) data of the entry named `name`, which is the raw name
+    /// as stored in the archive.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn decrypt(&self, data: &mut [u8], name: &[u8], len_deflated: u64) {
+        let key = self.entry_key(name, len_deflated);
+        for (i, byte) in data.iter_mut().take(ENCRYPTED_LEN + name.len()).enumerate() {
+            *byte = self.table[usize::from(*byte)]
+                .wrapping_sub(key)
+                .wrapping_sub(i as u8);
+        }
+    }
+
+    /// The inverse of `decrypt()`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn encrypt(&self, data: &mut [u8], name: &[u8], len_deflated: u64) {
+        let mut inverse = [0; 256];
+        for (plain, &cipher) in (0..=u8::MAX).zip(&self.table) {
+            inverse[usize::from(cipher)] = plain;
+        }
+
+        let key = self.entry_key(name, len_deflated);
+        for (i, byte) in data.iter_mut().take(ENCRYPTED_LEN + name.len()).enumerate() {
+            *byte = inverse[usize::from(byte.wrapping_add(key).wrapping_add(i as u8))];
+        }
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)] // only the low byte is ever used
+fn name_key(header_key: u32, index: u32, pos: usize) -> u8 {
+    [24, 16, 8, 0]
+        .into_iter()
+        .fold(0xfc_u32.wrapping_mul(pos as u32), |key, shift| {
+            key.wrapping_sub(header_key >> shift)
+                .wrapping_sub(index >> shift)
+        }) as u8
+}
+
+/// Unscrambles the name of the entry at `index` in the entry table.
+pub(super) fn decrypt_name(name: &mut [u8], header_key: u32, index: u32) {
+    for (pos, byte) in name.iter_mut().enumerate() {
+        *byte = byte.wrapping_add(name_key(header_key, index, pos));
+    }
+}
+
+pub(super) fn encrypt_name(name: &mut [u8], header_key: u32, index: u32) {
+    for (pos, byte) in name.iter_mut().enumerate() {
+        *byte = byte.wrapping_sub(name_key(header_key, index, pos));
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A made-up key; any permutation of the bytes works as a table.
+    pub fn test_key() -> NpaKey {
+        let mut table = [0; 256];
+        for (plain, cipher) in (0..=u8::MAX).zip(&mut table) {
+            *cipher = plain.wrapping_mul(167).wrapping_add(13);
+        }
+        NpaKey {
+            seed: 0x1234_5678,
+            table,
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        let name = b"nss\\boot.nss";
+        for index in [0, 1, 0x1234] {
+            let mut scrambled = name.to_vec();
+            encrypt_name(&mut scrambled, 0x4d2_u32.wrapping_mul(0x162e), index);
+            assert_ne!(scrambled, name);
+            decrypt_name(&mut scrambled, 0x4d2_u32.wrapping_mul(0x162e), index);
+            assert_eq!(scrambled, name);
+        }
+    }
+
+    #[test]
+    fn data_round_trips() {
+        let key = test_key();
+        let name = b"nss\\boot.nss";
+        // longer than the encrypted part, so the plain tail is covered too
+        let data = (0..ENCRYPTED_LEN * 2)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut encrypted = data.clone();
+        key.encrypt(&mut encrypted, name, data.len() as u64);
+        let encrypted_len = ENCRYPTED_LEN + name.len();
+        assert_ne!(encrypted[..encrypted_len], data[..encrypted_len]);
+        assert_eq!(encrypted[encrypted_len..], data[encrypted_len..]);
+
+        key.decrypt(&mut encrypted, name, data.len() as u64);
+        assert_eq!(encrypted, data);
+    }
+
+    #[test]
+    fn reads_key_files() {
+        let key = test_key();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut bytes = key.seed.to_le_bytes().to_vec();
+        bytes.extend(key.table);
+        std::fs::write(file.path(), bytes).unwrap();
+
+        let read = NpaKey::from_file(file.path()).unwrap();
+        assert_eq!(read.seed, key.seed);
+        assert_eq!(read.table, key.table);
+    }
+
+    #[test]
+    fn rejects_tables_that_arent_permutations() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), [0; KEY_FILE_LEN]).unwrap();
+        let error = NpaKey::from_file(file.path()).unwrap_err();
+        assert!(error.contains("every byte value exactly once"), "{error}");
+    }
+
+    #[test]
+    fn unknown_keys_are_errors() {
+        let error = NpaKey::for_game("no-such-game").unwrap_err();
+        assert!(error.contains("unknown NPA key 'no-such-game'"), "{error}");
+    }
+}
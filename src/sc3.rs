@@ -0,0 +1,13 @@
+mod asm;
+pub mod charset;
+mod disasm;
+pub mod instructions;
+mod script;
+pub mod strings;
+pub mod text;
+
+pub use asm::assemble;
+pub use charset::Charset;
+pub use disasm::disassemble;
+pub use instructions::InstructionSet;
+pub use script::{ReturnAddress, Sc3Script};
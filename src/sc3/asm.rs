@@ -0,0 +1,321 @@
+// Turns the text form written by `disassemble()` back into a script. Label and return address
+// markers are placed wherever they appear in the code, and strings are laid out one after
+// another, so the tables are rebuilt from scratch no matter how much the code or strings grew.
+
+use crate::sc3::instructions::{InstructionSet, Operand};
+use crate::sc3::script::{ReturnAddress, Sc3Script};
+use crate::sc3::text;
+use crate::select::parse_offset;
+use std::collections::BTreeMap;
+
+#[derive(Default)]
+struct Assembler {
+    code: Vec<u8>,
+    labels: BTreeMap<usize, usize>,
+    return_addresses: BTreeMap<usize, ReturnAddress>,
+    strings: BTreeMap<usize, Vec<u8>>,
+    // (line, label or string index) of every reference, checked once everything is defined
+    label_refs: Vec<(usize, usize)>,
+    string_refs: Vec<(usize, usize)>,
+}
+
+fn parse_hex_bytes<'a>(bytes: impl Iterator<Item = &'a str>) -> Result<Vec<u8>, String> {
+    bytes
+        .map(|b| u8::from_str_radix(b, 16).map_err(|_| format!("invalid byte '{b}'")))
+        .collect()
+}
+
+// An expression written as `expr_` and its bytes in hex, which have to make up exactly one
+// expression.
+fn parse_expression(s: &str) -> Result<Vec<u8>, String> {
+    let hex = s
+        .strip_prefix("expr_")
+        .ok_or_else(|| format!("expected an expression, not '{s}'"))?;
+    if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return Err(format!(
+            "invalid expression '{s}', expected its bytes in hex"
+        ));
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect::<Vec<_>>();
+    if text::expression_len(&bytes) != Some(bytes.len()) {
+        return Err(format!("'{s}' isn't a single complete expression"));
+    }
+    Ok(bytes)
+}
+
+fn parse_index(s: &str) -> Result<usize, String> {
+    s.parse().map_err(|_| format!("invalid index '{s}'"))
+}
+
+// `items` must be numbered 0, 1, 2, ... without gaps
+fn into_contiguous<T>(items: BTreeMap<usize, T>, kind: &str) -> Result<Vec<T>, String> {
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(expected, (i, item))| {
+            if i == expected {
+                Ok(item)
+            } else {
+                Err(format!("{kind}_{expected} is never defined"))
+            }
+        })
+        .collect()
+}
+
+fn insert_unique<T>(
+    map: &mut BTreeMap<usize, T>,
+    i: usize,
+    item: T,
+    kind: &str,
+) -> Result<(), String> {
+    if map.insert(i, item).is_some() {
+        return Err(format!("{kind}_{i} is defined more than once"));
+    }
+    Ok(())
+}
+
+impl Assembler {
+    fn marker(&mut self, name: &str) -> Result<(), String> {
+        let pos = self.code.len();
+        if let Some(i) = name.strip_prefix("label_") {
+            insert_unique(&mut self.labels, parse_index(i)?, pos, "label")
+        } else if let Some(i) = name.strip_prefix("return_") {
+            let i = parse_index(i)?;
+            insert_unique(
+                &mut self.return_addresses,
+                i,
+                ReturnAddress::Code(pos),
+                "return",
+            )
+        } else {
+            Err(format!("unknown marker '{name}'"))
+        }
+    }
+
+    fn directive(
+        &mut self,
+        directive: &str,
+        mut args: std::str::SplitWhitespace,
+    ) -> Result<(), String> {
+        match directive {
+            ".db" => {
+                let bytes = parse_hex_bytes(args)?;
+                self.code.extend(bytes);
+            }
+            ".string" => {
+                let i = parse_index(args.next().ok_or(".string needs an index")?)?;
+                let string = parse_hex_bytes(args)?;
+                insert_unique(&mut self.strings, i, string, "string")?;
+            }
+            ".return" => {
+                let i = parse_index(args.next().ok_or(".return needs an index")?)?;
+                let addr = parse_offset(args.next().ok_or(".return needs an address")?)?;
+                let addr = u32::try_from(addr)
+                    .map_err(|_| format!("return address {addr:#x} too large"))?;
+                insert_unique(
+                    &mut self.return_addresses,
+                    i,
+                    ReturnAddress::Raw(addr),
+                    "return",
+                )?;
+            }
+            _ => return Err(format!("unknown directive '{directive}'")),
+        }
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        line_no: usize,
+        mnemonic: &str,
+        args: std::str::SplitWhitespace,
+        instructions: &InstructionSet,
+    ) -> Result<(), String> {
+        let instruction = instructions
+            .by_name(mnemonic)
+            .ok_or_else(|| format!("unknown instruction '{mnemonic}'"))?;
+        let args = args.collect::<Vec<_>>();
+        if args.len() != instruction.operands.len() {
+            return Err(format!(
+                "{mnemonic} takes {} operands, not {}",
+                instruction.operands.len(),
+                args.len()
+            ));
+        }
+
+        self.code.extend(&instruction.opcode);
+        for (&operand, arg) in instruction.operands.iter().zip(args) {
+            let Some(size) = operand.fixed_size() else {
+                self.code.extend(parse_expression(arg)?);
+                continue;
+            };
+            let value = match operand {
+                Operand::U8 | Operand::U16 | Operand::U32 => parse_offset(arg)?,
+                Operand::Label => {
+                    let i = parse_index(
+                        arg.strip_prefix("label_")
+                            .ok_or_else(|| format!("expected a label, not '{arg}'"))?,
+                    )?;
+                    self.label_refs.push((line_no, i));
+                    i as u64
+                }
+                Operand::String => {
+                    let i = parse_index(
+                        arg.strip_prefix("string_")
+                            .ok_or_else(|| format!("expected a string, not '{arg}'"))?,
+                    )?;
+                    self.string_refs.push((line_no, i));
+                    i as u64
+                }
+                Operand::Expression => unreachable!("expressions have no fixed size"),
+            };
+
+            let bytes = value.to_le_bytes();
+            if bytes[size..].iter().any(|&b| b != 0) {
+                return Err(format!("'{arg}' doesn't fit in a {operand} operand"));
+            }
+            self.code.extend(&bytes[..size]);
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Sc3Script, String> {
+        let labels = into_contiguous(self.labels, "label")?;
+        let strings = into_contiguous(self.strings, "string")?;
+        let return_addresses = into_contiguous(self.return_addresses, "return")?;
+
+        if let Some((line_no, i)) = self.label_refs.iter().find(|(_, i)| *i >= labels.len()) {
+            return Err(format!("line {line_no}: label_{i} is never defined"));
+        }
+        if let Some((line_no, i)) = self.string_refs.iter().find(|(_, i)| *i >= strings.len()) {
+            return Err(format!("line {line_no}: string_{i} is never defined"));
+        }
+
+        Ok(Sc3Script {
+            labels,
+            code: self.code,
+            strings,
+            return_addresses,
+        })
+    }
+}
+
+/// Assembles the text form of a script, encoding instructions with `instructions`.
+pub fn assemble(text: &str, instructions: &InstructionSet) -> Result<Sc3Script, String> {
+    let mut assembler = Assembler::default();
+    for (line_no, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+        let line = line.split(';').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let result = if let Some(marker) = line.strip_suffix(':') {
+            assembler.marker(marker)
+        } else {
+            let mut parts = line.split_whitespace();
+            let first = parts.next().unwrap();
+            if first.starts_with('.') {
+                assembler.directive(first, parts)
+            } else {
+                assembler.instruction(line_no, first, parts, instructions)
+            }
+        };
+        result.map_err(|e| format!("line {line_no}: {e}"))?;
+    }
+
+    assembler.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sc3::disassemble;
+    use crate::sc3::script::tests::{test_scx, TEST_INSTRUCTIONS};
+
+    fn test_instructions() -> InstructionSet {
+        InstructionSet::parse("test", TEST_INSTRUCTIONS).unwrap()
+    }
+
+    #[test]
+    fn reassembles_a_disassembled_script() {
+        let scx = test_scx();
+        let instructions = test_instructions();
+        // with and without instructions to decode the code with
+        for instructions in [&instructions, &InstructionSet::default()] {
+            let text = disassemble(&Sc3Script::parse(&scx), instructions, None);
+            let script = assemble(&text, instructions).unwrap();
+            assert_eq!(script.to_bytes(), scx, "{}", instructions.name);
+        }
+    }
+
+    #[test]
+    fn edited_code_moves_labels_and_strings() {
+        let text = "\
+label_0:
+    Msg 1 string_0  ; a comment
+    Jump label_1
+label_1:
+return_0:
+    End
+
+.string 0 00 01 02 ff
+";
+        let script = assemble(text, &test_instructions()).unwrap();
+        assert_eq!(script.labels, [0, 10]);
+        assert_eq!(script.code, [2, 0x10, 1, 0, 0, 0, 1, 5, 1, 0, 0]);
+        assert_eq!(script.strings, [[0, 1, 2, 0xff]]);
+        assert_eq!(script.return_addresses, [ReturnAddress::Code(10)]);
+    }
+
+    #[test]
+    fn assembles_expressions() {
+        let instructions = InstructionSet::parse("test", "03 Set expr u8").unwrap();
+        let script = assemble("    Set expr_e00102030400 9", &instructions).unwrap();
+        assert_eq!(script.code, [3, 0xe0, 1, 2, 3, 4, 0, 9]);
+
+        for (text, error) in [
+            ("    Set 7 9", "line 1: expected an expression, not '7'"),
+            (
+                "    Set expr_a007 9",
+                "line 1: 'expr_a007' isn't a single complete expression",
+            ),
+            (
+                "    Set expr_a0070000 9",
+                "line 1: 'expr_a0070000' isn't a single complete expression",
+            ),
+            (
+                "    Set expr_a0070 9",
+                "line 1: invalid expression 'expr_a0070', expected its bytes in hex",
+            ),
+        ] {
+            let e = assemble(text, &instructions).unwrap_err();
+            assert_eq!(e, error, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_references() {
+        for (text, error) in [
+            ("    Jump label_0", "line 1: label_0 is never defined"),
+            ("label_1:", "label_0 is never defined"),
+            ("    Msg 1 string_0", "line 1: string_0 is never defined"),
+            (
+                "    Msg 70000 string_0",
+                "line 1: '70000' doesn't fit in a u16 operand",
+            ),
+            ("    Msg 1", "line 1: Msg takes 2 operands, not 1"),
+            ("    Call label_0", "line 1: unknown instruction 'Call'"),
+            (
+                "label_0:\nlabel_0:",
+                "line 2: label_0 is defined more than once",
+            ),
+        ] {
+            let e = assemble(text, &test_instructions()).unwrap_err();
+            assert_eq!(e, error, "{text:?}");
+        }
+    }
+}
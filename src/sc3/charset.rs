@@ -0,0 +1,144 @@
+// Which character each glyph of a game's font draws. Fonts differ between games, so charsets
+// are data rather than code. A charset file is UTF-8 text listing the characters in glyph order,
+// starting at glyph 0; line breaks are ignored so the table can be laid out like the font atlas.
+//
+// A character listed more than once (e.g. as filler for unused glyphs) is encoded as its first
+// glyph, and its other glyphs are left as `{xxxx}` when decoding so strings still round-trip.
+
+use crate::sc3::text::MAX_GLYPH;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct Charset {
+    pub name: String,
+    chars: Vec<char>,
+    glyphs: HashMap<char, u16>,
+}
+
+/// Charsets for games whose fonts have been checked against retail data, by the name passed to
+/// `--charset`, along with their tables. There are none yet, so charsets are only loaded from
+/// files for now.
+pub static KNOWN_CHARSETS: &[(&str, &str)] = &[];
+
+impl Default for Charset {
+    /// A charset without any characters, leaving every glyph as its index.
+    fn default() -> Self {
+        Self {
+            name: "none".to_string(),
+            chars: Vec::new(),
+            glyphs: HashMap::new(),
+        }
+    }
+}
+
+impl Charset {
+    /// Looks up `game` in [`KNOWN_CHARSETS`], falling back to treating it as the path to a
+    /// charset file.
+    #[must_use]
+    pub fn for_game(game: &str) -> Self {
+        if let Some((name, table)) = KNOWN_CHARSETS.iter().find(|(name, _)| *name == game) {
+            return Self::parse(name, table)
+                .unwrap_or_else(|e| panic!("built-in charset '{name}' is invalid: {e}"));
+        }
+
+        let path = Path::new(game);
+        if !path.is_file() {
+            let known = KNOWN_CHARSETS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>();
+            let known = if known.is_empty() {
+                "none yet, pass a charset file instead".to_string()
+            } else {
+                known.join(", ")
+            };
+            panic!("unknown charset '{game}' (known charsets: {known})");
+        }
+        Self::from_file(path)
+    }
+
+    #[must_use]
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let table = std::fs::read_to_string(path).unwrap();
+        Self::parse(&path.display().to_string(), &table)
+            .unwrap_or_else(|e| panic!("invalid charset {}: {e}", path.display()))
+    }
+
+    pub fn parse(name: &str, table: &str) -> Result<Self, String> {
+        let chars = table
+            .chars()
+            .filter(|&c| c != '\n' && c != '\r')
+            .collect::<Vec<_>>();
+        if chars.len() > usize::from(MAX_GLYPH) {
+            return Err(format!(
+                "{} characters, but glyphs only go up to {MAX_GLYPH:#06x}",
+                chars.len()
+            ));
+        }
+
+        let mut glyphs = HashMap::new();
+        for (glyph, &c) in (0..).zip(&chars) {
+            glyphs.entry(c).or_insert(glyph);
+        }
+        Ok(Self {
+            name: name.to_string(),
+            chars,
+            glyphs,
+        })
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    /// The character `glyph` draws, unless it's unknown or a duplicate.
+    #[must_use]
+    pub fn char(&self, glyph: u16) -> Option<char> {
+        let c = *self.chars.get(usize::from(glyph))?;
+        (self.glyphs[&c] == glyph).then_some(c)
+    }
+
+    /// Every glyph with a character, in glyph order, skipping duplicates.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, char)> + '_ {
+        (0..)
+            .zip(&self.chars)
+            .filter(|&(glyph, c)| self.glyphs[c] == glyph)
+            .map(|(glyph, &c)| (glyph, c))
+    }
+
+    /// The glyph that draws `c`, if the font has one.
+    #[must_use]
+    pub fn glyph(&self, c: char) -> Option<u16> {
+        self.glyphs.get(&c).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyphs_follow_the_table_ignoring_line_breaks() {
+        let charset = Charset::parse("test", "ab\r\ncd\na").unwrap();
+        assert_eq!(charset.char(2), Some('c'));
+        assert_eq!(charset.glyph('d'), Some(3));
+        assert_eq!(charset.char(5), None);
+        // duplicates encode as their first glyph and don't decode at all
+        assert_eq!(charset.glyph('a'), Some(0));
+        assert_eq!(charset.char(4), None);
+        assert_eq!(
+            charset.iter().collect::<Vec<_>>(),
+            [(0, 'a'), (1, 'b'), (2, 'c'), (3, 'd')]
+        );
+    }
+
+    #[test]
+    fn rejects_tables_past_the_last_glyph() {
+        let table = "a".repeat(usize::from(MAX_GLYPH));
+        assert!(Charset::parse("test", &table).is_ok());
+        assert!(Charset::parse("test", &format!("{table}a")).is_err());
+    }
+}
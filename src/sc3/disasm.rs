@@ -0,0 +1,195 @@
+// The text form of a script. Labels and return addresses become markers in the code listing
+// (`label_3:`, `return_0:`), code the instruction set knows is printed as mnemonics and
+// everything else as raw `.db` bytes. Expression operands are printed as their bytes in hex,
+// e.g. `expr_8a0500`. Strings follow the code as `.string` lines, with their
+// text in a comment when there's a charset to decode them with.
+//
+// Once a block of code hits bytes the instruction set doesn't know, there's no telling where
+// the next instruction starts, so the rest of the block up to the next marker stays raw.
+
+use crate::sc3::charset::Charset;
+use crate::sc3::instructions::{Instruction, InstructionSet, Operand};
+use crate::sc3::script::{ReturnAddress, Sc3Script};
+use crate::sc3::text;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const BYTES_PER_LINE: usize = 16;
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn write_raw(out: &mut String, bytes: &[u8]) {
+    for chunk in bytes.chunks(BYTES_PER_LINE) {
+        writeln!(out, "    .db {}", hex_bytes(chunk)).unwrap();
+    }
+}
+
+fn le_value(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0u32, |value, &b| (value << 8) | u32::from(b))
+}
+
+fn format_instruction(instruction: &Instruction, mut operand_bytes: &[u8]) -> String {
+    let mut line = instruction.name.clone();
+    for operand in &instruction.operands {
+        let (bytes, rest) = operand_bytes.split_at(operand.encoded_len(operand_bytes).unwrap());
+        operand_bytes = rest;
+        match operand {
+            Operand::U8 | Operand::U16 | Operand::U32 => write!(line, " {}", le_value(bytes)),
+            Operand::Label => write!(line, " label_{}", le_value(bytes)),
+            Operand::String => write!(line, " string_{}", le_value(bytes)),
+            Operand::Expression => write!(line, " expr_{}", hex_bytes(bytes).replace(' ', "")),
+        }
+        .unwrap();
+    }
+    line
+}
+
+fn write_block(out: &mut String, mut code: &[u8], instructions: &InstructionSet) {
+    while !code.is_empty() {
+        let Some((instruction, len)) = instructions
+            .decode(code)
+            .and_then(|instruction| Some((instruction, instruction.encoded_len(code)?)))
+        else {
+            write_raw(out, code);
+            return;
+        };
+
+        let (bytes, rest) = code.split_at(len);
+        let operand_bytes = &bytes[instruction.opcode.len()..];
+        writeln!(
+            out,
+            "    {}",
+            format_instruction(instruction, operand_bytes)
+        )
+        .unwrap();
+        code = rest;
+    }
+}
+
+/// Renders `script` as text, decoding its code with `instructions` and annotating its strings
+/// with their text in `charset`.
+#[must_use]
+pub fn disassemble(
+    script: &Sc3Script,
+    instructions: &InstructionSet,
+    charset: Option<&Charset>,
+) -> String {
+    let mut markers = BTreeMap::<usize, Vec<String>>::new();
+    for (i, &label) in script.labels.iter().enumerate() {
+        markers.entry(label).or_default().push(format!("label_{i}"));
+    }
+    let mut raw_returns = Vec::new();
+    for (i, &addr) in script.return_addresses.iter().enumerate() {
+        match addr {
+            ReturnAddress::Code(addr) => {
+                markers.entry(addr).or_default().push(format!("return_{i}"));
+            }
+            ReturnAddress::Raw(addr) => raw_returns.push((i, addr)),
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "; SC3 script: {} labels, {} strings, {} return addresses",
+        script.labels.len(),
+        script.strings.len(),
+        script.return_addresses.len()
+    )
+    .unwrap();
+    writeln!(out, "; instruction set: {}", instructions.name).unwrap();
+    writeln!(out).unwrap();
+
+    // code before the first marker, then each marker followed by the code up to the next one
+    let mut start = 0;
+    for (&pos, names) in &markers {
+        write_block(&mut out, &script.code[start..pos], instructions);
+        for name in names {
+            writeln!(out, "{name}:").unwrap();
+        }
+        start = pos;
+    }
+    write_block(&mut out, &script.code[start..], instructions);
+
+    if !script.strings.is_empty() {
+        writeln!(out).unwrap();
+    }
+    for (i, string) in script.strings.iter().enumerate() {
+        write!(out, ".string {i} {}", hex_bytes(string)).unwrap();
+        if let Some(charset) = charset {
+            write!(out, " ; {}", text::decode(string, charset)).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+    for (i, addr) in raw_returns {
+        writeln!(out, ".return {i} {addr:#x}").unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sc3::script::tests::{test_scx, TEST_INSTRUCTIONS};
+
+    #[test]
+    fn disassembles_a_script() {
+        let script = Sc3Script::parse(&test_scx());
+        let instructions = InstructionSet::parse("test", TEST_INSTRUCTIONS).unwrap();
+        assert_eq!(
+            disassemble(&script, &instructions, None),
+            "\
+; SC3 script: 2 labels, 2 strings, 2 return addresses
+; instruction set: test
+
+label_0:
+    Msg 7 string_0
+    Jump label_1
+label_1:
+    Msg 8 string_1
+return_0:
+    End
+    .db ff ee
+
+.string 0 00 01 ff
+.string 1 00 02 ff
+.return 1 0xdeadbeef
+"
+        );
+    }
+
+    #[test]
+    fn expressions_are_printed_as_hex() {
+        let script = Sc3Script {
+            labels: Vec::new(),
+            code: vec![3, 0xa0, 7, 0, 9, 3, 0xa0, 7],
+            strings: Vec::new(),
+            return_addresses: Vec::new(),
+        };
+        let instructions = InstructionSet::parse("test", "03 Set expr u8").unwrap();
+        // the second one is cut off, so it stays raw
+        assert!(disassemble(&script, &instructions, None)
+            .ends_with("\n    Set expr_a00700 9\n    .db 03 a0 07\n"));
+    }
+
+    #[test]
+    fn unknown_code_stays_raw_up_to_the_next_marker() {
+        let script = Sc3Script::parse(&test_scx());
+        let instructions = InstructionSet::parse("test", "0210 Msg u16 string").unwrap();
+        let text = disassemble(&script, &instructions, None);
+        assert!(text.contains(
+            "label_0:\n    Msg 7 string_0\n    .db 01 05 01 00\nlabel_1:\n    Msg 8 string_1\n\
+             return_0:\n    .db 00 ff ee\n"
+        ));
+    }
+}
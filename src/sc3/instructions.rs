@@ -0,0 +1,299 @@
+// SC3 opcodes differ between games and even between releases of the same game, so instruction
+// sets are data rather than code. A definition file has one instruction per line: the opcode
+// bytes in hex, the mnemonic, and the types of its operands in order, e.g.
+//
+//   # comments start with a hash
+//   0105 Jump label
+//   0210 ShowMessage u16 string
+//
+// Operands are little-endian. Labels and strings are referenced by their u16 index. An `expr`
+// operand is an expression encoded the same way as a string's color argument, so its length
+// follows from its terms.
+
+use crate::sc3::text;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    U8,
+    U16,
+    U32,
+    Label,
+    String,
+    Expression,
+}
+
+impl Operand {
+    /// The encoded size of the operand, or `None` for expressions, whose size depends on their
+    /// contents.
+    #[must_use]
+    pub const fn fixed_size(self) -> Option<usize> {
+        match self {
+            Self::U8 => Some(1),
+            Self::U16 | Self::Label | Self::String => Some(2),
+            Self::U32 => Some(4),
+            Self::Expression => None,
+        }
+    }
+
+    /// The size of the operand at the start of `bytes`, or `None` if `bytes` is too short to
+    /// hold it.
+    #[must_use]
+    pub fn encoded_len(self, bytes: &[u8]) -> Option<usize> {
+        match self {
+            Self::Expression => text::expression_len(bytes),
+            _ => self.fixed_size().filter(|&size| size <= bytes.len()),
+        }
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::U32 => "u32",
+            Self::Label => "label",
+            Self::String => "string",
+            Self::Expression => "expr",
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Operand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            Self::U8,
+            Self::U16,
+            Self::U32,
+            Self::Label,
+            Self::String,
+            Self::Expression,
+        ]
+        .into_iter()
+        .find(|operand| operand.name() == s)
+        .ok_or_else(|| format!("unknown operand type '{s}'"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub opcode: Vec<u8>,
+    pub name: String,
+    pub operands: Vec<Operand>,
+}
+
+impl Instruction {
+    /// The encoded size of the instruction at the start of `code`, opcode included, or `None`
+    /// if `code` ends before its operands do.
+    #[must_use]
+    pub fn encoded_len(&self, code: &[u8]) -> Option<usize> {
+        let mut len = self.opcode.len();
+        for operand in &self.operands {
+            len += operand.encoded_len(code.get(len..)?)?;
+        }
+        Some(len)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InstructionSet {
+    pub name: String,
+    instructions: Vec<Instruction>,
+}
+
+/// Instruction sets for games whose opcodes have been checked against retail scripts, by the
+/// name passed to `--instructions`, along with their definitions.
+pub static KNOWN_INSTRUCTION_SETS: &[(&str, &str)] = &[];
+
+impl Default for InstructionSet {
+    /// An instruction set that decodes nothing, leaving all code as raw bytes.
+    fn default() -> Self {
+        Self {
+            name: "none".to_string(),
+            instructions: Vec::new(),
+        }
+    }
+}
+
+impl InstructionSet {
+    /// Looks up `game` in [`KNOWN_INSTRUCTION_SETS`], falling back to treating it as the path
+    /// to a definition file.
+    #[must_use]
+    pub fn for_game(game: &str) -> Self {
+        if let Some((name, definition)) = KNOWN_INSTRUCTION_SETS
+            .iter()
+            .find(|(name, _)| *name == game)
+        {
+            return Self::parse(name, definition)
+                .unwrap_or_else(|e| panic!("built-in instruction set '{name}' is invalid: {e}"));
+        }
+
+        let path = Path::new(game);
+        if !path.is_file() {
+            let known = KNOWN_INSTRUCTION_SETS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>();
+            let known = if known.is_empty() {
+                "none yet, pass a definition file instead".to_string()
+            } else {
+                known.join(", ")
+            };
+            panic!("unknown instruction set '{game}' (known instruction sets: {known})");
+        }
+        Self::from_file(path)
+    }
+
+    #[must_use]
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let definition = std::fs::read_to_string(path).unwrap();
+        Self::parse(&path.display().to_string(), &definition)
+            .unwrap_or_else(|e| panic!("invalid instruction set {}: {e}", path.display()))
+    }
+
+    pub fn parse(name: &str, definition: &str) -> Result<Self, String> {
+        let mut instructions = Vec::<Instruction>::new();
+        for (line_no, line) in definition.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at_line = |e: String| format!("line {}: {e}", line_no + 1);
+
+            let mut parts = line.split_whitespace();
+            let opcode = parts.next().unwrap();
+            let name = parts
+                .next()
+                .ok_or_else(|| at_line("missing mnemonic".to_string()))?;
+            let opcode = parse_hex(opcode).map_err(at_line)?;
+            let operands = parts
+                .map(str::parse)
+                .collect::<Result<Vec<Operand>, _>>()
+                .map_err(at_line)?;
+
+            if instructions.iter().any(|ins| ins.name == name) {
+                return Err(at_line(format!("duplicate mnemonic '{name}'")));
+            }
+            if instructions.iter().any(|ins| ins.opcode == opcode) {
+                return Err(at_line(format!("duplicate opcode for '{name}'")));
+            }
+            instructions.push(Instruction {
+                opcode,
+                name: name.to_string(),
+                operands,
+            });
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            instructions,
+        })
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    #[must_use]
+    pub fn by_name(&self, name: &str) -> Option<&Instruction> {
+        self.instructions.iter().find(|ins| ins.name == name)
+    }
+
+    /// The instruction whose opcode starts `code`, preferring the longest opcode if several
+    /// match.
+    #[must_use]
+    pub fn decode(&self, code: &[u8]) -> Option<&Instruction> {
+        self.instructions
+            .iter()
+            .filter(|ins| code.starts_with(&ins.opcode))
+            .max_by_key(|ins| ins.opcode.len())
+    }
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    if s.is_empty() || !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!(
+            "invalid opcode '{s}', expected an even number of hex digits"
+        ));
+    }
+    Ok((0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_the_longest_opcode() {
+        let instructions = InstructionSet::parse("test", "01 Short\n0105 Long u16\n").unwrap();
+        assert_eq!(
+            instructions.decode(&[0x01, 0x05, 0, 0]).unwrap().name,
+            "Long"
+        );
+        assert_eq!(instructions.decode(&[0x01, 0x06]).unwrap().name, "Short");
+        assert!(instructions.decode(&[0x02]).is_none());
+        assert_eq!(
+            instructions
+                .by_name("Long")
+                .unwrap()
+                .encoded_len(&[1, 5, 0, 0]),
+            Some(4)
+        );
+        assert_eq!(
+            instructions
+                .by_name("Long")
+                .unwrap()
+                .encoded_len(&[1, 5, 0]),
+            None
+        );
+    }
+
+    #[test]
+    fn expression_operands_are_as_long_as_their_terms() {
+        let instructions = InstructionSet::parse(
+            "test",
+            "03 Set expr u8
+",
+        )
+        .unwrap();
+        let set = instructions.by_name("Set").unwrap();
+        // an immediate with one byte of value, then the terminator
+        assert_eq!(set.encoded_len(&[3, 0xa0, 7, 0, 9]), Some(5));
+        // a four-byte immediate and an operator with its precedence
+        assert_eq!(
+            set.encoded_len(&[3, 0xe0, 1, 2, 3, 4, 0x0a, 1, 0, 9]),
+            Some(10)
+        );
+        // no terminator, or no room for the u8 after it
+        assert_eq!(set.encoded_len(&[3, 0xa0, 7]), None);
+        assert_eq!(set.encoded_len(&[3, 0xa0, 7, 0]), None);
+    }
+
+    #[test]
+    fn rejects_bad_definitions() {
+        for (definition, error) in [
+            ("0105", "line 1: missing mnemonic"),
+            ("# header\n010 Jump", "line 2: invalid opcode '010'"),
+            ("01 Jump i16", "line 1: unknown operand type 'i16'"),
+            ("01 Jump\n02 Jump", "line 2: duplicate mnemonic 'Jump'"),
+            ("01 Jump\n01 Call", "line 2: duplicate opcode for 'Call'"),
+        ] {
+            let e = InstructionSet::parse("test", definition).unwrap_err();
+            assert!(e.starts_with(error), "{definition:?}: {e}");
+        }
+    }
+}
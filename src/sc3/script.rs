@@ -0,0 +1,227 @@
+// SC3 scripts (`.SCX`) start with a small header pointing at the tables that follow the code:
+//
+//   "SC3\0"
+//   u32 string table offset
+//   u32 return address table offset
+//   u32 label offsets, up to wherever the first label points
+//   bytecode
+//   u32 string offsets, up to the return address table
+//   u32 return addresses, up to wherever the first string starts
+//   string data
+//
+// Jumps and string references in the bytecode go through the label and string tables by index,
+// so the code itself holds no offsets.
+
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone)]
+pub struct Sc3Script {
+    /// Label offsets relative to the start of `code`.
+    pub labels: Vec<usize>,
+    pub code: Vec<u8>,
+    /// The raw encoded strings, each including its terminator.
+    pub strings: Vec<Vec<u8>>,
+    pub return_addresses: Vec<ReturnAddress>,
+}
+
+/// An entry in the return address table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReturnAddress {
+    /// A position in the code, relative to its start.
+    Code(usize),
+    /// Anything that doesn't point into the code, kept as-is.
+    Raw(u32),
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    let bytes = data
+        .get(pos..pos + 4)
+        .unwrap_or_else(|| panic!("SC3 script truncated at {pos:#x}"));
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
+
+fn read_offset(data: &[u8], pos: usize) -> usize {
+    read_u32(data, pos) as usize
+}
+
+impl Sc3Script {
+    pub const SC3_SIG: &'static [u8] = b"SC3\0";
+    const LABEL_TABLE_OFFSET: usize = 0xc;
+
+    #[must_use]
+    pub fn parse(data: &[u8]) -> Self {
+        assert!(data.starts_with(Self::SC3_SIG), "invalid SC3 signature");
+        let string_table = read_offset(data, 4);
+        let return_table = read_offset(data, 8);
+        assert!(
+            Self::LABEL_TABLE_OFFSET <= string_table
+                && string_table <= return_table
+                && return_table <= data.len(),
+            "SC3 header points outside the script"
+        );
+
+        // the label table has no count, it just runs until the code starts
+        let mut labels = Vec::new();
+        let mut code_start = string_table;
+        let mut pos = Self::LABEL_TABLE_OFFSET;
+        while pos < code_start {
+            let label = read_offset(data, pos);
+            assert!(
+                label <= string_table,
+                "label {} points outside the code",
+                labels.len()
+            );
+            code_start = code_start.min(label);
+            labels.push(label);
+            pos += 4;
+        }
+        let code_start = code_start.max(pos);
+        let code = data[code_start..string_table].to_vec();
+        let labels = labels
+            .into_iter()
+            .enumerate()
+            .map(|(i, label)| {
+                label
+                    .checked_sub(code_start)
+                    .unwrap_or_else(|| panic!("label {i} points into the label table"))
+            })
+            .collect();
+
+        let string_offsets = (string_table..return_table)
+            .step_by(4)
+            .map(|pos| read_offset(data, pos))
+            .collect::<Vec<_>>();
+        let strings_start = string_offsets.iter().copied().min().unwrap_or(data.len());
+        assert!(
+            return_table <= strings_start
+                && string_offsets.iter().all(|&offset| offset <= data.len()),
+            "SC3 string table points outside the script"
+        );
+
+        // strings have no stored length, so each one runs until the next one starts
+        let boundaries = string_offsets
+            .iter()
+            .copied()
+            .chain([data.len()])
+            .collect::<BTreeSet<_>>();
+        let strings = string_offsets
+            .iter()
+            .map(|&start| {
+                let end = boundaries
+                    .range(start + 1..)
+                    .next()
+                    .copied()
+                    .unwrap_or(start);
+                data[start..end].to_vec()
+            })
+            .collect();
+
+        let return_addresses = (return_table..strings_start)
+            .step_by(4)
+            .map(|pos| {
+                let addr = read_u32(data, pos);
+                (addr as usize)
+                    .checked_sub(code_start)
+                    .filter(|&addr| addr <= code.len())
+                    .map_or(ReturnAddress::Raw(addr), ReturnAddress::Code)
+            })
+            .collect();
+
+        Self {
+            labels,
+            code,
+            strings,
+            return_addresses,
+        }
+    }
+    /// Lays the script back out as an `.SCX` file, with every table rebuilt.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let to_u32 = |offset: usize| u32::try_from(offset).expect("SC3 script too large");
+        let code_start = Self::LABEL_TABLE_OFFSET + 4 * self.labels.len();
+        let string_table = code_start + self.code.len();
+        let return_table = string_table + 4 * self.strings.len();
+        let strings_start = return_table + 4 * self.return_addresses.len();
+
+        let mut out =
+            Vec::with_capacity(strings_start + self.strings.iter().map(Vec::len).sum::<usize>());
+        out.extend(Self::SC3_SIG);
+        out.extend(to_u32(string_table).to_le_bytes());
+        out.extend(to_u32(return_table).to_le_bytes());
+        for &label in &self.labels {
+            out.extend(to_u32(code_start + label).to_le_bytes());
+        }
+        out.extend(&self.code);
+
+        let mut string_offset = strings_start;
+        for string in &self.strings {
+            out.extend(to_u32(string_offset).to_le_bytes());
+            string_offset += string.len();
+        }
+        for &addr in &self.return_addresses {
+            let addr = match addr {
+                ReturnAddress::Code(addr) => to_u32(code_start + addr),
+                ReturnAddress::Raw(addr) => addr,
+            };
+            out.extend(addr.to_le_bytes());
+        }
+        for string in &self.strings {
+            out.extend(string);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub const TEST_INSTRUCTIONS: &str = "\
+        00 End\n\
+        0105 Jump label\n\
+        0210 Msg u16 string\n";
+
+    /// A script with every table filled in: two labels, two strings, a return address into the
+    /// code and one that isn't, and trailing bytes no instruction decodes.
+    #[rustfmt::skip]
+    pub fn test_scx() -> Vec<u8> {
+        [
+            &b"SC3\0"[..],
+            &[0x27, 0, 0, 0], // string table
+            &[0x2f, 0, 0, 0], // return address table
+            &[0x14, 0, 0, 0], // label_0
+            &[0x1e, 0, 0, 0], // label_1
+            &[0x02, 0x10, 0x07, 0x00, 0x00, 0x00], // Msg 7 string_0
+            &[0x01, 0x05, 0x01, 0x00], // Jump label_1
+            &[0x02, 0x10, 0x08, 0x00, 0x01, 0x00], // Msg 8 string_1
+            &[0x00], // End
+            &[0xff, 0xee],
+            &[0x37, 0, 0, 0], // string_0
+            &[0x3a, 0, 0, 0], // string_1
+            &[0x24, 0, 0, 0], // return_0, at End
+            &[0xef, 0xbe, 0xad, 0xde], // return_1
+            &[0x00, 0x01, 0xff],
+            &[0x00, 0x02, 0xff],
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn parses_every_table() {
+        let script = Sc3Script::parse(&test_scx());
+        assert_eq!(script.labels, [0, 10]);
+        assert_eq!(script.code.len(), 19);
+        assert_eq!(script.strings, [[0x00, 0x01, 0xff], [0x00, 0x02, 0xff]]);
+        assert_eq!(
+            script.return_addresses,
+            [ReturnAddress::Code(16), ReturnAddress::Raw(0xdead_beef)]
+        );
+    }
+
+    #[test]
+    fn lays_a_parsed_script_back_out_unchanged() {
+        let scx = test_scx();
+        assert_eq!(Sc3Script::parse(&scx).to_bytes(), scx);
+    }
+}
@@ -0,0 +1,306 @@
+// Translation files. Every string of every script is a line with an ID of the form
+// `SCRIPT:INDEX`, its source text as markup and a translation, which is left empty on export.
+// PO files keep the ID in `msgctxt` so identical source lines stay separate entries.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringFormat {
+    Po,
+    Csv,
+    Json,
+}
+
+impl StringFormat {
+    pub const ALL: [Self; 3] = [Self::Po, Self::Csv, Self::Json];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Po => "po",
+            Self::Csv => "csv",
+            Self::Json => "json",
+        }
+    }
+
+    /// The format a file's extension says it's in, if it's one we know.
+    #[must_use]
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        Self::ALL.into_iter().find(|format| format.name() == ext)
+    }
+}
+
+impl fmt::Display for StringFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for StringFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| format!("unknown string format '{s}' (po, csv, json)"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StringLine {
+    pub id: String,
+    pub source: String,
+    #[serde(default)]
+    pub translation: String,
+}
+
+fn po_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+}
+
+fn po_unescape(s: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(c @ ('\\' | '"')) => out.push(c),
+            other => return Err(format!("invalid escape '\\{}'", other.unwrap_or(' '))),
+        }
+    }
+    Ok(out)
+}
+
+fn write_po<W: Write>(writer: &mut W, lines: &[StringLine]) -> std::io::Result<()> {
+    writeln!(writer, "msgid \"\"")?;
+    writeln!(
+        writer,
+        "msgstr \"Content-Type: text/plain; charset=UTF-8\\n\""
+    )?;
+    for line in lines {
+        writeln!(writer)?;
+        writeln!(writer, "msgctxt \"{}\"", po_escape(&line.id))?;
+        writeln!(writer, "msgid \"{}\"", po_escape(&line.source))?;
+        writeln!(writer, "msgstr \"{}\"", po_escape(&line.translation))?;
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct PoEntry {
+    msgctxt: Option<String>,
+    msgid: String,
+    msgstr: String,
+}
+
+fn read_po(text: &str) -> Result<Vec<StringLine>, String> {
+    let mut entries = vec![PoEntry::default()];
+    // which field continuation strings are appended to
+    let mut field = None;
+    for (line_no, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let at_line = |e: String| format!("line {line_no}: {e}");
+
+        // continuation lines are just a quoted string, which can have spaces of its own
+        let (keyword, quoted) = if line.starts_with('"') {
+            ("", line)
+        } else {
+            line.split_once(' ').unwrap_or((line, ""))
+        };
+        let value = quoted
+            .trim()
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .ok_or_else(|| at_line(format!("expected a quoted string, not '{quoted}'")))?;
+        let value = po_unescape(value).map_err(at_line)?;
+
+        match keyword {
+            "msgctxt" => {
+                entries.push(PoEntry {
+                    msgctxt: Some(value),
+                    ..PoEntry::default()
+                });
+                field = Some("msgctxt");
+            }
+            "msgid" => {
+                // an entry without a context starts at its msgid
+                if !entries.last().unwrap().msgid.is_empty()
+                    || field.is_some_and(|field| field != "msgctxt")
+                {
+                    entries.push(PoEntry::default());
+                }
+                entries.last_mut().unwrap().msgid = value;
+                field = Some("msgid");
+            }
+            "msgstr" => {
+                entries.last_mut().unwrap().msgstr = value;
+                field = Some("msgstr");
+            }
+            "" => {
+                let entry = entries.last_mut().unwrap();
+                match field {
+                    Some("msgctxt") => entry.msgctxt.as_mut().unwrap().push_str(&value),
+                    Some("msgid") => entry.msgid.push_str(&value),
+                    Some("msgstr") => entry.msgstr.push_str(&value),
+                    _ => return Err(at_line("string outside of an entry".to_string())),
+                }
+            }
+            _ => return Err(at_line(format!("unsupported keyword '{keyword}'"))),
+        }
+    }
+
+    // entries without a context are the header or weren't written by us
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| {
+            entry.msgctxt.map(|id| StringLine {
+                id,
+                source: entry.msgid,
+                translation: entry.msgstr,
+            })
+        })
+        .collect())
+}
+
+/// Writes `lines` as a translation file.
+pub fn write_lines<W: Write>(writer: &mut W, lines: &[StringLine], format: StringFormat) {
+    match format {
+        StringFormat::Po => write_po(writer, lines).expect("failed to write PO file"),
+        StringFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for line in lines {
+                csv_writer
+                    .serialize(line)
+                    .expect("failed to write CSV record");
+            }
+            csv_writer.flush().expect("failed to write CSV file");
+        }
+        StringFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, lines).expect("failed to write JSON");
+            writeln!(writer).unwrap();
+        }
+    }
+}
+
+/// Reads the lines of a translation file.
+pub fn read_lines(text: &str, format: StringFormat) -> Result<Vec<StringLine>, String> {
+    match format {
+        StringFormat::Po => read_po(text),
+        StringFormat::Csv => csv::Reader::from_reader(text.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string()),
+        StringFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines() -> Vec<StringLine> {
+        [
+            ("main:0", "Hello, \"world\"[br]", ""),
+            ("main:1", "a\\b\tc\nd", "translated, with a comma"),
+            ("sub/ev:12", "オカリン", "Okarin"),
+        ]
+        .into_iter()
+        .map(|(id, source, translation)| StringLine {
+            id: id.to_string(),
+            source: source.to_string(),
+            translation: translation.to_string(),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn every_format_reads_back_what_it_wrote() {
+        for format in StringFormat::ALL {
+            let mut file = Vec::new();
+            write_lines(&mut file, &lines(), format);
+            let text = String::from_utf8(file).unwrap();
+            assert_eq!(read_lines(&text, format).unwrap(), lines(), "{format}");
+        }
+    }
+
+    #[test]
+    fn reads_po_files_rewrapped_by_other_tools() {
+        let text = r#"
+# translator comment
+msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+
+#: main.scx
+msgctxt "main:0"
+msgid ""
+"Hello, "
+"\"world\"[br]"
+msgstr "Hi"
+
+msgid "no context"
+msgstr "skipped"
+"#;
+        assert_eq!(
+            read_lines(text, StringFormat::Po).unwrap(),
+            [StringLine {
+                id: "main:0".to_string(),
+                source: "Hello, \"world\"[br]".to_string(),
+                translation: "Hi".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_po_files() {
+        for (text, error) in [
+            (
+                "msgctxt main:0",
+                "line 1: expected a quoted string, not 'main:0'",
+            ),
+            ("msgid \"\\q\"", "line 1: invalid escape '\\q'"),
+            (
+                "msgid_plural \"x\"",
+                "line 1: unsupported keyword 'msgid_plural'",
+            ),
+            ("\"x\"", "line 1: string outside of an entry"),
+        ] {
+            assert_eq!(
+                read_lines(text, StringFormat::Po).unwrap_err(),
+                error,
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn formats_come_from_extensions() {
+        assert_eq!(
+            StringFormat::from_extension("out/strings.PO"),
+            Some(StringFormat::Po)
+        );
+        assert_eq!(
+            StringFormat::from_extension("strings.json"),
+            Some(StringFormat::Json)
+        );
+        assert_eq!(StringFormat::from_extension("strings.txt"), None);
+        assert_eq!("CSV".parse(), Ok(StringFormat::Csv));
+    }
+}
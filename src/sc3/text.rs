@@ -0,0 +1,367 @@
+// SC3 strings aren't text in any standard encoding. Each one is a run of two-byte glyph indices
+// into the game's font (marked by the high bit of the first byte), interleaved with one-byte
+// control codes for line breaks, name tags, ruby and so on, and terminated by 0xff.
+//
+// Strings are rendered as markup: glyphs as the character they draw in the game's charset, or as
+// their index in braces (`{01a3}`) if it has none, control codes as bracketed names (`[br]`,
+// `[color:8c00]`) with any argument bytes in hex, and anything that can't be made sense of as
+// `[raw:...]`. Literal `[`, `{` and `\` are escaped with a backslash. Encoding the markup with
+// the same charset gives back the original bytes.
+
+use crate::sc3::charset::Charset;
+use std::fmt;
+use std::fmt::Write;
+
+const TERMINATOR: u8 = 0xff;
+const GLYPH_FLAG: u8 = 0x80;
+// past this, the first byte of a glyph would read as the terminator
+pub(crate) const MAX_GLYPH: u16 = 0x7f00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arg {
+    None,
+    U16,
+    Expression,
+}
+
+struct ControlCode {
+    code: u8,
+    name: &'static str,
+    arg: Arg,
+}
+
+const fn control(code: u8, name: &'static str, arg: Arg) -> ControlCode {
+    ControlCode { code, name, arg }
+}
+
+static CONTROL_CODES: &[ControlCode] = &[
+    control(0x00, "br", Arg::None),
+    control(0x01, "name", Arg::None),
+    control(0x02, "line", Arg::None),
+    control(0x03, "present", Arg::None),
+    control(0x04, "color", Arg::Expression),
+    control(0x08, "present-reset", Arg::None),
+    control(0x09, "ruby-base", Arg::None),
+    control(0x0a, "ruby-text", Arg::None),
+    control(0x0b, "ruby-end", Arg::None),
+    control(0x0c, "font-size", Arg::U16),
+    control(0x0e, "parallel", Arg::None),
+    control(0x0f, "center", Arg::None),
+    control(0x11, "margin-top", Arg::U16),
+    control(0x12, "margin-left", Arg::U16),
+    control(0x13, "hardcoded", Arg::U16),
+    control(0x15, "expr", Arg::Expression),
+    control(0x18, "present-18", Arg::None),
+    control(0x19, "auto-forward", Arg::None),
+    control(0x1a, "auto-forward-1a", Arg::None),
+    control(0x1e, "ruby-center", Arg::None),
+    control(0x1f, "alt-br", Arg::None),
+];
+
+// control codes that end a line of text on screen
+const LINE_BREAKS: [u8; 2] = [0x00, 0x1f];
+
+/// One piece of a decoded string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Glyph(u16),
+    /// A control code and its argument bytes.
+    Control(u8, Vec<u8>),
+    /// Bytes that couldn't be tokenized, kept as-is.
+    Raw(Vec<u8>),
+}
+
+// Expressions are skipped rather than evaluated. Each term starts with a byte that says how long
+// it is: immediates have the high bit set and carry up to four more bytes, operators are followed
+// by a precedence byte, and a 0 byte ends the expression.
+pub(crate) fn expression_len(bytes: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    loop {
+        let term = *bytes.get(pos)?;
+        pos += 1;
+        if term == 0 {
+            return Some(pos);
+        }
+        pos += if term & 0x80 == 0 {
+            1
+        } else {
+            match term & 0x60 {
+                0x00 => 0,
+                0x20 => 1,
+                0x40 => 2,
+                _ => 4,
+            }
+        };
+    }
+}
+
+/// Splits an encoded string (with or without its terminator) into tokens. Anything after the
+/// terminator is ignored.
+#[must_use]
+pub fn tokenize(bytes: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let b = bytes[pos];
+        if b == TERMINATOR {
+            break;
+        } else if b & GLYPH_FLAG != 0 {
+            if let Some(&lo) = bytes.get(pos + 1) {
+                tokens.push(Token::Glyph(u16::from_be_bytes([b & !GLYPH_FLAG, lo])));
+                pos += 2;
+                continue;
+            }
+        } else if let Some(control) = CONTROL_CODES.iter().find(|c| c.code == b) {
+            let rest = &bytes[pos + 1..];
+            let arg_len = match control.arg {
+                Arg::None => Some(0),
+                Arg::U16 => (rest.len() >= 2).then_some(2),
+                Arg::Expression => expression_len(rest),
+            };
+            if let Some(arg_len) = arg_len {
+                tokens.push(Token::Control(b, rest[..arg_len].to_vec()));
+                pos += 1 + arg_len;
+                continue;
+            }
+        }
+
+        // no telling how long an unknown control code is, so give up on the rest
+        let rest = &bytes[pos..];
+        let rest = rest.strip_suffix(&[TERMINATOR]).unwrap_or(rest);
+        tokens.push(Token::Raw(rest.to_vec()));
+        break;
+    }
+
+    tokens
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        write!(s, "{b:02x}").unwrap();
+        s
+    })
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex '{s}'"));
+    }
+    Ok((0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+const ESCAPED: [char; 3] = ['[', '{', '\\'];
+
+/// Renders an encoded string as markup, with glyphs as the characters they draw in `charset`.
+#[must_use]
+pub fn decode(bytes: &[u8], charset: &Charset) -> String {
+    let mut out = String::new();
+    for token in tokenize(bytes) {
+        match token {
+            Token::Glyph(glyph) => match charset.char(glyph) {
+                Some(c) if ESCAPED.contains(&c) => write!(out, "\\{c}").unwrap(),
+                Some(c) => out.push(c),
+                None => write!(out, "{{{glyph:04x}}}").unwrap(),
+            },
+            Token::Control(code, arg) => {
+                let control = CONTROL_CODES.iter().find(|c| c.code == code).unwrap();
+                if arg.is_empty() {
+                    write!(out, "[{}]", control.name).unwrap();
+                } else {
+                    write!(out, "[{}:{}]", control.name, hex(&arg)).unwrap();
+                }
+            }
+            Token::Raw(bytes) => write!(out, "[raw:{}]", hex(&bytes)).unwrap(),
+        }
+    }
+    out
+}
+
+fn encode_tag(tag: &str, out: &mut Vec<u8>) -> Result<(), String> {
+    let (name, arg) = tag.split_once(':').unwrap_or((tag, ""));
+    let arg = parse_hex(arg)?;
+    if name == "raw" {
+        out.extend(arg);
+        return Ok(());
+    }
+
+    let control = CONTROL_CODES
+        .iter()
+        .find(|c| c.name == name)
+        .ok_or_else(|| format!("unknown tag '[{tag}]'"))?;
+    let is_valid = match control.arg {
+        Arg::None => arg.is_empty(),
+        Arg::U16 => arg.len() == 2,
+        Arg::Expression => expression_len(&arg) == Some(arg.len()),
+    };
+    if !is_valid {
+        return Err(format!("invalid argument for '[{tag}]'"));
+    }
+
+    out.push(control.code);
+    out.extend(arg);
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The markup itself is malformed.
+    Markup(String),
+    /// These characters aren't in the charset, in the order they first appear.
+    MissingChars(Vec<char>),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Markup(e) => write!(f, "{e}"),
+            Self::MissingChars(chars) => {
+                let chars = chars.iter().map(|c| format!("'{c}'")).collect::<Vec<_>>();
+                write!(f, "not in the charset: {}", chars.join(", "))
+            }
+        }
+    }
+}
+
+fn encode_glyph(glyph: u16, out: &mut Vec<u8>) {
+    out.extend((glyph | u16::from(GLYPH_FLAG) << 8).to_be_bytes());
+}
+
+/// Encodes markup back into a string, terminator included, looking characters up in `charset`.
+///
+/// Characters the charset doesn't have are all collected into one error, so a translation can be
+/// fixed in one go.
+pub fn encode(markup: &str, charset: &Charset) -> Result<Vec<u8>, EncodeError> {
+    let mut out = Vec::new();
+    let mut missing = Vec::new();
+    let mut rest = markup;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        let c = match c {
+            '[' | '{' => {
+                let close = if c == '[' { ']' } else { '}' };
+                let (tag, after) = rest
+                    .split_once(close)
+                    .ok_or_else(|| EncodeError::Markup(format!("unclosed '{c}'")))?;
+                rest = after;
+                if c == '[' {
+                    encode_tag(tag, &mut out).map_err(EncodeError::Markup)?;
+                } else {
+                    let glyph = u16::from_str_radix(tag, 16)
+                        .ok()
+                        .filter(|&glyph| glyph < MAX_GLYPH)
+                        .ok_or_else(|| EncodeError::Markup(format!("invalid glyph '{{{tag}}}'")))?;
+                    encode_glyph(glyph, &mut out);
+                }
+                continue;
+            }
+            '\\' => {
+                let escaped = rest.chars().next().filter(|c| ESCAPED.contains(c));
+                let escaped = escaped.ok_or_else(|| {
+                    EncodeError::Markup("'\\' has to be followed by '[', '{' or '\\'".to_string())
+                })?;
+                rest = &rest[escaped.len_utf8()..];
+                escaped
+            }
+            c => c,
+        };
+
+        match charset.glyph(c) {
+            Some(glyph) => encode_glyph(glyph, &mut out),
+            None if !missing.contains(&c) => missing.push(c),
+            None => {}
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(EncodeError::MissingChars(missing));
+    }
+    out.push(TERMINATOR);
+    Ok(out)
+}
+
+/// How many glyphs each line of an encoded string has, for checking it fits in a text box.
+#[must_use]
+pub fn line_lengths(bytes: &[u8]) -> Vec<usize> {
+    let mut lengths = vec![0];
+    for token in tokenize(bytes) {
+        match token {
+            Token::Glyph(_) => *lengths.last_mut().unwrap() += 1,
+            Token::Control(code, _) if LINE_BREAKS.contains(&code) => lengths.push(0),
+            _ => {}
+        }
+    }
+    lengths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn charset() -> Charset {
+        Charset::parse("test", "abc[\n{\\a").unwrap()
+    }
+
+    #[rustfmt::skip]
+    const STRING: &[u8] = &[
+        0x80, 0x00, 0x80, 0x01, // ab
+        0x00, // [br]
+        0x80, 0x03, // [
+        0x04, 0x8c, 0x00, // [color:8c00]
+        0x80, 0x06, // a again
+        0x0c, 0x20, 0x00, // [font-size:2000]
+        0x80, 0x05, 0x80, 0x04, // \{
+        0x81, 0x23, // a glyph the charset doesn't have
+        0x05, 0x01, 0x02, // an unknown control code
+        0xff,
+    ];
+    const MARKUP: &str = r"ab[br]\[[color:8c00]{0006}[font-size:2000]\\\{{0123}[raw:050102]";
+
+    #[test]
+    fn strings_round_trip() {
+        assert_eq!(decode(STRING, &charset()), MARKUP);
+        assert_eq!(encode(MARKUP, &charset()).unwrap(), STRING);
+    }
+
+    #[test]
+    fn strings_round_trip_without_a_charset() {
+        let markup = decode(STRING, &Charset::default());
+        assert!(markup.starts_with("{0000}{0001}[br]{0003}"));
+        assert_eq!(encode(&markup, &Charset::default()).unwrap(), STRING);
+    }
+
+    #[test]
+    fn missing_chars_are_collected() {
+        assert_eq!(
+            encode("abxcyx", &charset()),
+            Err(EncodeError::MissingChars(vec!['x', 'y']))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_markup() {
+        for (markup, error) in [
+            ("a[br", "unclosed '['"),
+            ("[blink]", "unknown tag '[blink]'"),
+            ("[br:00]", "invalid argument for '[br:00]'"),
+            ("[font-size:20]", "invalid argument for '[font-size:20]'"),
+            ("[color:8c]", "invalid argument for '[color:8c]'"),
+            ("{7f00}", "invalid glyph '{7f00}'"),
+            (r"\a", r"'\' has to be followed by '[', '{' or '\'"),
+        ] {
+            assert_eq!(
+                encode(markup, &charset()),
+                Err(EncodeError::Markup(error.to_string())),
+                "{markup}"
+            );
+        }
+    }
+
+    #[test]
+    fn counts_glyphs_per_line() {
+        assert_eq!(line_lengths(STRING), [2, 5]);
+        assert_eq!(line_lengths(&[0x80, 0x00, 0x1f, 0x1f, 0xff]), [1, 0, 0]);
+    }
+}
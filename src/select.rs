@@ -0,0 +1,476 @@
+use crate::archive::ArchiveEntry;
+use crate::sniff::FileType;
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use std::ops::RangeInclusive;
+
+#[derive(Debug)]
+enum PatternKind {
+    Id(u32),
+    IdRange(RangeInclusive<u32>),
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+#[derive(Debug)]
+struct Pattern {
+    source: String,
+    kind: PatternKind,
+}
+
+impl Pattern {
+    // Patterns are, in order of precedence:
+    //   - a numeric ID, e.g. `42`
+    //   - an inclusive range of IDs, e.g. `100-250`
+    //   - a regex, either prefixed with `re:` or wrapped in slashes, e.g. `/^SG0[1-3]_/`
+    //   - a glob, e.g. `KUN_A*.png`
+    #[allow(clippy::option_if_let_else)] // a chain of map_or_else()s would be unreadable
+    fn new(source: &str, case_insensitive: bool) -> Result<Self, String> {
+        let kind = if let Ok(id) = source.parse::<u32>() {
+            PatternKind::Id(id)
+        } else if let Some(range) = parse_id_range(source) {
+            PatternKind::IdRange(range)
+        } else if let Some(regex) = source
+            .strip_prefix("re:")
+            .or_else(|| source.strip_prefix('/')?.strip_suffix('/'))
+        {
+            PatternKind::Regex(
+                RegexBuilder::new(regex)
+                    .case_insensitive(case_insensitive)
+                    .build()
+                    .map_err(|e| format!("invalid entry regex '{source}': {e}"))?,
+            )
+        } else {
+            PatternKind::Glob(
+                GlobBuilder::new(source)
+                    .case_insensitive(case_insensitive)
+                    .build()
+                    .map_err(|e| format!("invalid entry glob '{source}': {e}"))?
+                    .compile_matcher(),
+            )
+        };
+
+        Ok(Self {
+            source: source.to_string(),
+            kind,
+        })
+    }
+
+    fn is_match(&self, id: u32, name: &str) -> bool {
+        match &self.kind {
+            PatternKind::Id(pat_id) => id == *pat_id,
+            PatternKind::IdRange(range) => range.contains(&id),
+            PatternKind::Glob(glob) => glob.is_match(name),
+            PatternKind::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+fn parse_id_range(s: &str) -> Option<RangeInclusive<u32>> {
+    let (start, end) = s.split_once('-')?;
+    Some(start.parse().ok()?..=end.parse().ok()?)
+}
+
+/// Checks that `s` is a valid entry pattern (see [`EntrySelectorBuilder::include()`]), so a bad
+/// regex or glob is reported while parsing arguments rather than once an archive is open.
+pub fn parse_pattern(s: &str) -> Result<String, String> {
+    Pattern::new(s, false)?;
+    Ok(s.to_string())
+}
+
+pub(crate) fn parse_offset(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    s.strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .map_or_else(|| s.parse(), |hex| u64::from_str_radix(hex, 16))
+        .map_err(|e| format!("invalid offset '{s}': {e}"))
+}
+
+/// Parses an inclusive range of archive offsets like `0x1000-0x8000`. Either end may be left
+/// out to leave that side unbounded, e.g. `0x1000-`.
+pub fn parse_offset_range(s: &str) -> Result<RangeInclusive<u64>, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("invalid offset range '{s}', expected START-END"))?;
+    let start = if start.is_empty() {
+        0
+    } else {
+        parse_offset(start)?
+    };
+    let end = if end.is_empty() {
+        u64::MAX
+    } else {
+        parse_offset(end)?
+    };
+
+    Ok(start..=end)
+}
+
+/// Decides which entries of an archive an operation applies to.
+///
+/// An entry is selected when it matches any of the include patterns (or there are none), matches
+/// none of the exclude patterns, and satisfies every predicate. See [`EntrySelectorBuilder`].
+#[derive(Debug, Default)]
+pub struct EntrySelector {
+    includes: Vec<Pattern>,
+    excludes: Vec<Pattern>,
+    compressed: Option<bool>,
+    size_range: Option<RangeInclusive<u64>>,
+    offset_range: Option<RangeInclusive<u64>>,
+    types: Vec<FileType>,
+}
+
+/// The entries picked out by an [`EntrySelector`], plus any patterns that didn't match a single
+/// entry (which are usually typos).
+#[derive(Debug)]
+pub struct Selection<'a> {
+    pub entries: Vec<&'a dyn ArchiveEntry>,
+    pub unmatched_includes: Vec<String>,
+    pub unmatched_excludes: Vec<String>,
+}
+
+impl EntrySelector {
+    /// A selector that matches every entry.
+    #[must_use]
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn builder() -> EntrySelectorBuilder {
+        EntrySelectorBuilder::default()
+    }
+
+    /// Whether the selector needs to look at entry contents, i.e. filters on file type.
+    #[must_use]
+    pub const fn needs_contents(&self) -> bool {
+        !self.types.is_empty()
+    }
+
+    fn passes_predicates(&self, entry: &dyn ArchiveEntry) -> bool {
+        self.compressed
+            .is_none_or(|compressed| entry.is_compressed() == compressed)
+            && self
+                .size_range
+                .as_ref()
+                .is_none_or(|range| range.contains(&entry.len_deflated()))
+            && self
+                .offset_range
+                .as_ref()
+                .is_none_or(|range| range.contains(&entry.offset()))
+    }
+
+    /// Runs the selector over `entries`, calling `sniff` to detect an entry's file type only if
+    /// the selector filters on it.
+    pub fn select<'a, I, F>(&self, entries: I, mut sniff: F) -> Selection<'a>
+    where
+        I: IntoIterator<Item = &'a dyn ArchiveEntry>,
+        F: FnMut(&dyn ArchiveEntry) -> FileType,
+    {
+        let mut include_hits = vec![false; self.includes.len()];
+        let mut exclude_hits = vec![false; self.excludes.len()];
+
+        let mut selected = Vec::new();
+        for entry in entries {
+            let mut included = self.includes.is_empty();
+            for (pattern, hit) in self.includes.iter().zip(&mut include_hits) {
+                if pattern.is_match(entry.id(), entry.name()) {
+                    *hit = true;
+                    included = true;
+                }
+            }
+
+            let mut excluded = false;
+            for (pattern, hit) in self.excludes.iter().zip(&mut exclude_hits) {
+                if pattern.is_match(entry.id(), entry.name()) {
+                    *hit = true;
+                    excluded = true;
+                }
+            }
+
+            if included
+                && !excluded
+                && self.passes_predicates(entry)
+                && (!self.needs_contents() || self.types.contains(&sniff(entry)))
+            {
+                selected.push(entry);
+            }
+        }
+
+        let unmatched = |patterns: &[Pattern], hits: Vec<bool>| {
+            patterns
+                .iter()
+                .zip(hits)
+                .filter(|(_, hit)| !hit)
+                .map(|(pattern, _)| pattern.source.clone())
+                .collect()
+        };
+
+        Selection {
+            entries: selected,
+            unmatched_includes: unmatched(&self.includes, include_hits),
+            unmatched_excludes: unmatched(&self.excludes, exclude_hits),
+        }
+    }
+}
+
+/// Builds up an [`EntrySelector`]. Name patterns are compiled by `build()`, so
+/// `case_insensitive()` applies to every pattern regardless of the order things are added.
+#[derive(Debug, Default)]
+pub struct EntrySelectorBuilder {
+    includes: Vec<String>,
+    excludes: Vec<String>,
+    case_insensitive: bool,
+    compressed: Option<bool>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    offset_range: Option<RangeInclusive<u64>>,
+    types: Vec<FileType>,
+}
+
+impl EntrySelectorBuilder {
+    /// Selects entries matching `pattern`: an ID, an ID range like `100-250`, a regex like
+    /// `re:^SG0[1-3]_` or `/^SG0[1-3]_/`, or a glob.
+    pub fn include<S: Into<String>>(&mut self, pattern: S) -> &mut Self {
+        self.includes.push(pattern.into());
+        self
+    }
+
+    pub fn includes<I: IntoIterator<Item = S>, S: Into<String>>(
+        &mut self,
+        patterns: I,
+    ) -> &mut Self {
+        self.includes.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Drops entries matching `pattern`, which uses the same syntax as `include()`.
+    pub fn exclude<S: Into<String>>(&mut self, pattern: S) -> &mut Self {
+        self.excludes.push(pattern.into());
+        self
+    }
+
+    pub fn excludes<I: IntoIterator<Item = S>, S: Into<String>>(
+        &mut self,
+        patterns: I,
+    ) -> &mut Self {
+        self.excludes.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    pub const fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+        self.case_insensitive = yes;
+        self
+    }
+
+    /// Only selects compressed (`Some(true)`) or stored (`Some(false)`) entries.
+    pub const fn compressed(&mut self, compressed: Option<bool>) -> &mut Self {
+        self.compressed = compressed;
+        self
+    }
+
+    /// Only selects entries whose uncompressed size is at least `min_size`.
+    pub const fn min_size(&mut self, min_size: Option<u64>) -> &mut Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Only selects entries whose uncompressed size is at most `max_size`.
+    pub const fn max_size(&mut self, max_size: Option<u64>) -> &mut Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Only selects entries whose data starts within `range`.
+    pub const fn offset_range(&mut self, range: Option<RangeInclusive<u64>>) -> &mut Self {
+        self.offset_range = range;
+        self
+    }
+
+    /// Only selects entries whose sniffed contents are one of `types`. Empty means any type.
+    pub fn types(&mut self, types: &[FileType]) -> &mut Self {
+        self.types = types.to_vec();
+        self
+    }
+
+    /// # Errors
+    ///
+    /// Fails if a pattern is an invalid regex or glob.
+    pub fn build(&self) -> Result<EntrySelector, String> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| Pattern::new(pattern, self.case_insensitive))
+                .collect::<Result<_, _>>()
+        };
+
+        let size_range = (self.min_size.is_some() || self.max_size.is_some())
+            .then(|| self.min_size.unwrap_or(0)..=self.max_size.unwrap_or(u64::MAX));
+
+        Ok(EntrySelector {
+            includes: compile(&self.includes)?,
+            excludes: compile(&self.excludes)?,
+            compressed: self.compressed,
+            size_range,
+            offset_range: self.offset_range.clone(),
+            types: self.types.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::Archive;
+    use crate::mpk::{build_mpk, Endian, MagesArchive};
+    use std::io::Cursor;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+
+    const ENTRIES: &[(&str, &[u8], u32)] = &[
+        ("SG01_a.txt", b"alpha", 0),
+        ("SG02_b.png", PNG, 0),
+        ("sg03_c.txt", b"charlie charlie charlie", 0),
+        // a PNG by contents only
+        ("KUN_A1.bin", PNG, 0),
+    ];
+
+    // the names of the selected entries, and the include and exclude patterns nothing matched
+    fn select(builder: &EntrySelectorBuilder) -> (Vec<String>, Vec<String>, Vec<String>) {
+        let mpk = build_mpk((2, 0), Endian::Little, ENTRIES, 0);
+        let archive = MagesArchive::build(&mut Cursor::new(&mpk));
+        let selection = archive.select(&mut Cursor::new(&mpk), &builder.build().unwrap());
+        let names = selection
+            .entries
+            .iter()
+            .map(|entry| entry.name().to_string())
+            .collect();
+        (
+            names,
+            selection.unmatched_includes,
+            selection.unmatched_excludes,
+        )
+    }
+
+    fn names(builder: &EntrySelectorBuilder) -> Vec<String> {
+        select(builder).0
+    }
+
+    #[test]
+    fn ids_and_id_ranges() {
+        assert_eq!(names(EntrySelector::builder().include("1")), ["SG02_b.png"]);
+        assert_eq!(
+            names(EntrySelector::builder().include("2-3")),
+            ["sg03_c.txt", "KUN_A1.bin"]
+        );
+        // a reversed range is just empty
+        assert!(names(EntrySelector::builder().include("3-2")).is_empty());
+    }
+
+    #[test]
+    fn globs_and_regexes() {
+        assert_eq!(
+            names(EntrySelector::builder().include("*.png")),
+            ["SG02_b.png"]
+        );
+        assert_eq!(
+            names(EntrySelector::builder().include("re:^SG0[12]_")),
+            ["SG01_a.txt", "SG02_b.png"]
+        );
+        assert_eq!(
+            names(EntrySelector::builder().include("/^sg0/")),
+            ["sg03_c.txt"]
+        );
+        // `.` is a wildcard in a regex but literal in a glob
+        assert_eq!(
+            names(EntrySelector::builder().include("re:_a.txt")),
+            ["SG01_a.txt"]
+        );
+        assert!(names(EntrySelector::builder().include("SG01_a?txt.")).is_empty());
+
+        let mut builder = EntrySelector::builder();
+        builder
+            .include("sg0*")
+            .include("/^kun/")
+            .case_insensitive(true);
+        assert_eq!(
+            names(&builder),
+            ["SG01_a.txt", "SG02_b.png", "sg03_c.txt", "KUN_A1.bin"]
+        );
+    }
+
+    #[test]
+    fn excludes_win_over_includes() {
+        let mut builder = EntrySelector::builder();
+        builder.includes(["*.txt", "1"]).exclude("sg03*");
+        assert_eq!(names(&builder), ["SG01_a.txt", "SG02_b.png"]);
+
+        // without includes, everything not excluded is selected
+        assert_eq!(
+            names(EntrySelector::builder().excludes(["*.txt", "3"])),
+            ["SG02_b.png"]
+        );
+    }
+
+    #[test]
+    fn patterns_matching_nothing_are_reported() {
+        let mut builder = EntrySelector::builder();
+        builder
+            .includes(["*.txt", "*.ogg"])
+            .excludes(["9", "*_c.*"]);
+        let (names, unmatched_includes, unmatched_excludes) = select(&builder);
+        assert_eq!(names, ["SG01_a.txt"]);
+        assert_eq!(unmatched_includes, ["*.ogg"]);
+        assert_eq!(unmatched_excludes, ["9"]);
+    }
+
+    #[test]
+    fn predicates_narrow_the_selection() {
+        let mut builder = EntrySelector::builder();
+        builder.min_size(Some(6)).max_size(Some(20));
+        assert_eq!(names(&builder), ["SG02_b.png", "KUN_A1.bin"]);
+
+        let mut builder = EntrySelector::builder();
+        builder.offset_range(Some(parse_offset_range("0x1000-").unwrap()));
+        assert_eq!(names(&builder), ["SG02_b.png", "sg03_c.txt", "KUN_A1.bin"]);
+
+        let mut builder = EntrySelector::builder();
+        builder.include("*_*").types(&[FileType::Png]);
+        assert!(builder.build().unwrap().needs_contents());
+        assert_eq!(names(&builder), ["SG02_b.png", "KUN_A1.bin"]);
+    }
+
+    #[test]
+    fn bad_patterns_are_errors() {
+        assert_eq!(
+            parse_pattern("re:^SG0[12]_"),
+            Ok("re:^SG0[12]_".to_string())
+        );
+        assert_eq!(parse_pattern("*.txt"), Ok("*.txt".to_string()));
+        let message = parse_pattern("re:SG0[12").unwrap_err();
+        assert!(
+            message.starts_with("invalid entry regex 're:SG0[12'"),
+            "{message}"
+        );
+        let message = parse_pattern("/(unclosed/").unwrap_err();
+        assert!(message.starts_with("invalid entry regex"), "{message}");
+        let message = parse_pattern("SG0[12_*").unwrap_err();
+        assert!(
+            message.starts_with("invalid entry glob 'SG0[12_*'"),
+            "{message}"
+        );
+
+        let mut builder = EntrySelector::builder();
+        builder.include("*.txt").exclude("{a,b");
+        assert!(builder.build().unwrap_err().contains("'{a,b'"));
+    }
+
+    #[test]
+    fn parses_offset_ranges() {
+        assert_eq!(parse_offset_range("0x800-4096"), Ok(0x800..=4096));
+        assert_eq!(parse_offset_range("-0X10"), Ok(0..=0x10));
+        assert_eq!(parse_offset_range("16-"), Ok(16..=u64::MAX));
+        assert!(parse_offset_range("0x800").is_err());
+        assert!(parse_offset_range("0xzz-").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::lay;
+use flate2::{Decompress, FlushDecompress};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The kind of data an entry holds, as determined from its contents rather than its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileType {
+    Png,
+    Ogg,
+    Wav,
+    Dds,
+    Zlib,
+    Sc3,
+    Lay,
+    Text,
+    Unknown,
+}
+
+impl FileType {
+    /// How many bytes from the start of a file `sniff()` wants to see.
+    pub const SNIFF_LEN: usize = 64;
+
+    pub const ALL: [Self; 9] = [
+        Self::Png,
+        Self::Ogg,
+        Self::Wav,
+        Self::Dds,
+        Self::Zlib,
+        Self::Sc3,
+        Self::Lay,
+        Self::Text,
+        Self::Unknown,
+    ];
+
+    /// Guesses the type of a file from its first (up to) `SNIFF_LEN` bytes and its total length.
+    #[must_use]
+    pub fn sniff(head: &[u8], len: u64) -> Self {
+        if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Self::Png
+        } else if head.starts_with(b"OggS") {
+            Self::Ogg
+        } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WAVE") {
+            Self::Wav
+        } else if head.starts_with(b"DDS ") {
+            Self::Dds
+        } else if head.starts_with(b"SC3\0") {
+            Self::Sc3
+        } else if is_lay_header(head, len) {
+            Self::Lay
+        } else if is_text(head) {
+            // checked before zlib, text can start with a valid zlib header like "x^"
+            Self::Text
+        } else if is_zlib(head) {
+            Self::Zlib
+        } else {
+            Self::Unknown
+        }
+    }
+
+    /// The type a file name's extension claims it is, if the extension is one we know.
+    #[must_use]
+    pub fn from_extension<P: AsRef<Path>>(name: P) -> Option<Self> {
+        let ext = name.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "png" => Some(Self::Png),
+            "ogg" => Some(Self::Ogg),
+            "wav" => Some(Self::Wav),
+            "dds" => Some(Self::Dds),
+            "scx" => Some(Self::Sc3),
+            "lay" => Some(Self::Lay),
+            "txt" => Some(Self::Text),
+            _ => None,
+        }
+    }
+
+    /// Whether the extension of `name` is consistent with this type. Unknown extensions are
+    /// never considered a mismatch.
+    #[must_use]
+    pub fn agrees_with<P: AsRef<Path>>(self, name: P) -> bool {
+        Self::from_extension(name).is_none_or(|ext_type| ext_type == self)
+    }
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Ogg => "ogg",
+            Self::Wav => "wav",
+            Self::Dds => "dds",
+            Self::Zlib => "zlib",
+            Self::Sc3 => "sc3",
+            Self::Lay => "lay",
+            Self::Text => "text",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for FileType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|file_type| file_type.name() == s)
+            .or(match s.as_str() {
+                "scx" => Some(Self::Sc3),
+                "txt" => Some(Self::Text),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                let names = Self::ALL.map(Self::name).join(", ");
+                format!("unknown file type '{s}' (expected one of: {names})")
+            })
+    }
+}
+
+// CMF byte says deflate with a window of at most 32K, and the first two bytes are a multiple
+// of 31 as RFC 1950 requires. Two bytes pass that by chance often enough that the rest of the
+// sample also has to inflate cleanly.
+fn is_zlib(head: &[u8]) -> bool {
+    let is_header = match head {
+        [cmf, flg, ..] => {
+            cmf & 0x0f == 8 && cmf >> 4 <= 7 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0
+        }
+        _ => false,
+    };
+
+    // the sample usually ends mid-stream, which is fine as long as nothing was invalid so far
+    let mut out = [0; 1024];
+    is_header
+        && Decompress::new(true)
+            .decompress(head, &mut out, FlushDecompress::None)
+            .is_ok()
+}
+
+// .lay files don't have a magic number, so the best we can do is check that the state and
+// chunk counts at the start account for exactly the length of the file
+fn is_lay_header(head: &[u8], len: u64) -> bool {
+    let (Some(states), Some(chunks)) = (read_u32_le(head, 0), read_u32_le(head, 4)) else {
+        return false;
+    };
+
+    states > 0
+        && chunks > 0
+        && lay::HEADER_LEN + u64::from(states) * lay::STATE_LEN + u64::from(chunks) * lay::CHUNK_LEN
+            == len
+}
+
+fn read_u32_le(buf: &[u8], pos: usize) -> Option<u32> {
+    buf.get(pos..pos + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn is_text(head: &[u8]) -> bool {
+    if head.is_empty() {
+        return false;
+    }
+
+    // the sample may cut a multibyte character in half, so only judge what decoded cleanly
+    let valid = match std::str::from_utf8(head) {
+        Ok(s) => s,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap(),
+        Err(_) => return false,
+    };
+
+    valid
+        .chars()
+        .all(|c| !c.is_control() || c.is_ascii_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn sniff(data: &[u8]) -> FileType {
+        FileType::sniff(
+            &data[..data.len().min(FileType::SNIFF_LEN)],
+            data.len() as u64,
+        )
+    }
+
+    #[test]
+    fn zlib_stream() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0xab; 4096]).unwrap();
+        assert_eq!(sniff(&encoder.finish().unwrap()), FileType::Zlib);
+    }
+
+    #[test]
+    fn text_with_zlib_header() {
+        // 'x' '^' is a valid zlib header
+        assert_eq!(
+            sniff(b"x^ looks like zlib, but it's text\n"),
+            FileType::Text
+        );
+    }
+
+    #[test]
+    fn binary_with_zlib_header() {
+        assert_eq!(
+            sniff(&[0x78, 0x5e, 0xff, 0xff, 0x00, 0x01, 0x02]),
+            FileType::Unknown
+        );
+    }
+
+    #[test]
+    fn magic_numbers() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0"), FileType::Png);
+        assert_eq!(sniff(b"OggS\0\x02"), FileType::Ogg);
+        assert_eq!(sniff(b"RIFF\0\0\0\0WAVEfmt "), FileType::Wav);
+        assert_eq!(sniff(b"SC3\0\x10\0\0\0"), FileType::Sc3);
+    }
+}
@@ -0,0 +1,275 @@
+use crate::archive::{Archive, ArchiveEntry};
+use bytesize::ByteSize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+const LARGEST_ENTRIES_SHOWN: usize = 10;
+
+#[derive(Debug, Default, Serialize)]
+pub struct SizeStats {
+    pub count: u64,
+    pub len_compressed: u64,
+    pub len_deflated: u64,
+}
+
+impl SizeStats {
+    fn add(&mut self, entry: &dyn ArchiveEntry) {
+        self.count += 1;
+        self.len_compressed += entry.len_compressed();
+        self.len_deflated += entry.len_deflated();
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    #[must_use]
+    pub fn compression_ratio(&self) -> f64 {
+        if self.len_deflated == 0 {
+            1.0
+        } else {
+            self.len_compressed as f64 / self.len_deflated as f64
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EntrySummary {
+    pub id: u32,
+    pub name: String,
+    pub len_compressed: u64,
+    pub len_deflated: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveStats {
+    pub format: String,
+    pub archive_len: u64,
+    /// The entry count written in the archive header, which doesn't always match reality.
+    pub reported_entry_count: u64,
+    /// Whether the game's profile says the archive pads its entry table with empty headers,
+    /// making the reported entry count expected to be off.
+    pub padded_entry_table: bool,
+    pub total: SizeStats,
+    pub compressed: SizeStats,
+    pub stored: SizeStats,
+    pub header_table_len: u64,
+    /// Bytes spent aligning entry data on the format's boundaries.
+    pub padding: u64,
+    /// Bytes not used by headers, entries, or required padding.
+    pub unused: u64,
+    pub by_extension: BTreeMap<String, SizeStats>,
+    pub largest_entries: Vec<EntrySummary>,
+}
+
+impl ArchiveStats {
+    #[must_use]
+    pub fn new(archive: &dyn Archive, archive_len: u64) -> Self {
+        let mut total = SizeStats::default();
+        let mut compressed = SizeStats::default();
+        let mut stored = SizeStats::default();
+        let mut by_extension = BTreeMap::<_, SizeStats>::new();
+
+        for entry in archive.entries() {
+            total.add(entry);
+            if entry.is_compressed() {
+                compressed.add(entry);
+            } else {
+                stored.add(entry);
+            }
+
+            let ext = Path::new(entry.name()).extension().map_or_else(
+                || String::from("(none)"),
+                |ext| ext.to_string_lossy().to_lowercase(),
+            );
+            by_extension.entry(ext).or_default().add(entry);
+        }
+
+        let mut largest = archive.entries();
+        largest.sort_by_key(|entry| std::cmp::Reverse(entry.len_deflated()));
+        let largest_entries = largest
+            .into_iter()
+            .take(LARGEST_ENTRIES_SHOWN)
+            .map(|entry| EntrySummary {
+                id: entry.id(),
+                name: entry.name().to_string(),
+                len_compressed: entry.len_compressed(),
+                len_deflated: entry.len_deflated(),
+            })
+            .collect();
+
+        Self {
+            format: archive.description(),
+            archive_len,
+            reported_entry_count: archive.reported_entry_count(),
+            padded_entry_table: false,
+            total,
+            compressed,
+            stored,
+            header_table_len: archive.header_table_len(),
+            padding: archive.padding(),
+            unused: archive.unused_space(archive_len),
+            by_extension,
+            largest_entries,
+        }
+    }
+
+    #[must_use]
+    pub const fn entry_count(&self) -> u64 {
+        self.total.count
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn percent_of(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        100.0 * part as f64 / whole as f64
+    }
+}
+
+impl fmt::Display for ArchiveStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.format)?;
+        write!(f, "Entries:        {}", self.entry_count())?;
+        if self.entry_count() == self.reported_entry_count {
+            writeln!(f)?;
+        } else if self.padded_entry_table {
+            writeln!(
+                f,
+                " (header reports {}, padded with empty headers as usual for this archive)",
+                self.reported_entry_count
+            )?;
+        } else {
+            writeln!(f, " (header reports {})", self.reported_entry_count)?;
+        }
+        writeln!(f, "Archive size:   {}", ByteSize::b(self.archive_len))?;
+        writeln!(
+            f,
+            "Entry data:     {} ({} deflated, {:.1}% ratio)",
+            ByteSize::b(self.total.len_compressed),
+            ByteSize::b(self.total.len_deflated),
+            100.0 * self.total.compression_ratio()
+        )?;
+        writeln!(f, "Header table:   {}", ByteSize::b(self.header_table_len))?;
+        writeln!(
+            f,
+            "Padding:        {} ({:.1}%)",
+            ByteSize::b(self.padding),
+            percent_of(self.padding, self.archive_len)
+        )?;
+        writeln!(
+            f,
+            "Unused:         {} ({:.1}%)",
+            ByteSize::b(self.unused),
+            percent_of(self.unused, self.archive_len)
+        )?;
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<12} {:<8} {:<12} {:<12} Ratio",
+            "Kind", "Count", "Size", "Deflated"
+        )?;
+        writeln!(f, "================================================")?;
+        let kinds = [("compressed", &self.compressed), ("stored", &self.stored)];
+        let extensions = self.by_extension.iter().map(|(ext, s)| (ext.as_str(), s));
+        for (i, (kind, stats)) in kinds.into_iter().chain(extensions).enumerate() {
+            if i == kinds.len() {
+                writeln!(f, "------------------------------------------------")?;
+            }
+            writeln!(
+                f,
+                "{:<12} {:<8} {:<12} {:<12} {:.1}%",
+                kind,
+                stats.count,
+                ByteSize::b(stats.len_compressed).to_string(),
+                ByteSize::b(stats.len_deflated).to_string(),
+                100.0 * stats.compression_ratio()
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<5} {:<20} {:<12} Stored", "ID", "Largest", "Size")?;
+        writeln!(f, "================================================")?;
+        for entry in &self.largest_entries {
+            writeln!(
+                f,
+                "{:<5} {:<20} {:<12} {}",
+                entry.id,
+                entry.name,
+                ByteSize::b(entry.len_deflated).to_string(),
+                ByteSize::b(entry.len_compressed)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpk::MagesArchive;
+    use std::io::Cursor;
+
+    // a v2 header table with an empty header after the entries, which are each a name, offset,
+    // stored and deflated size; the data itself is never read
+    fn build_header_table(entries: &[(&str, u64, u64, u64)]) -> Vec<u8> {
+        let entry_count = entries.len() + 1;
+        let mut mpk = vec![0; 0x40 + entry_count * 0x100];
+        mpk[..4].copy_from_slice(b"MPK\0");
+        mpk[6] = 2;
+        mpk[8] = u8::try_from(entry_count).unwrap();
+        for (id, &(name, offset, len_compressed, len_deflated)) in entries.iter().enumerate() {
+            let header = &mut mpk[0x40 + id * 0x100..][..0x100];
+            let indicator = u32::from(len_compressed != len_deflated);
+            header[..4].copy_from_slice(&indicator.to_le_bytes());
+            header[4..8].copy_from_slice(&u32::try_from(id).unwrap().to_le_bytes());
+            header[8..16].copy_from_slice(&offset.to_le_bytes());
+            header[16..24].copy_from_slice(&len_compressed.to_le_bytes());
+            header[24..32].copy_from_slice(&len_deflated.to_le_bytes());
+            header[32..32 + name.len()].copy_from_slice(name.as_bytes());
+        }
+        mpk
+    }
+
+    #[test]
+    fn counts_padding_unused_space_and_extensions() {
+        let mpk = build_header_table(&[
+            ("a.txt", 0x800, 0x100, 0x100),
+            ("b.png", 0x1000, 0x200, 0x800),
+            // aligning after b.png only needs to reach 0x1800
+            ("c.TXT", 0x2000, 0x10, 0x10),
+        ]);
+        let archive = MagesArchive::build(&mut Cursor::new(mpk));
+        let stats = ArchiveStats::new(&archive, 0x2100);
+
+        assert_eq!((stats.entry_count(), stats.reported_entry_count), (3, 4));
+        assert_eq!(stats.header_table_len, 0x440);
+        assert_eq!(
+            stats.padding,
+            (0x800 - 0x440) + (0x1000 - 0x900) + (0x1800 - 0x1200)
+        );
+        assert_eq!(stats.unused, (0x2000 - 0x1800) + (0x2100 - 0x2010));
+        assert_eq!(
+            (stats.compressed.count, stats.compressed.len_deflated),
+            (1, 0x800)
+        );
+        assert_eq!(
+            (stats.stored.count, stats.stored.len_compressed),
+            (2, 0x110)
+        );
+
+        let extensions = stats
+            .by_extension
+            .iter()
+            .map(|(ext, s)| (ext.as_str(), s.count, s.len_deflated))
+            .collect::<Vec<_>>();
+        assert_eq!(extensions, [("png", 1, 0x800), ("txt", 2, 0x110)]);
+        assert_eq!(stats.largest_entries[0].name, "b.png");
+        assert!(stats
+            .to_string()
+            .contains("Entries:        3 (header reports 4)"));
+    }
+}